@@ -2,16 +2,15 @@ CREATE OR REPLACE FUNCTION git_lookup_commit(commit_hash TEXT)
   RETURNS "commit"
 AS $BODY$
 DECLARE
-  blob BYTEA;
+  result "commit";
 BEGIN
 
 SELECT
-content INTO blob
-FROM "headers"
-WHERE "hash" = commit_hash
-AND "type" = 'commit';
+hash, tree, parent, author, committer, author_time, commit_time, message, pgp INTO result
+FROM "commits"
+WHERE "hash" = commit_hash;
 
-RETURN git_parse_commit(commit_hash, blob);
+RETURN result;
 END;
 $BODY$
 LANGUAGE 'plpgsql';
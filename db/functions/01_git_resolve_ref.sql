@@ -1,4 +1,6 @@
-CREATE OR REPLACE FUNCTION git_resolve_ref(ref_name TEXT)
+DROP FUNCTION IF EXISTS git_resolve_ref(TEXT);
+
+CREATE OR REPLACE FUNCTION git_resolve_ref(repo INTEGER, ref_name TEXT)
   RETURNS TEXT
 AS $BODY$
 DECLARE 
@@ -6,7 +8,7 @@ DECLARE
 BEGIN
   tmp := ref_name;
   WHILE tmp = 'HEAD' OR tmp LIKE '%/%' LOOP
-    SELECT "target" INTO tmp FROM "refs" WHERE "name" = tmp;
+    SELECT "target" INTO tmp FROM "refs" WHERE "repository" = repo AND "name" = tmp;
   END LOOP;
   RETURN tmp;
 END
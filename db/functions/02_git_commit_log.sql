@@ -1,26 +1,22 @@
-CREATE OR REPLACE FUNCTION git_commit_log(commitish TEXT)
+DROP FUNCTION IF EXISTS git_commit_log(TEXT);
+
+CREATE OR REPLACE FUNCTION git_commit_log(repo INTEGER, commitish TEXT)
   RETURNS SETOF "commits"
 AS $BODY$
 DECLARE 
   head TEXT;
 BEGIN
-  head := git_resolve_ref(commitish);
-  
+  head := git_resolve_ref(repo, commitish);
+
+  -- Commits are listed in topological order, newest first.
   RETURN QUERY
-  WITH RECURSIVE tree(hash, parent, depth) AS (
-	SELECT
-    "hash",
-    "parent",
-    1
-	FROM "commits" c
-	WHERE "hash" = head
-    UNION ALL
-    SELECT c.hash, c.parent, t.depth + 1
-    FROM "commits" c, tree t
-    WHERE t.parent @> array[c.hash]
+  WITH RECURSIVE ancestry(hash) AS (
+      SELECT head
+    UNION
+      SELECT p.parent FROM ancestry a JOIN commit_parents p ON (p.hash = a.hash)
   )
-  SELECT commits.* FROM tree INNER JOIN "commits" ON tree.hash = commits.hash
-  ORDER BY tree.depth ASC;
+  SELECT c.* FROM ancestry a INNER JOIN "commits" c ON (c.hash = a.hash)
+  ORDER BY c.generation DESC NULLS FIRST, c.commit_time DESC;
 END
 $BODY$
 LANGUAGE 'plpgsql';
@@ -1,55 +1,44 @@
+-- Tree entry names are bytes; those that are not UTF-8 are decoded with escapes, as the commits view does.
+CREATE OR REPLACE FUNCTION git_tree_entry_name(name BYTEA)
+    RETURNS TEXT
+AS $BODY$
+BEGIN
+    RETURN convert_from(name, 'UTF8');
+EXCEPTION WHEN character_not_in_repertoire THEN
+    RETURN encode(name, 'escape');
+END;
+$BODY$
+LANGUAGE 'plpgsql' IMMUTABLE;
+
 DROP FUNCTION IF EXISTS git_parse_tree(TEXT, BYTEA);
 
 CREATE OR REPLACE FUNCTION git_parse_tree(tree_hash TEXT, blob BYTEA)
     RETURNS SETOF tree_entry
 AS $BODY$
 DECLARE
-    buffer BYTEA;
-    b INT;
-    inside_sha INT;
-    tot_len INT;
-    id INT;
-    tmp BYTEA;
-    headers TEXT;
-    mode TEXT;
-    name TEXT;
+    rest BYTEA;
+    nul INT;
+    space INT;
 BEGIN
-    inside_sha := 0;
-    id := 0;
-    tmp := E'\\000';
-    tot_len := octet_length(blob);
-    buffer := E'';
+    rest := blob;
 
-    LOOP
-        IF id = tot_len THEN
-            EXIT;
-        END IF;
-
-        b = get_byte(blob, id);
-        buffer = buffer || set_byte(tmp, 0, b);
+    -- Each entry is "<mode> <name>\0" followed by the 20 byte hash of the child.
+    WHILE octet_length(rest) > 0 LOOP
+        nul := position('\000'::BYTEA IN rest);
+        space := position(' '::BYTEA IN rest);
 
-        IF b = 0 AND inside_sha = 0 THEN
-            inside_sha = id;
+        IF nul = 0 OR space = 0 OR space > nul OR octet_length(rest) < nul + 20 THEN
+            RAISE EXCEPTION 'Malformed tree %', tree_hash;
         END IF;
 
-        IF inside_sha > 0 AND (id - inside_sha) = 20 THEN
-            headers = substring(buffer for (position(E'\\000' in buffer)));
-            mode = substring(headers for (position(E' ' in headers) - 1));
-            name = substring(headers from (octet_length(mode) + 2));
-            name = substring(name for (octet_length(name) - 4));
-
-            RETURN NEXT (
-                tree_hash,
-                mode::TEXT,
-                name::TEXT,
-                encode(substring(buffer from octet_length(headers) - 2), 'hex')
-            )::tree_entry;
-
-            buffer = b'';
-            inside_sha = 0;
-        END IF;
+        RETURN NEXT (
+            tree_hash,
+            encode(substring(rest FROM 1 FOR space - 1), 'escape'),
+            git_tree_entry_name(substring(rest FROM space + 1 FOR nul - space - 1)),
+            encode(substring(rest FROM nul + 1 FOR 20), 'hex')
+        )::tree_entry;
 
-        id = id + 1;
+        rest := substring(rest FROM nul + 21);
     END LOOP;
 END;
 $BODY$
@@ -3,18 +3,10 @@ DROP FUNCTION IF EXISTS git_lookup_tree(TEXT);
 CREATE OR REPLACE FUNCTION git_lookup_tree(tree_hash TEXT)
     RETURNS SETOF "tree_entry"
 AS $BODY$
-DECLARE 
-  tmp_content BYTEA;
 BEGIN
-    SELECT "content" INTO tmp_content FROM "headers" WHERE "hash" = tree_hash AND type = 'tree';
-
-    IF tmp_content IS NOT NULL THEN
-        RETURN QUERY
-        SELECT * FROM git_parse_tree(tree_hash, tmp_content);
-    END IF;
-
     RETURN QUERY
-    SELECT hash as parent, hash as mode, hash as name, hash as leaf FROM commits WHERE 1=0; 
+    SELECT e.tree, e.mode, git_tree_entry_name(e.name), e.child FROM tree_entries e
+    WHERE e.tree = tree_hash ORDER BY e.position;
 END
 $BODY$
 LANGUAGE 'plpgsql';
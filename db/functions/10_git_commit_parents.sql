@@ -0,0 +1,13 @@
+CREATE OR REPLACE FUNCTION git_commit_parents(commit_hash TEXT)
+  RETURNS TEXT[]
+AS $BODY$
+  SELECT ARRAY(SELECT parent FROM commit_parents WHERE hash = commit_hash ORDER BY position);
+$BODY$
+LANGUAGE 'sql' STABLE;
+
+CREATE OR REPLACE FUNCTION git_commit_time(commit_hash TEXT)
+  RETURNS TIMESTAMP WITH TIME ZONE
+AS $BODY$
+  SELECT commit_time FROM commit_meta WHERE hash = commit_hash;
+$BODY$
+LANGUAGE 'sql' STABLE;
@@ -0,0 +1,46 @@
+DROP FUNCTION IF EXISTS git_shallow_walk(TEXT[], INTEGER, TIMESTAMP WITH TIME ZONE, TEXT[]);
+
+-- Walks the commit graph from the given heads for a shallow fetch.
+-- The walk stops after max_depth commits, at commits older than since,
+-- and at commits reachable from excluded. Each commit that is included
+-- is returned, with boundary set when any of its parents is left out.
+CREATE OR REPLACE FUNCTION git_shallow_walk(
+  heads TEXT[],
+  max_depth INTEGER,
+  since TIMESTAMP WITH TIME ZONE,
+  excluded TEXT[]
+)
+  RETURNS TABLE (
+    hash TEXT,
+    boundary BOOLEAN
+  )
+AS $BODY$
+BEGIN
+  RETURN QUERY
+  WITH RECURSIVE hidden(hash) AS (
+      SELECT unnest(COALESCE(excluded, ARRAY[]::TEXT[]))
+    UNION
+      SELECT unnest(git_commit_parents(h.hash)) FROM hidden h
+  ),
+  walk(hash, depth) AS (
+      SELECT unnest(heads), 1
+    UNION
+      SELECT p.parent, CASE WHEN max_depth IS NULL THEN 1 ELSE w.depth + 1 END
+      FROM walk w, unnest(git_commit_parents(w.hash)) AS p(parent)
+      WHERE (max_depth IS NULL OR w.depth < max_depth)
+        AND NOT EXISTS (SELECT 1 FROM hidden x WHERE x.hash = p.parent)
+        AND (since IS NULL OR git_commit_time(p.parent) >= since)
+  ),
+  included AS (
+    SELECT DISTINCT w.hash FROM walk w
+  )
+  SELECT
+    i.hash,
+    EXISTS (
+      SELECT 1 FROM unnest(git_commit_parents(i.hash)) AS p(parent)
+      WHERE p.parent NOT IN (SELECT n.hash FROM included n)
+    )
+  FROM included i;
+END
+$BODY$
+LANGUAGE 'plpgsql';
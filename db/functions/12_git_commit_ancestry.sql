@@ -0,0 +1,241 @@
+-- Checks whether ancestor is reachable from descendant, including when they are the same commit.
+-- Commits with a smaller generation than ancestor cannot lead to it, so the walk skips them.
+CREATE OR REPLACE FUNCTION git_is_ancestor(ancestor TEXT, descendant TEXT)
+  RETURNS BOOLEAN
+AS $BODY$
+DECLARE
+  target_generation INTEGER;
+BEGIN
+  SELECT generation INTO target_generation FROM commit_meta WHERE hash = ancestor;
+
+  RETURN EXISTS (
+    WITH RECURSIVE walk(hash) AS (
+        SELECT descendant
+      UNION
+        SELECT p.parent
+        FROM walk w
+        JOIN commit_parents p ON (p.hash = w.hash)
+        JOIN commit_meta m ON (m.hash = p.parent)
+        WHERE target_generation IS NULL OR m.generation IS NULL OR m.generation >= target_generation
+    )
+    SELECT 1 FROM walk WHERE hash = ancestor
+  );
+END
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Checks whether any of targets is descendant or one of its ancestors, peeling tags on descendant.
+-- Commits with a smaller generation than every target commit cannot lead to one, so the walk
+-- skips them. Commits without a generation are always followed.
+CREATE OR REPLACE FUNCTION git_reaches_any(descendant TEXT, targets TEXT[])
+  RETURNS BOOLEAN
+AS $BODY$
+DECLARE
+  lowest_generation INTEGER;
+BEGIN
+  SELECT coalesce(min(generation), 2147483647) INTO lowest_generation FROM commit_meta WHERE hash = ANY(targets);
+
+  RETURN EXISTS (
+    WITH RECURSIVE peeled(hash) AS (
+        SELECT descendant
+      UNION
+        SELECT g.object COLLATE "default" FROM peeled p JOIN tags g ON (g.hash = p.hash)
+    ),
+    walk(hash) AS (
+        SELECT hash FROM peeled
+      UNION
+        SELECT p.parent
+        FROM walk w
+        JOIN commit_parents p ON (p.hash = w.hash)
+        JOIN commit_meta m ON (m.hash = p.parent)
+        WHERE m.generation IS NULL OR m.generation >= lowest_generation
+    )
+    SELECT 1 FROM walk WHERE hash = ANY(targets)
+  );
+END
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Lists the best common ancestors of two commits: those that are not ancestors of another common ancestor.
+--
+-- As in git, commits are visited by decreasing generation, one generation at a time, each flagged
+-- with whether a (1) or b (2) reaches it, and whether a common ancestor found earlier does (4). A
+-- common ancestor is reached from both sides before any of its ancestors is visited, so it is a
+-- best one unless flagged 4 already. The walk stops once every commit left to visit is flagged 4.
+-- Commits with unknown generations, as in shallow repositories, are compared by their whole ancestry.
+CREATE OR REPLACE FUNCTION git_merge_base(a TEXT, b TEXT)
+  RETURNS SETOF TEXT
+AS $BODY$
+DECLARE
+  hashes TEXT[];
+  flags INTEGER[];
+  generations INTEGER[];
+  visiting INTEGER;
+BEGIN
+  IF a = b THEN
+    RETURN NEXT a;
+    RETURN;
+  END IF;
+
+  SELECT array_agg(m.hash), array_agg(CASE WHEN m.hash = a THEN 1 ELSE 2 END), array_agg(m.generation)
+  INTO hashes, flags, generations
+  FROM commit_meta m WHERE m.hash IN (a, b);
+
+  IF array_length(hashes, 1) = 2 AND array_position(generations, NULL) IS NULL THEN
+    LOOP
+      EXIT WHEN NOT EXISTS (SELECT 1 FROM unnest(flags) f WHERE f & 4 = 0);
+      SELECT max(g) INTO visiting FROM unnest(generations) g;
+
+      RETURN QUERY
+      SELECT v.hash FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+      WHERE v.generation = visiting AND v.flag = 3;
+
+      SELECT array_agg(n.hash), array_agg(n.flag), array_agg(n.generation)
+      INTO hashes, flags, generations
+      FROM (
+        SELECT w.hash, bit_or(w.flag) AS flag, max(w.generation) AS generation FROM (
+            SELECT v.hash, v.flag, v.generation FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+            WHERE v.generation < visiting
+          UNION ALL
+            SELECT p.parent, CASE WHEN v.flag = 3 THEN 7 ELSE v.flag END, m.generation
+            FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+            JOIN commit_parents p ON (p.hash = v.hash)
+            JOIN commit_meta m ON (m.hash = p.parent)
+            WHERE v.generation = visiting
+        ) w
+        GROUP BY w.hash
+      ) n;
+    END LOOP;
+    RETURN;
+  END IF;
+
+  RETURN QUERY
+  WITH RECURSIVE left_ancestry(hash) AS (
+      SELECT a
+    UNION
+      SELECT p.parent FROM left_ancestry l JOIN commit_parents p ON (p.hash = l.hash)
+  ),
+  right_ancestry(hash) AS (
+      SELECT b
+    UNION
+      SELECT p.parent FROM right_ancestry r JOIN commit_parents p ON (p.hash = r.hash)
+  ),
+  common AS (
+    SELECT l.hash FROM left_ancestry l INTERSECT SELECT r.hash FROM right_ancestry r
+  )
+  -- Common ancestors are closed under parents, so a redundant one is the parent of another.
+  SELECT c.hash FROM common c
+  WHERE NOT EXISTS (
+    SELECT 1 FROM commit_parents p JOIN common d ON (d.hash = p.hash) WHERE p.parent = c.hash
+  );
+END
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Lists the commits reachable from tips that are not reachable from hidden, newest first, peeling
+-- tags on both sides. The parents of the commits in shallow are not followed from tips, and those
+-- of the commits in hidden_shallow are not followed from hidden.
+--
+-- As in git, commits are visited by decreasing generation, each flagged with whether tips (1) or
+-- hidden (2) reach it. Before the newest commit flagged 1 only is visited, the hidden commits
+-- above it are walked down to its generation at once, so a commit visited with flag 1 only is
+-- not hidden. The walk stops once no commit left to visit is flagged 1 only, so hidden history
+-- older than the listed commits is never read. Without hidden commits, or with unknown
+-- generations, as in shallow repositories, whole ancestries are compared instead.
+CREATE OR REPLACE FUNCTION git_new_commits(tips TEXT[], hidden TEXT[], shallow TEXT[], hidden_shallow TEXT[])
+  RETURNS SETOF TEXT
+AS $BODY$
+DECLARE
+  hashes TEXT[];
+  flags INTEGER[];
+  generations INTEGER[];
+  visiting INTEGER;
+BEGIN
+  shallow := coalesce(shallow, '{}');
+  hidden_shallow := coalesce(hidden_shallow, '{}');
+
+  WITH RECURSIVE peeled(hash, flag) AS (
+      SELECT t, 1 FROM unnest(tips) AS t
+    UNION ALL
+      SELECT h, 2 FROM unnest(hidden) AS h
+    UNION
+      SELECT g.object COLLATE "default", p.flag FROM peeled p JOIN tags g ON (g.hash = p.hash)
+  )
+  SELECT array_agg(s.hash), array_agg(s.flag), array_agg(s.generation)
+  INTO hashes, flags, generations
+  FROM (
+    SELECT m.hash, bit_or(p.flag) AS flag, m.generation
+    FROM peeled p JOIN commit_meta m ON (m.hash = p.hash)
+    GROUP BY m.hash, m.generation
+  ) s;
+
+  IF NOT EXISTS (SELECT 1 FROM unnest(flags) f WHERE f & 2 = 2) OR array_position(generations, NULL) IS NOT NULL THEN
+    RETURN QUERY
+    WITH RECURSIVE hidden_ancestry(hash) AS (
+        SELECT v.hash FROM unnest(hashes, flags) AS v(hash, flag) WHERE v.flag & 2 = 2
+      UNION
+        SELECT p.parent FROM hidden_ancestry h JOIN commit_parents p ON (p.hash = h.hash)
+        WHERE NOT h.hash = ANY(hidden_shallow)
+    ),
+    tip_ancestry(hash) AS (
+        SELECT v.hash FROM unnest(hashes, flags) AS v(hash, flag) WHERE v.flag = 1
+      UNION
+        SELECT p.parent FROM tip_ancestry t JOIN commit_parents p ON (p.hash = t.hash)
+        WHERE NOT t.hash = ANY(shallow) AND NOT EXISTS (SELECT 1 FROM hidden_ancestry h WHERE h.hash = p.parent)
+    )
+    SELECT m.hash FROM tip_ancestry t JOIN commit_meta m ON (m.hash = t.hash)
+    ORDER BY m.generation DESC NULLS LAST, m.commit_time DESC;
+    RETURN;
+  END IF;
+
+  LOOP
+    SELECT max(v.generation) INTO visiting
+    FROM unnest(flags, generations) AS v(flag, generation) WHERE v.flag = 1;
+    EXIT WHEN visiting IS NULL;
+
+    WITH RECURSIVE above(hash, generation) AS (
+        SELECT v.hash, v.generation FROM unnest(hashes, generations) AS v(hash, generation)
+        WHERE v.generation > visiting
+      UNION
+        SELECT p.parent, m.generation
+        FROM above a
+        JOIN commit_parents p ON (p.hash = a.hash)
+        JOIN commit_meta m ON (m.hash = p.parent)
+        WHERE a.generation > visiting AND NOT a.hash = ANY(hidden_shallow)
+    )
+    SELECT array_agg(n.hash), array_agg(n.flag), array_agg(n.generation)
+    INTO hashes, flags, generations
+    FROM (
+      SELECT w.hash, bit_or(w.flag) AS flag, max(w.generation) AS generation FROM (
+          SELECT v.hash, v.flag, v.generation FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+          WHERE v.generation <= visiting
+        UNION ALL
+          SELECT a.hash, 2, a.generation FROM above a WHERE a.generation <= visiting
+      ) w
+      GROUP BY w.hash
+    ) n;
+
+    RETURN QUERY
+    SELECT v.hash FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+    WHERE v.generation = visiting AND v.flag = 1;
+
+    SELECT array_agg(n.hash), array_agg(n.flag), array_agg(n.generation)
+    INTO hashes, flags, generations
+    FROM (
+      SELECT w.hash, bit_or(w.flag) AS flag, max(w.generation) AS generation FROM (
+          SELECT v.hash, v.flag, v.generation FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+          WHERE v.generation < visiting
+        UNION ALL
+          SELECT p.parent, CASE WHEN v.flag & 2 = 2 THEN 2 ELSE 1 END, m.generation
+          FROM unnest(hashes, flags, generations) AS v(hash, flag, generation)
+          JOIN commit_parents p ON (p.hash = v.hash)
+          JOIN commit_meta m ON (m.hash = p.parent)
+          WHERE v.generation = visiting
+          AND NOT v.hash = ANY(CASE WHEN v.flag & 2 = 2 THEN hidden_shallow ELSE shallow END)
+      ) w
+      GROUP BY w.hash
+    ) n;
+  END LOOP;
+END
+$BODY$
+LANGUAGE 'plpgsql';
@@ -0,0 +1,28 @@
+CREATE OR REPLACE FUNCTION git_lookup_tag(tag_hash TEXT)
+  RETURNS "tag"
+AS $BODY$
+  SELECT hash, object, type, name, tagger, tag_time, message, pgp FROM tags WHERE hash = tag_hash;
+$BODY$
+LANGUAGE 'sql' STABLE;
+
+-- Follows annotated tags, including tags of tags, to the object they ultimately point at.
+-- Objects that are not tags peel to themselves.
+CREATE OR REPLACE FUNCTION git_peel(object_hash TEXT)
+  RETURNS TEXT
+AS $BODY$
+DECLARE
+  current TEXT;
+  target TEXT;
+BEGIN
+  current := object_hash;
+  LOOP
+    target := NULL;
+    SELECT object INTO target FROM tags WHERE hash = current;
+    IF target IS NULL THEN
+      RETURN current;
+    END IF;
+    current := target;
+  END LOOP;
+END
+$BODY$
+LANGUAGE 'plpgsql' STABLE;
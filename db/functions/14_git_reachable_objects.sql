@@ -0,0 +1,28 @@
+-- Lists every object reachable from the references and reflogs of all repositories sharing the
+-- database, following commit parents and trees, tree entries and tag targets. Symbolic references
+-- and submodule commits are followed no further, and objects that are missing are still listed.
+CREATE OR REPLACE FUNCTION git_reachable_objects()
+  RETURNS SETOF TEXT
+AS $BODY$
+  WITH RECURSIVE reachable(hash) AS (
+      SELECT target FROM refs WHERE target NOT LIKE '%/%'
+    UNION
+      SELECT old_target FROM reflog WHERE old_target IS NOT NULL AND old_target NOT LIKE '%/%'
+    UNION
+      SELECT new_target FROM reflog WHERE new_target IS NOT NULL AND new_target NOT LIKE '%/%'
+    UNION
+      SELECT c.child
+      FROM reachable r
+      CROSS JOIN LATERAL (
+          SELECT m.tree FROM commit_meta m WHERE m.hash = r.hash
+        UNION ALL
+          SELECT p.parent FROM commit_parents p WHERE p.hash = r.hash
+        UNION ALL
+          SELECT e.child FROM tree_entries e WHERE e.tree = r.hash AND e.mode != '160000'
+        UNION ALL
+          SELECT t.object COLLATE "default" FROM tags t WHERE t.hash = r.hash
+      ) AS c(child)
+  )
+  SELECT hash FROM reachable;
+$BODY$
+LANGUAGE 'sql' STABLE;
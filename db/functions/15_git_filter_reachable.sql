@@ -0,0 +1,61 @@
+-- Lists the given objects that are reachable from the references of a repository, which are the
+-- only objects its clients may fetch. Each object is first traced up to the trees and commits that
+-- contain it. Those commits are then looked for walking down from the references, skipping commits
+-- whose generation is too small to lead to any of them.
+CREATE OR REPLACE FUNCTION git_filter_reachable(repo INTEGER, wanted TEXT[])
+  RETURNS SETOF TEXT
+AS $BODY$
+  WITH RECURSIVE tips(hash) AS (
+      SELECT target FROM refs WHERE repository = repo AND target NOT LIKE '%/%'
+    UNION
+      SELECT t.object COLLATE "default" FROM tips p JOIN tags t ON (t.hash = p.hash)
+  ),
+  containers(hash) AS (
+      SELECT w.hash FROM unnest(wanted) AS w(hash) WHERE NOT EXISTS (SELECT 1 FROM tips t WHERE t.hash = w.hash)
+    UNION
+      SELECT c.container
+      FROM containers r
+      CROSS JOIN LATERAL (
+          SELECT e.tree FROM tree_entries e WHERE e.child = r.hash AND e.mode != '160000'
+        UNION ALL
+          SELECT m.hash FROM commit_meta m WHERE m.tree = r.hash
+      ) AS c(container)
+  ),
+  candidates AS (
+    SELECT m.hash, m.generation FROM commit_meta m JOIN containers c ON (c.hash = m.hash)
+  ),
+  lowest AS (
+    SELECT CASE WHEN bool_and(generation IS NOT NULL) THEN min(generation) END AS generation FROM candidates
+  ),
+  history(hash) AS (
+      SELECT m.hash FROM commit_meta m JOIN tips t ON (t.hash = m.hash)
+      WHERE EXISTS (SELECT 1 FROM candidates)
+    UNION
+      SELECT p.parent
+      FROM history h
+      JOIN commit_parents p ON (p.hash = h.hash)
+      JOIN commit_meta m ON (m.hash = p.parent)
+      CROSS JOIN lowest l
+      WHERE l.generation IS NULL OR m.generation IS NULL OR m.generation >= l.generation
+  ),
+  reached(hash) AS (
+      SELECT c.hash FROM candidates c JOIN history h ON (h.hash = c.hash)
+    UNION
+      SELECT t.hash FROM tips t JOIN containers c ON (c.hash = t.hash)
+    UNION
+      SELECT c.child
+      FROM reached r
+      CROSS JOIN LATERAL (
+          SELECT m.tree FROM commit_meta m WHERE m.hash = r.hash
+        UNION ALL
+          SELECT e.child FROM tree_entries e WHERE e.tree = r.hash AND e.mode != '160000'
+      ) AS c(child)
+      JOIN containers k ON (k.hash = c.child)
+  )
+  SELECT hash FROM tips WHERE hash = ANY(wanted)
+  UNION
+  SELECT hash FROM reached WHERE hash = ANY(wanted);
+$BODY$
+LANGUAGE 'sql' STABLE
+-- The recursive walks are estimated far larger than they are, which would JIT compile every call.
+SET jit = off;
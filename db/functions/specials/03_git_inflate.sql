@@ -0,0 +1,241 @@
+CREATE OR REPLACE FUNCTION git_inflate_bits(data BYTEA, bitpos BIGINT, count INTEGER)
+  RETURNS INTEGER
+IMMUTABLE
+AS $BODY$
+DECLARE
+  value INTEGER := 0;
+  bit BIGINT;
+BEGIN
+  FOR i IN 0..count - 1 LOOP
+    bit := bitpos + i;
+    value := value | (((get_byte(data, (bit >> 3)::INTEGER) >> (bit & 7)::INTEGER) & 1) << i);
+  END LOOP;
+  RETURN value;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Builds a canonical Huffman decoding table, indexed by (1 << code length) + code.
+CREATE OR REPLACE FUNCTION git_inflate_table(lengths INTEGER[], first INTEGER, count INTEGER)
+  RETURNS INTEGER[]
+IMMUTABLE
+AS $BODY$
+DECLARE
+  result INTEGER[] := array_fill(NULL::INTEGER, ARRAY[65536], ARRAY[0]);
+  counts INTEGER[] := array_fill(0, ARRAY[16], ARRAY[0]);
+  next_code INTEGER[] := array_fill(0, ARRAY[16], ARRAY[0]);
+  code INTEGER := 0;
+  len INTEGER;
+BEGIN
+  FOR symbol IN 0..count - 1 LOOP
+    len := lengths[first + symbol];
+    counts[len] := counts[len] + 1;
+  END LOOP;
+
+  counts[0] := 0;
+  FOR bits IN 1..15 LOOP
+    code := (code + counts[bits - 1]) << 1;
+    next_code[bits] := code;
+  END LOOP;
+
+  FOR symbol IN 0..count - 1 LOOP
+    len := lengths[first + symbol];
+    IF len > 0 THEN
+      result[(1 << len) + next_code[len]] := symbol;
+      next_code[len] := next_code[len] + 1;
+    END IF;
+  END LOOP;
+  RETURN result;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Decompresses a zlib stream. Used when PL/Python is unavailable, so it favors clarity over speed.
+CREATE OR REPLACE FUNCTION git_inflate(data BYTEA)
+  RETURNS BYTEA
+IMMUTABLE
+AS $BODY$
+DECLARE
+  length_base INTEGER[] := '[0:28]={3,4,5,6,7,8,9,10,11,13,15,17,19,23,27,31,35,43,51,59,67,83,99,115,131,163,195,227,258}';
+  length_extra INTEGER[] := '[0:28]={0,0,0,0,0,0,0,0,1,1,1,1,2,2,2,2,3,3,3,3,4,4,4,4,5,5,5,5,0}';
+  distance_base INTEGER[] := '[0:29]={1,2,3,4,5,7,9,13,17,25,33,49,65,97,129,193,257,385,513,769,1025,1537,2049,3073,4097,6145,8193,12289,16385,24577}';
+  distance_extra INTEGER[] := '[0:29]={0,0,0,0,1,1,2,2,3,3,4,4,5,5,6,6,7,7,8,8,9,9,10,10,11,11,12,12,13,13}';
+  code_length_order INTEGER[] := '[0:18]={16,17,18,0,8,7,9,6,10,5,11,4,12,3,13,2,14,1,15}';
+  output INTEGER[] := '{}';
+  outlen INTEGER := 0;
+  bitpos BIGINT := 16;
+  final INTEGER := 0;
+  btype INTEGER;
+  lengths INTEGER[];
+  literals INTEGER[];
+  distances INTEGER[];
+  code_lengths INTEGER[];
+  hlit INTEGER;
+  hdist INTEGER;
+  hclen INTEGER;
+  code INTEGER;
+  len INTEGER;
+  symbol INTEGER;
+  repeat INTEGER;
+  previous INTEGER;
+  distance INTEGER;
+  i INTEGER;
+BEGIN
+  IF get_byte(data, 0) & 15 <> 8 OR get_byte(data, 1) & 32 <> 0 THEN
+    RAISE EXCEPTION 'Unsupported zlib stream';
+  END IF;
+
+  WHILE final = 0 LOOP
+    final := git_inflate_bits(data, bitpos, 1);
+    btype := git_inflate_bits(data, bitpos + 1, 2);
+    bitpos := bitpos + 3;
+
+    IF btype = 0 THEN
+      bitpos := ((bitpos + 7) >> 3) << 3;
+      len := get_byte(data, (bitpos >> 3)::INTEGER) | (get_byte(data, (bitpos >> 3)::INTEGER + 1) << 8);
+      bitpos := bitpos + 32;
+      FOR j IN 0..len - 1 LOOP
+        output[outlen] := get_byte(data, (bitpos >> 3)::INTEGER + j);
+        outlen := outlen + 1;
+      END LOOP;
+      bitpos := bitpos + len * 8;
+      CONTINUE;
+    END IF;
+
+    IF btype = 1 THEN
+      lengths := array_fill(8, ARRAY[320], ARRAY[0]);
+      FOR j IN 144..255 LOOP lengths[j] := 9; END LOOP;
+      FOR j IN 256..279 LOOP lengths[j] := 7; END LOOP;
+      FOR j IN 288..319 LOOP lengths[j] := 5; END LOOP;
+      literals := git_inflate_table(lengths, 0, 288);
+      distances := git_inflate_table(lengths, 288, 30);
+    ELSIF btype = 2 THEN
+      hlit := git_inflate_bits(data, bitpos, 5) + 257;
+      hdist := git_inflate_bits(data, bitpos + 5, 5) + 1;
+      hclen := git_inflate_bits(data, bitpos + 10, 4) + 4;
+      bitpos := bitpos + 14;
+
+      lengths := array_fill(0, ARRAY[19], ARRAY[0]);
+      FOR j IN 0..hclen - 1 LOOP
+        lengths[code_length_order[j]] := git_inflate_bits(data, bitpos, 3);
+        bitpos := bitpos + 3;
+      END LOOP;
+      code_lengths := git_inflate_table(lengths, 0, 19);
+
+      lengths := array_fill(0, ARRAY[hlit + hdist], ARRAY[0]);
+      i := 0;
+      WHILE i < hlit + hdist LOOP
+        code := 0;
+        len := 0;
+        LOOP
+          code := (code << 1) | ((get_byte(data, (bitpos >> 3)::INTEGER) >> (bitpos & 7)::INTEGER) & 1);
+          bitpos := bitpos + 1;
+          len := len + 1;
+          symbol := code_lengths[(1 << len) + code];
+          EXIT WHEN symbol IS NOT NULL;
+          IF len >= 15 THEN
+            RAISE EXCEPTION 'Invalid deflate code length code';
+          END IF;
+        END LOOP;
+
+        IF symbol < 16 THEN
+          lengths[i] := symbol;
+          i := i + 1;
+        ELSE
+          IF symbol = 16 THEN
+            previous := lengths[i - 1];
+            repeat := 3 + git_inflate_bits(data, bitpos, 2);
+            bitpos := bitpos + 2;
+          ELSIF symbol = 17 THEN
+            previous := 0;
+            repeat := 3 + git_inflate_bits(data, bitpos, 3);
+            bitpos := bitpos + 3;
+          ELSE
+            previous := 0;
+            repeat := 11 + git_inflate_bits(data, bitpos, 7);
+            bitpos := bitpos + 7;
+          END IF;
+
+          FOR j IN 1..repeat LOOP
+            lengths[i] := previous;
+            i := i + 1;
+          END LOOP;
+        END IF;
+      END LOOP;
+
+      literals := git_inflate_table(lengths, 0, hlit);
+      distances := git_inflate_table(lengths, hlit, hdist);
+    ELSE
+      RAISE EXCEPTION 'Invalid deflate block type';
+    END IF;
+
+    LOOP
+      code := 0;
+      len := 0;
+      LOOP
+        code := (code << 1) | ((get_byte(data, (bitpos >> 3)::INTEGER) >> (bitpos & 7)::INTEGER) & 1);
+        bitpos := bitpos + 1;
+        len := len + 1;
+        symbol := literals[(1 << len) + code];
+        EXIT WHEN symbol IS NOT NULL;
+        IF len >= 15 THEN
+          RAISE EXCEPTION 'Invalid deflate literal/length code';
+        END IF;
+      END LOOP;
+
+      IF symbol < 256 THEN
+        output[outlen] := symbol;
+        outlen := outlen + 1;
+        CONTINUE;
+      END IF;
+
+      EXIT WHEN symbol = 256;
+
+      symbol := symbol - 257;
+      len := length_base[symbol] + git_inflate_bits(data, bitpos, length_extra[symbol]);
+      bitpos := bitpos + length_extra[symbol];
+
+      code := 0;
+      i := 0;
+      LOOP
+        code := (code << 1) | ((get_byte(data, (bitpos >> 3)::INTEGER) >> (bitpos & 7)::INTEGER) & 1);
+        bitpos := bitpos + 1;
+        i := i + 1;
+        symbol := distances[(1 << i) + code];
+        EXIT WHEN symbol IS NOT NULL;
+        IF i >= 15 THEN
+          RAISE EXCEPTION 'Invalid deflate distance code';
+        END IF;
+      END LOOP;
+
+      distance := distance_base[symbol] + git_inflate_bits(data, bitpos, distance_extra[symbol]);
+      bitpos := bitpos + distance_extra[symbol];
+      IF distance > outlen THEN
+        RAISE EXCEPTION 'Invalid deflate distance';
+      END IF;
+
+      FOR j IN 1..len LOOP
+        output[outlen] := output[outlen - distance];
+        outlen := outlen + 1;
+      END LOOP;
+    END LOOP;
+  END LOOP;
+
+  IF outlen = 0 THEN
+    RETURN ''::BYTEA;
+  END IF;
+  RETURN decode(array_to_string(ARRAY(SELECT lpad(to_hex(b), 2, '0') FROM unnest(output) AS b), ''), 'hex');
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- <PYTHON ONLY> --
+CREATE OR REPLACE FUNCTION git_inflate(data BYTEA)
+  RETURNS BYTEA
+IMMUTABLE
+AS $BODY$
+import zlib
+return zlib.decompress(data)
+$BODY$
+LANGUAGE 'plpython3u';
+-- </PYTHON ONLY> --
@@ -0,0 +1,91 @@
+CREATE OR REPLACE FUNCTION git_apply_delta(base BYTEA, delta BYTEA)
+  RETURNS BYTEA
+IMMUTABLE
+AS $BODY$
+DECLARE
+  pos INTEGER := 0;
+  op INTEGER;
+  copy_offset BIGINT;
+  copy_size INTEGER;
+  parts BYTEA[] := '{}';
+BEGIN
+  -- Skip the base and result sizes.
+  FOR k IN 1..2 LOOP
+    LOOP
+      op := get_byte(delta, pos);
+      pos := pos + 1;
+      EXIT WHEN op & 128 = 0;
+    END LOOP;
+  END LOOP;
+
+  WHILE pos < length(delta) LOOP
+    op := get_byte(delta, pos);
+    pos := pos + 1;
+
+    IF op & 128 <> 0 THEN
+      copy_offset := 0;
+      copy_size := 0;
+      FOR bit IN 0..3 LOOP
+        IF op & (1 << bit) <> 0 THEN
+          copy_offset := copy_offset | (get_byte(delta, pos)::BIGINT << (8 * bit));
+          pos := pos + 1;
+        END IF;
+      END LOOP;
+      FOR bit IN 0..2 LOOP
+        IF op & (16 << bit) <> 0 THEN
+          copy_size := copy_size | (get_byte(delta, pos) << (8 * bit));
+          pos := pos + 1;
+        END IF;
+      END LOOP;
+      IF copy_size = 0 THEN
+        copy_size := 65536;
+      END IF;
+      parts := array_append(parts, substring(base FROM (copy_offset + 1)::INTEGER FOR copy_size));
+    ELSIF op <> 0 THEN
+      parts := array_append(parts, substring(delta FROM pos + 1 FOR op));
+      pos := pos + op;
+    ELSE
+      RAISE EXCEPTION 'Invalid delta opcode';
+    END IF;
+  END LOOP;
+
+  RETURN coalesce((SELECT string_agg(part, ''::BYTEA ORDER BY n) FROM unnest(parts) WITH ORDINALITY AS t(part, n)), ''::BYTEA);
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- <PYTHON ONLY> --
+CREATE OR REPLACE FUNCTION git_apply_delta(base BYTEA, delta BYTEA)
+  RETURNS BYTEA
+IMMUTABLE
+AS $BODY$
+pos = 0
+for _ in range(2):
+    while delta[pos] & 0x80:
+        pos += 1
+    pos += 1
+
+out = bytearray()
+while pos < len(delta):
+    op = delta[pos]
+    pos += 1
+    if op & 0x80:
+        offset = size = 0
+        for bit in range(4):
+            if op & (1 << bit):
+                offset |= delta[pos] << (8 * bit)
+                pos += 1
+        for bit in range(3):
+            if op & (0x10 << bit):
+                size |= delta[pos] << (8 * bit)
+                pos += 1
+        out += base[offset:offset + (size or 0x10000)]
+    elif op:
+        out += delta[pos:pos + op]
+        pos += op
+    else:
+        plpy.error('Invalid delta opcode')
+return bytes(out)
+$BODY$
+LANGUAGE 'plpython3u';
+-- </PYTHON ONLY> --
@@ -0,0 +1,25 @@
+DROP FUNCTION IF EXISTS git_decode_object(TEXT, BYTEA) CASCADE;
+DROP FUNCTION IF EXISTS git_decode_object(TEXT, BYTEA, TEXT) CASCADE;
+
+-- Objects offloaded to a directory are not readable by the database, and decode to NULL.
+CREATE OR REPLACE FUNCTION git_decode_object(encoding TEXT, content BYTEA, base_hash TEXT, large_object OID)
+  RETURNS BYTEA
+STABLE
+AS $BODY$
+DECLARE
+  base_content BYTEA;
+BEGIN
+  IF encoding = 'zlib' THEN
+    RETURN git_inflate(content);
+  ELSIF encoding = 'delta' THEN
+    SELECT git_decode_object(o.encoding, o.content, o.base, o.large_object) INTO base_content FROM objects o WHERE o.hash = base_hash;
+    RETURN git_apply_delta(base_content, content);
+  ELSIF encoding = 'largeobject' THEN
+    RETURN lo_get(large_object);
+  ELSIF encoding = 'file' THEN
+    RETURN NULL;
+  END IF;
+  RETURN content;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS "objects.bases"
+  ON objects(base) WHERE base IS NOT NULL;
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS "tree_entries.children"
+  ON tree_entries(child);
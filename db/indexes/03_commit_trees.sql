@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS "commit_meta.trees"
+  ON commit_meta(tree);
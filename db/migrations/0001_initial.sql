@@ -0,0 +1,50 @@
+-- Baseline schema: the objects and references of a single repository.
+-- Databases created before schema versioning already match it, so every statement is idempotent.
+
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+CREATE TABLE IF NOT EXISTS schema_version (
+  version INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  applied TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT schema_version_pkey PRIMARY KEY (version)
+);
+
+COMMENT ON TABLE schema_version
+IS 'Applied Schema Migrations';
+
+CREATE TABLE IF NOT EXISTS objects (
+  hash TEXT NOT NULL,
+  content BYTEA NOT NULL,
+  CONSTRAINT hash PRIMARY KEY (hash),
+  CONSTRAINT "object-content-valid" CHECK (
+    encode(digest(content, 'sha1'), 'hex') = hash
+  )
+);
+
+COMMENT ON TABLE objects
+IS 'Git Objects';
+
+COMMENT ON CONSTRAINT "object-content-valid" ON objects
+IS 'Checks if the object hash matches the content hash.';
+
+COMMENT ON COLUMN objects.hash
+IS 'Object Hash';
+
+COMMENT on COLUMN objects.content
+IS 'Object Content';
+
+CREATE TABLE IF NOT EXISTS refs (
+  name TEXT NOT NULL,
+  target TEXT NOT NULL,
+  CONSTRAINT refs_pkey PRIMARY KEY (name)
+);
+
+COMMENT ON TABLE refs
+IS 'Git References';
+
+COMMENT ON COLUMN refs.name
+IS 'Reference Name (ex: refs/heads/master)';
+
+COMMENT ON COLUMN refs.target
+IS 'Reference Target (ex: d1dee1299555cbd126755df887db06503e0ab811 or refs/heads/master)';
@@ -0,0 +1,36 @@
+-- Scopes references by repository so several repositories can share one object store.
+-- References of an existing single-repository database are assigned to the repository
+-- named by the gitsql.repository setting. Databases created by git-sql init before schema
+-- versioning may already scope their references, and are left as they are.
+
+CREATE TABLE IF NOT EXISTS repositories (
+  id SERIAL NOT NULL,
+  name TEXT NOT NULL,
+  created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT repositories_pkey PRIMARY KEY (id),
+  CONSTRAINT repositories_name_key UNIQUE (name)
+);
+
+COMMENT ON TABLE repositories
+IS 'Git Repositories sharing the object store';
+
+COMMENT ON COLUMN repositories.name
+IS 'Repository Name (ex: mygitrepo)';
+
+INSERT INTO repositories (name)
+  SELECT current_setting('gitsql.repository', true)
+  WHERE EXISTS (SELECT 1 FROM refs) AND current_setting('gitsql.repository', true) IS NOT NULL
+  ON CONFLICT (name) DO NOTHING;
+
+ALTER TABLE refs ADD COLUMN IF NOT EXISTS repository INTEGER REFERENCES repositories(id) ON DELETE CASCADE;
+
+UPDATE refs SET repository = (
+  SELECT id FROM repositories WHERE name = current_setting('gitsql.repository', true)
+) WHERE repository IS NULL;
+
+ALTER TABLE refs ALTER COLUMN repository SET NOT NULL;
+ALTER TABLE refs DROP CONSTRAINT refs_pkey;
+ALTER TABLE refs ADD CONSTRAINT refs_pkey PRIMARY KEY (repository, name);
+
+COMMENT ON COLUMN refs.repository
+IS 'Repository the reference belongs to';
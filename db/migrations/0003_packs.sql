@@ -0,0 +1,49 @@
+-- Generated packs are kept per repository. Databases created before repositories were tracked
+-- may hold packs without one; packs are only a cache, so those are dropped and generated again.
+DO $$
+BEGIN
+  IF to_regclass('packs') IS NOT NULL AND NOT EXISTS (
+    SELECT 1 FROM information_schema.columns
+    WHERE table_schema = current_schema() AND table_name = 'packs' AND column_name = 'repository'
+  ) THEN
+    DROP TABLE IF EXISTS pack_objects;
+    DROP TABLE packs;
+  END IF;
+END
+$$;
+
+CREATE TABLE IF NOT EXISTS packs (
+  repository INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  refs_digest TEXT NOT NULL,
+  pack BYTEA NOT NULL,
+  index BYTEA NOT NULL,
+  created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  CONSTRAINT packs_pkey PRIMARY KEY (repository, name)
+);
+
+COMMENT ON TABLE packs
+IS 'Generated Packs for the Dumb HTTP Protocol';
+
+COMMENT ON COLUMN packs.name
+IS 'Pack Checksum (ex: pack-<name>.pack)';
+
+COMMENT ON COLUMN packs.refs_digest
+IS 'Digest of the references the pack was generated for';
+
+COMMENT ON COLUMN packs.pack
+IS 'Pack Content';
+
+COMMENT ON COLUMN packs.index
+IS 'Version 2 Pack Index Content';
+
+CREATE TABLE IF NOT EXISTS pack_objects (
+  repository INTEGER NOT NULL,
+  pack TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  CONSTRAINT pack_objects_pkey PRIMARY KEY (repository, pack, hash),
+  FOREIGN KEY (repository, pack) REFERENCES packs(repository, name) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE pack_objects
+IS 'Objects Contained in Generated Packs';
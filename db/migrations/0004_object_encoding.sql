@@ -0,0 +1,19 @@
+-- Objects may be stored zlib-compressed, as in a loose object file. The content hash can only
+-- be checked by the database for raw rows; compressed rows are verified by git-sql on insert.
+
+ALTER TABLE objects ADD COLUMN encoding TEXT NOT NULL DEFAULT 'raw';
+
+ALTER TABLE objects ADD CONSTRAINT "object-encoding-valid" CHECK (encoding IN ('raw', 'zlib'));
+
+ALTER TABLE objects DROP CONSTRAINT "object-content-valid";
+
+-- Existing rows were validated by the previous constraint, so they are not scanned again.
+ALTER TABLE objects ADD CONSTRAINT "object-content-valid" CHECK (
+  encoding <> 'raw' OR encode(digest(content, 'sha1'), 'hex') = hash
+) NOT VALID;
+
+COMMENT ON CONSTRAINT "object-content-valid" ON objects
+IS 'Checks if the object hash matches the content hash of uncompressed objects.';
+
+COMMENT ON COLUMN objects.encoding
+IS 'Storage Encoding (raw or zlib)';
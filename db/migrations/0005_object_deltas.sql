@@ -0,0 +1,23 @@
+-- Objects may be stored as a delta against the raw form of a base object, which is reconstructed
+-- on read. Chains are bounded by depth, the number of deltas between an object and a full base.
+
+ALTER TABLE objects ADD COLUMN base TEXT REFERENCES objects(hash);
+
+ALTER TABLE objects ADD COLUMN depth INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE objects DROP CONSTRAINT "object-encoding-valid";
+
+ALTER TABLE objects ADD CONSTRAINT "object-encoding-valid" CHECK (encoding IN ('raw', 'zlib', 'delta'));
+
+ALTER TABLE objects ADD CONSTRAINT "object-delta-valid" CHECK (
+  (encoding = 'delta') = (base IS NOT NULL) AND (base IS NULL) = (depth = 0)
+);
+
+COMMENT ON COLUMN objects.encoding
+IS 'Storage Encoding (raw, zlib or delta)';
+
+COMMENT ON COLUMN objects.base
+IS 'Base Object of a delta';
+
+COMMENT ON COLUMN objects.depth
+IS 'Number of deltas applied to reconstruct the object';
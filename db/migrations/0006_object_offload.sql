@@ -0,0 +1,36 @@
+-- Large objects may be offloaded to pg_largeobject or to a directory outside the database. Their
+-- rows keep only the "<type> <size>\0" header as content, so types and sizes are readable in place.
+
+ALTER TABLE objects ADD COLUMN large_object OID;
+
+ALTER TABLE objects DROP CONSTRAINT "object-encoding-valid";
+
+ALTER TABLE objects ADD CONSTRAINT "object-encoding-valid" CHECK (
+  encoding IN ('raw', 'zlib', 'delta', 'largeobject', 'file')
+);
+
+ALTER TABLE objects ADD CONSTRAINT "object-large-object-valid" CHECK (
+  (encoding = 'largeobject') = (large_object IS NOT NULL)
+);
+
+CREATE OR REPLACE FUNCTION git_unlink_large_object()
+  RETURNS TRIGGER
+AS $BODY$
+BEGIN
+  IF OLD.large_object IS NOT NULL THEN
+    PERFORM lo_unlink(OLD.large_object);
+  END IF;
+  RETURN OLD;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE TRIGGER "objects.unlink_large_object"
+  AFTER DELETE ON objects
+  FOR EACH ROW EXECUTE PROCEDURE git_unlink_large_object();
+
+COMMENT ON COLUMN objects.encoding
+IS 'Storage Encoding (raw, zlib, delta, largeobject or file)';
+
+COMMENT ON COLUMN objects.large_object
+IS 'Large Object holding the raw form of an offloaded object';
@@ -0,0 +1,105 @@
+-- The type and size of each object are stored in columns when it is inserted, instead of being
+-- parsed from its content on every query. git-sql fills them itself; the trigger covers plain SQL
+-- inserts. Existing rows are backfilled here, after which object_type_cache is no longer needed.
+
+DO $BODY$
+BEGIN
+  IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'objtype') THEN
+    CREATE TYPE objtype AS ENUM ('commit', 'tree', 'blob', 'tag');
+  END IF;
+END;
+$BODY$;
+
+ALTER TABLE objects ADD COLUMN type objtype;
+
+ALTER TABLE objects ADD COLUMN size BIGINT;
+
+-- Parses the "<type> <size>\0" header at the start of the raw form of an object.
+CREATE OR REPLACE FUNCTION git_parse_object_header(raw BYTEA, OUT type objtype, OUT size BIGINT)
+IMMUTABLE
+AS $BODY$
+DECLARE
+  header TEXT;
+BEGIN
+  header := convert_from(substring(raw FROM 1 FOR position('\000'::BYTEA IN raw) - 1), 'UTF8');
+  type := split_part(header, ' ', 1)::objtype;
+  size := split_part(header, ' ', 2)::BIGINT;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+-- Reads the header of a stored object. Offloaded objects keep their header as content; compressed
+-- objects and deltas are decoded, which the functions of the loaded schema can do whenever such rows exist.
+CREATE OR REPLACE FUNCTION git_read_object_header(_encoding TEXT, _content BYTEA, _base TEXT, OUT type objtype, OUT size BIGINT)
+AS $BODY$
+DECLARE
+  encodings TEXT[];
+  contents BYTEA[];
+  raw BYTEA;
+BEGIN
+  IF _encoding = 'zlib' THEN
+    raw := git_inflate(_content);
+  ELSIF _encoding = 'delta' THEN
+    -- The chain of bases, from the full object to the direct base.
+    WITH RECURSIVE chain(hash, encoding, content, base, position) AS (
+      SELECT o.hash, o.encoding, o.content, o.base, 0 FROM objects o WHERE o.hash = _base
+      UNION ALL
+      SELECT o.hash, o.encoding, o.content, o.base, c.position + 1
+      FROM objects o JOIN chain c ON (o.hash = c.base)
+    )
+    SELECT array_agg(c.encoding ORDER BY c.position DESC), array_agg(c.content ORDER BY c.position DESC)
+    INTO encodings, contents FROM chain c;
+
+    raw := contents[1];
+    IF encodings[1] = 'zlib' THEN
+      raw := git_inflate(raw);
+    END IF;
+
+    FOR i IN 2..coalesce(array_length(contents, 1), 1) LOOP
+      raw := git_apply_delta(raw, contents[i]);
+    END LOOP;
+
+    raw := git_apply_delta(raw, _content);
+  ELSE
+    raw := _content;
+  END IF;
+
+  SELECT h.type, h.size INTO type, size FROM git_parse_object_header(raw) h;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE OR REPLACE FUNCTION git_fill_object_metadata()
+  RETURNS TRIGGER
+AS $BODY$
+BEGIN
+  IF NEW.type IS NULL OR NEW.size IS NULL THEN
+    SELECT h.type, h.size INTO NEW.type, NEW.size
+    FROM git_read_object_header(NEW.encoding, NEW.content, NEW.base) h;
+  END IF;
+  RETURN NEW;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE TRIGGER "objects.fill_metadata"
+  BEFORE INSERT ON objects
+  FOR EACH ROW EXECUTE PROCEDURE git_fill_object_metadata();
+
+UPDATE objects SET (type, size) = (
+  SELECT h.type, h.size FROM git_read_object_header(objects.encoding, objects.content, objects.base) h
+);
+
+ALTER TABLE objects ALTER COLUMN type SET NOT NULL;
+
+ALTER TABLE objects ALTER COLUMN size SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS "objects.types" ON objects (type);
+
+DROP MATERIALIZED VIEW IF EXISTS object_type_cache CASCADE;
+
+COMMENT ON COLUMN objects.type
+IS 'Object Type';
+
+COMMENT ON COLUMN objects.size
+IS 'Object Size, excluding the header';
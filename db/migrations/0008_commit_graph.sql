@@ -0,0 +1,41 @@
+-- Commits are indexed into commit_meta and commit_parents when git-sql inserts them, so that the
+-- commit graph can be walked without parsing objects. Existing commits are indexed by git-sql
+-- migrate after this migration is applied.
+--
+-- The generation of a commit is one more than the largest generation of its parents, and 1 for a
+-- root commit. It is NULL until all of its ancestors are known, such as in shallow repositories.
+
+CREATE TABLE commit_meta (
+  hash TEXT NOT NULL,
+  tree TEXT NOT NULL,
+  author TEXT,
+  committer TEXT,
+  author_time TIMESTAMP WITH TIME ZONE,
+  commit_time TIMESTAMP WITH TIME ZONE,
+  message TEXT NOT NULL,
+  pgp TEXT,
+  generation INTEGER,
+  CONSTRAINT commit_meta_pkey PRIMARY KEY (hash),
+  CONSTRAINT commit_meta_object FOREIGN KEY (hash) REFERENCES objects (hash) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE commit_meta
+IS 'Parsed Commits';
+
+COMMENT ON COLUMN commit_meta.generation
+IS 'Length of the longest path to a root commit, or NULL if an ancestor is missing';
+
+CREATE TABLE commit_parents (
+  hash TEXT NOT NULL,
+  position INTEGER NOT NULL,
+  parent TEXT NOT NULL,
+  CONSTRAINT commit_parents_pkey PRIMARY KEY (hash, position),
+  CONSTRAINT commit_parents_commit FOREIGN KEY (hash) REFERENCES commit_meta (hash) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE commit_parents
+IS 'Commit Parents, in order';
+
+CREATE INDEX "commit_parents.parents" ON commit_parents (parent);
+
+CREATE INDEX "commit_meta.pending" ON commit_meta (hash) WHERE generation IS NULL;
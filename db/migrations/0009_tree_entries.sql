@@ -0,0 +1,18 @@
+-- The entries of each tree are indexed into tree_entries when git-sql inserts it, so that paths can
+-- be resolved and trees crawled without parsing objects. Existing trees are indexed by git-sql
+-- migrate after this migration is applied.
+
+CREATE TABLE tree_entries (
+  tree TEXT NOT NULL,
+  name TEXT NOT NULL,
+  mode TEXT NOT NULL,
+  child TEXT NOT NULL,
+  CONSTRAINT tree_entries_pkey PRIMARY KEY (tree, name),
+  CONSTRAINT tree_entries_tree FOREIGN KEY (tree) REFERENCES objects (hash) ON DELETE CASCADE
+);
+
+COMMENT ON TABLE tree_entries
+IS 'Parsed Tree Entries';
+
+COMMENT ON COLUMN tree_entries.mode
+IS 'Entry Mode, in octal as stored in the tree';
@@ -0,0 +1,69 @@
+-- Every change to a reference is recorded in reflog by a trigger, so the entry is committed or
+-- rolled back together with the change, including changes made with plain SQL. git-sql sets the
+-- gitsql.actor and gitsql.reflog_message settings for the transaction that changes the reference;
+-- without them the database user is recorded as the actor. Peeled ^{} rows are derived from
+-- their reference and are not logged.
+
+CREATE TABLE IF NOT EXISTS reflog (
+  id BIGSERIAL NOT NULL,
+  repository INTEGER NOT NULL REFERENCES repositories(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  old_target TEXT,
+  new_target TEXT,
+  time TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+  actor TEXT NOT NULL,
+  message TEXT,
+  CONSTRAINT reflog_pkey PRIMARY KEY (id)
+);
+
+CREATE INDEX IF NOT EXISTS "reflog.refs" ON reflog (repository, name, id);
+
+COMMENT ON TABLE reflog
+IS 'History of Reference Changes';
+
+COMMENT ON COLUMN reflog.old_target
+IS 'Target before the change, or NULL if the reference was created';
+
+COMMENT ON COLUMN reflog.new_target
+IS 'Target after the change, or NULL if the reference was deleted';
+
+COMMENT ON COLUMN reflog.actor
+IS 'Who changed the reference (ex: a user, or the address of a pushing client)';
+
+CREATE OR REPLACE FUNCTION git_log_ref_change()
+  RETURNS TRIGGER
+AS $BODY$
+DECLARE
+  actor TEXT;
+  message TEXT;
+BEGIN
+  actor := COALESCE(NULLIF(current_setting('gitsql.actor', true), ''), session_user);
+  message := NULLIF(current_setting('gitsql.reflog_message', true), '');
+
+  IF TG_OP = 'DELETE' THEN
+    IF OLD.name NOT LIKE '%^{}' THEN
+      INSERT INTO reflog (repository, name, old_target, new_target, actor, message)
+      VALUES (OLD.repository, OLD.name, OLD.target, NULL, actor, message);
+    END IF;
+    RETURN OLD;
+  END IF;
+
+  IF NEW.name LIKE '%^{}' THEN
+    RETURN NEW;
+  END IF;
+
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO reflog (repository, name, old_target, new_target, actor, message)
+    VALUES (NEW.repository, NEW.name, NULL, NEW.target, actor, message);
+  ELSIF NEW.target IS DISTINCT FROM OLD.target THEN
+    INSERT INTO reflog (repository, name, old_target, new_target, actor, message)
+    VALUES (NEW.repository, NEW.name, OLD.target, NEW.target, actor, message);
+  END IF;
+  RETURN NEW;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE TRIGGER "refs.log_change"
+  AFTER INSERT OR UPDATE OR DELETE ON refs
+  FOR EACH ROW EXECUTE PROCEDURE git_log_ref_change();
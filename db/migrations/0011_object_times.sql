@@ -0,0 +1,9 @@
+-- Objects record when they were last stored, so that git-sql gc only deletes unreachable objects
+-- older than its prune age. This protects objects written by a push whose references are not
+-- updated yet. Storing an object that already exists updates its time, as git freshens loose
+-- objects. Existing objects are dated to this migration.
+
+ALTER TABLE objects ADD COLUMN created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+
+COMMENT ON COLUMN objects.created
+IS 'Time the object was last stored';
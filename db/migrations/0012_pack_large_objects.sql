@@ -0,0 +1,24 @@
+-- Generated packs are written to large objects, as the pack of a whole repository may not fit in a
+-- BYTEA, and are streamed when downloaded. Packs are only a cache, so existing ones are dropped.
+-- A pack replaced by one generated for newer references is kept for a while, as clients listed
+-- it before the references changed and may still be downloading it.
+
+DELETE FROM packs;
+
+ALTER TABLE packs DROP COLUMN pack;
+ALTER TABLE packs ADD COLUMN large_object OID NOT NULL;
+ALTER TABLE packs ADD COLUMN size BIGINT NOT NULL;
+ALTER TABLE packs ADD COLUMN superseded TIMESTAMP WITH TIME ZONE;
+
+CREATE TRIGGER "packs.unlink_large_object"
+  AFTER DELETE ON packs
+  FOR EACH ROW EXECUTE PROCEDURE git_unlink_large_object();
+
+COMMENT ON COLUMN packs.large_object
+IS 'Large Object holding the pack content';
+
+COMMENT ON COLUMN packs.size
+IS 'Pack Size in bytes';
+
+COMMENT ON COLUMN packs.superseded
+IS 'Time the pack was replaced by one generated for newer references, or NULL';
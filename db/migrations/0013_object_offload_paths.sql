@@ -0,0 +1,13 @@
+-- Repositories sharing a database may configure different offload directories, while objects are
+-- shared by all of them, so rows of objects offloaded to a file record where the file is. Rows
+-- offloaded before this migration have no path, and are looked up in the offload directory of
+-- the repository reading them until `git-sql migrate` finds their files.
+
+ALTER TABLE objects ADD COLUMN path TEXT;
+
+ALTER TABLE objects ADD CONSTRAINT "object-path-valid" CHECK (
+  path IS NULL OR encoding = 'file'
+);
+
+COMMENT ON COLUMN objects.path
+IS 'File holding the raw form of an object offloaded to an offload directory';
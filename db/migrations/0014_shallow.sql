@@ -0,0 +1,10 @@
+-- Commits whose parents were never stored on purpose, as when a repository is imported from a
+-- shallow clone. Their parents are not reported missing by fsck.
+
+CREATE TABLE IF NOT EXISTS shallow (
+  hash TEXT NOT NULL,
+  CONSTRAINT shallow_pkey PRIMARY KEY (hash)
+);
+
+COMMENT ON TABLE shallow
+IS 'Commits Stored Without Their Parents';
@@ -0,0 +1,102 @@
+-- Commits inserted without git-sql, such as with plain SQL or COPY, are indexed into commit_meta
+-- and commit_parents by a trigger when their transaction commits. git-sql indexes the commits it
+-- inserts within the same transaction, so the trigger skips them. A commit gets its generation
+-- once all of its parents have theirs, even when it is inserted before them.
+
+CREATE OR REPLACE FUNCTION git_index_commit()
+  RETURNS TRIGGER
+AS $BODY$
+DECLARE
+  raw BYTEA;
+  info TEXT;
+  meta TEXT;
+  tree TEXT;
+  author TEXT;
+  committer TEXT;
+  resolved TEXT[];
+BEGIN
+  IF EXISTS (SELECT 1 FROM commit_meta WHERE hash = NEW.hash) THEN
+    RETURN NULL;
+  END IF;
+
+  -- Commits deleted since, or offloaded to a directory, are not readable here.
+  SELECT content INTO raw FROM headers WHERE hash = NEW.hash;
+  IF raw IS NULL THEN
+    RETURN NULL;
+  END IF;
+
+  BEGIN
+    info := convert_from(raw, 'UTF8');
+  EXCEPTION WHEN character_not_in_repertoire THEN
+    info := encode(raw, 'escape');
+  END;
+
+  meta := split_part(info, E'\n\n', 1);
+  tree := substring(meta FROM '(?n)^tree ([0-9a-f]{40})$');
+  IF tree IS NULL THEN
+    RETURN NULL;
+  END IF;
+
+  author := substring(meta FROM '(?n)^author (.*)$');
+  committer := substring(meta FROM '(?n)^committer (.*)$');
+
+  INSERT INTO commit_meta (hash, tree, author, committer, author_time, commit_time, message, pgp)
+  VALUES (
+    NEW.hash,
+    tree,
+    COALESCE(substring(author FROM '^(.*>)'), author),
+    COALESCE(substring(committer FROM '^(.*>)'), committer),
+    to_timestamp(substring(author FROM '> *(\d+)')::BIGINT),
+    to_timestamp(substring(committer FROM '> *(\d+)')::BIGINT),
+    CASE WHEN position(E'\n\n' IN info) = 0 THEN '' ELSE substring(info FROM position(E'\n\n' IN info) + 2) END,
+    replace(substring(meta FROM '(?n)^gpgsig (.*(?:\n .*)*)'), E'\n ', E'\n')
+  );
+
+  INSERT INTO commit_parents (hash, position, parent)
+  SELECT NEW.hash, p.position - 1, p.match[1]
+  FROM regexp_matches(meta, '^parent ([0-9a-f]{40})$', 'gn') WITH ORDINALITY AS p(match, position);
+
+  WITH updated AS (
+    UPDATE commit_meta m SET generation = g.generation
+    FROM (
+      SELECT COALESCE(max(c.generation), 0) + 1 AS generation
+      FROM commit_parents p LEFT JOIN commit_meta c ON (c.hash = p.parent)
+      WHERE p.hash = NEW.hash
+      HAVING count(*) = count(c.generation)
+    ) g
+    WHERE m.hash = NEW.hash
+    RETURNING m.hash
+  )
+  SELECT array_agg(hash) INTO resolved FROM updated;
+
+  -- Children indexed before this commit, as when a statement inserts them first, get their
+  -- generations once all of their parents have one.
+  WHILE resolved IS NOT NULL LOOP
+    WITH updated AS (
+      UPDATE commit_meta m SET generation = g.generation
+      FROM (
+        SELECT k.hash, max(c.generation) + 1 AS generation
+        FROM commit_parents r
+        JOIN commit_meta k ON (k.hash = r.hash AND k.generation IS NULL)
+        JOIN commit_parents p ON (p.hash = k.hash)
+        LEFT JOIN commit_meta c ON (c.hash = p.parent)
+        WHERE r.parent = ANY(resolved)
+        GROUP BY k.hash
+        HAVING count(*) = count(c.generation)
+      ) g
+      WHERE m.hash = g.hash
+      RETURNING m.hash
+    )
+    SELECT array_agg(hash) INTO resolved FROM updated;
+  END LOOP;
+
+  RETURN NULL;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE CONSTRAINT TRIGGER "objects.index_commit"
+  AFTER INSERT ON objects
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW WHEN (NEW.type = 'commit')
+  EXECUTE PROCEDURE git_index_commit();
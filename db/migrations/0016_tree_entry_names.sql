@@ -0,0 +1,71 @@
+-- Tree entry names are stored as the bytes found in the tree, since git does not require them to
+-- be UTF-8, with their position in it, which is git's tree order. The entries indexed before are
+-- indexed again by git-sql migrate. Trees inserted without git-sql, such as with plain SQL or COPY,
+-- are indexed by a trigger when their transaction commits.
+
+TRUNCATE tree_entries;
+
+ALTER TABLE tree_entries ALTER COLUMN name TYPE BYTEA USING convert_to(name, 'UTF8');
+ALTER TABLE tree_entries ADD COLUMN position INTEGER NOT NULL;
+
+COMMENT ON COLUMN tree_entries.name
+IS 'Entry Name, as the bytes stored in the tree';
+
+COMMENT ON COLUMN tree_entries.position
+IS 'Position of the Entry in the tree';
+
+CREATE OR REPLACE FUNCTION git_index_tree()
+  RETURNS TRIGGER
+AS $BODY$
+DECLARE
+  rest BYTEA;
+  nul INT;
+  space INT;
+  entry INT;
+BEGIN
+  IF EXISTS (SELECT 1 FROM tree_entries WHERE tree = NEW.hash) THEN
+    RETURN NULL;
+  END IF;
+
+  -- Trees deleted since, or offloaded to a directory, are not readable here.
+  SELECT content INTO rest FROM headers WHERE hash = NEW.hash;
+  IF rest IS NULL THEN
+    RETURN NULL;
+  END IF;
+
+  -- Each entry is "<mode> <name>\0" followed by the 20 byte hash of the child. Malformed trees
+  -- are left unindexed, for git-sql fsck to report.
+  entry := 0;
+  WHILE octet_length(rest) > 0 LOOP
+    nul := position('\000'::BYTEA IN rest);
+    space := position(' '::BYTEA IN rest);
+
+    IF nul = 0 OR space = 0 OR space > nul OR octet_length(rest) < nul + 20 THEN
+      DELETE FROM tree_entries WHERE tree = NEW.hash;
+      RETURN NULL;
+    END IF;
+
+    INSERT INTO tree_entries (tree, name, mode, child, position)
+    VALUES (
+      NEW.hash,
+      substring(rest FROM space + 1 FOR nul - space - 1),
+      encode(substring(rest FROM 1 FOR space - 1), 'escape'),
+      encode(substring(rest FROM nul + 1 FOR 20), 'hex'),
+      entry
+    )
+    ON CONFLICT DO NOTHING;
+
+    rest := substring(rest FROM nul + 21);
+    entry := entry + 1;
+  END LOOP;
+
+  RETURN NULL;
+END;
+$BODY$
+LANGUAGE 'plpgsql';
+
+CREATE CONSTRAINT TRIGGER "objects.index_tree"
+  AFTER INSERT ON objects
+  DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW WHEN (NEW.type = 'tree')
+  EXECUTE PROCEDURE git_index_tree();
@@ -26,9 +26,19 @@ function sql() {
   done
 }
 
+# Migrations are recorded in schema_version as git-sql migrate would record them.
+function migration() {
+  for F in "${@}"
+  do
+    sql "${F}"
+    NAME="$(basename "${F}" .sql)"
+    echo "INSERT INTO schema_version (version, name) VALUES ($((10#${NAME%%_*})), '${NAME#*_}');" >> "${OUT}"
+  done
+}
+
 sql headers/*.sql
 sql types/*.sql
-sql tables/*.sql
+migration migrations/*.sql
 sql functions/specials/*.sql
 sql indexes/*.sql
 sql views/*.sql
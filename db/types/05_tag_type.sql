@@ -0,0 +1,28 @@
+CREATE OR REPLACE FUNCTION __do_create_tag_type__()
+  RETURNS VOID
+AS $BODY$
+
+BEGIN
+  IF NOT EXISTS(SELECT 1
+                FROM pg_type
+                WHERE typname = 'tag')
+  THEN
+    CREATE TYPE tag AS (
+      hash     TEXT,
+      object   TEXT,
+      type     objtype,
+      name     TEXT,
+      tagger   TEXT,
+      tag_time TIMESTAMP WITH TIME ZONE,
+      message  TEXT,
+      pgp      TEXT
+    );
+  END IF;
+END;
+
+$BODY$
+LANGUAGE 'plpgsql';
+
+SELECT __do_create_tag_type__();
+
+DROP FUNCTION __do_create_tag_type__();
@@ -2,7 +2,8 @@ DROP VIEW IF EXISTS headers CASCADE;
 
 CREATE VIEW headers AS
   SELECT
-    hash,
-    (git_parse_object_type(content))::objtype as type,
-    substring(content from position(E'\\000' in content) + 1) as content
-  FROM objects;
+    objects.hash,
+    objects.type,
+    objects.size,
+    substring(raw.content from position(E'\\000' in raw.content) + 1) as content
+  FROM objects, git_decode_object(objects.encoding, objects.content, objects.base, objects.large_object) AS raw(content);
@@ -2,5 +2,5 @@ DROP VIEW IF EXISTS contents CASCADE;
 
 CREATE VIEW contents AS SELECT
   objects.hash,
-  substring(objects.content from position(E'\\000' in objects.content) + 1) AS content
-FROM objects;
+  substring(raw.content from position(E'\\000' in raw.content) + 1) AS content
+FROM objects, git_decode_object(objects.encoding, objects.content, objects.base, objects.large_object) AS raw(content);
@@ -0,0 +1,39 @@
+DROP VIEW IF EXISTS tags CASCADE;
+
+CREATE VIEW tags AS WITH raw_tag_texts AS (
+    SELECT
+      headers.hash,
+      encode(headers.content, 'escape') AS info
+    FROM headers
+    WHERE headers.type = 'tag'::objtype
+),
+subsections AS (
+    SELECT
+      raw_tag_texts.hash,
+      CASE WHEN position(E'\n\n' in raw_tag_texts.info) = 0 THEN raw_tag_texts.info
+        ELSE substring(raw_tag_texts.info for position(E'\n\n' in raw_tag_texts.info) - 1)
+      END AS meta,
+      CASE WHEN position(E'\n\n' in raw_tag_texts.info) = 0 THEN ''
+        ELSE substring(raw_tag_texts.info from position(E'\n\n' in raw_tag_texts.info) + 2)
+      END AS body
+    FROM raw_tag_texts
+),
+-- The signature of a signed tag is appended to its message.
+signed AS (
+    SELECT
+      subsections.*,
+      substring(subsections.body from '(?:^|\n)(-----BEGIN [A-Z]+ SIGNATURE-----[\s\S]*)$') AS pgp
+    FROM subsections
+)
+SELECT
+  signed.hash,
+  (regexp_match(signed.meta, '^object ([0-9a-f]+)$', 'n'))[1] AS object,
+  (regexp_match(signed.meta, '^type (commit|tree|blob|tag)$', 'n'))[1]::objtype AS type,
+  (regexp_match(signed.meta, '^tag (.*)$', 'n'))[1] AS name,
+  (regexp_match(signed.meta, '^tagger (.*>)', 'n'))[1] AS tagger,
+  to_timestamp((regexp_match(signed.meta, '^tagger .*> (\d+)', 'n'))[1]::BIGINT) AS tag_time,
+  CASE WHEN signed.pgp IS NULL THEN signed.body
+    ELSE left(signed.body, length(signed.body) - length(signed.pgp))
+  END AS message,
+  signed.pgp
+FROM signed;
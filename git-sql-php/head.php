@@ -6,7 +6,7 @@ $found = false;
 
 header('Content-Type: text/plain');
 
-foreach (pg_fetch_all(git_load_refs($conn)) as $row) {
+foreach (pg_fetch_all(git_load_refs($conn, load_get_param('repo'))) as $row) {
     if ($row['name'] == 'HEAD') {
         $found = true;
         $target = $row['target'];
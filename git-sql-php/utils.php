@@ -84,10 +84,12 @@ function git_load_obj($db, $hash) {
     }
 }
 
-function git_load_refs($db) {
-    $result = pg_query(
+function git_load_refs($db, $repo_name) {
+    $result = pg_query_params(
       $db,
-      "SELECT name, git_resolve_ref(target) as target FROM refs"
+      "SELECT r.name, git_resolve_ref(r.repository, r.target) as target FROM refs r " .
+      "JOIN repositories p ON p.id = r.repository WHERE p.name = $1",
+      array($repo_name)
     ) or die_server_error('Failed to query database for refs.');
 
     return $result;
@@ -118,10 +118,14 @@ for row in cursor:
 
 conn.commit()
 
+cursor.execute('INSERT INTO repositories (name) VALUES (%s) ON CONFLICT (name) DO NOTHING;', (argv[2],))
+cursor.execute('SELECT id FROM repositories WHERE name = %s;', (argv[2],))
+repository_id = cursor.fetchone()[0]
+
 for ref_name in repo.references:  # type: str
     ref = repo.references[ref_name]
 
-    cursor.execute('SELECT target FROM refs WHERE name = %s;', (ref_name,))
+    cursor.execute('SELECT target FROM refs WHERE repository = %s AND name = %s;', (repository_id, ref_name,))
 
     found = cursor.rowcount
     current_target = None
@@ -131,8 +135,9 @@ for ref_name in repo.references:  # type: str
 
     if target != current_target:
         cn.execute(
-            'INSERT INTO refs (name, target) VALUES (%s, %s) ON CONFLICT (name) DO UPDATE SET target = %s;',
-            (ref_name, target, target,)
+            'INSERT INTO refs (repository, name, target) VALUES (%s, %s, %s) '
+            'ON CONFLICT (repository, name) DO UPDATE SET target = %s;',
+            (repository_id, ref_name, target, target,)
         )
         print('updated %s to %s' % (ref_name, target))
 
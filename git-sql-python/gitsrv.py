@@ -333,17 +333,21 @@ def handle_info_route(request):
 
 
 def handle_refs_route(request):
-    pool, conn = grab_connection(request.match_info['repo'])
+    repo_name = request.match_info['repo']
+    pool, conn = grab_connection(repo_name)
     cursor = conn.cursor()
 
     try:
-        cursor.execute('SELECT name FROM refs')
+        cursor.execute(
+            'SELECT r.name, git_resolve_ref(r.repository, r.target) FROM refs r '
+            'JOIN repositories p ON p.id = r.repository WHERE p.name = %s',
+            (repo_name,)
+        )
         rows = cursor.fetchall()
         result = ""
         for row in rows:
             ref = row[0]
-            cursor.execute("SELECT git_resolve_ref(%s)", (ref,))
-            real = cursor.fetchone()[0]
+            real = row[1]
             result += '{0}\t{1}\n'.format(real, ref)
 
         return web.Response(text=result)
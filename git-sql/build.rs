@@ -44,12 +44,44 @@ fn main() {
     
     scan_and_append(&mut out, &db_dir, "headers/*.sql");
     scan_and_append(&mut out, &db_dir, "types/*.sql");
-    scan_and_append(&mut out, &db_dir, "tables/*.sql");
     scan_and_append(&mut out, &db_dir, "functions/specials/*.sql");
     scan_and_append(&mut out, &db_dir, "indexes/*.sql");
     scan_and_append(&mut out, &db_dir, "views/*.sql");
     scan_and_append(&mut out, &db_dir, "functions/*.sql");
-    scan_and_append(&mut out, &db_dir, "caches/*.sql");
 
     file.write_all(out.as_bytes()).unwrap();
+
+    write_migrations(&db_dir, &out_dir.join("migrations.rs"));
+}
+
+/// Embeds `db/migrations/NNNN_name.sql` files, in order, as a `MIGRATIONS` table.
+fn write_migrations(db_dir: &Path, target: &Path) {
+    let mut pat = String::new();
+    pat.push_str(db_dir.join("migrations").to_str().unwrap());
+    pat.push_str("/*.sql");
+
+    let mut out = String::from("pub const MIGRATIONS: &[Migration] = &[\n");
+    let mut last = 0;
+    for entry in glob(pat.as_str()).unwrap() {
+        let path = fs::canonicalize(entry.unwrap()).unwrap();
+        println!("cargo:rerun-if-changed={}", path.to_str().unwrap());
+
+        let stem = path.file_stem().unwrap().to_str().unwrap().to_string();
+        let mut parts = stem.splitn(2, '_');
+        let version = parts.next().unwrap().parse::<i32>()
+            .expect("Migration file names must start with a version number.");
+        let name = parts.next().expect("Migration file names must be NNNN_name.sql.");
+        if version != last + 1 {
+            panic!("Migration {} does not follow version {}.", stem, last);
+        }
+        last = version;
+
+        out.push_str(&format!(
+            "    Migration {{ version: {}, name: {:?}, sql: include_str!({:?}) }},\n",
+            version, name, path.to_str().unwrap()
+        ));
+    }
+    out.push_str("];\n");
+
+    fs::File::create(target).unwrap().write_all(out.as_bytes()).unwrap();
 }
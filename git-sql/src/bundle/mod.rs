@@ -0,0 +1,162 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use revwalk::{self, ObjectWalk};
+
+use std::io::{BufRead, Write};
+
+const V2_SIGNATURE: &str = "# v2 git bundle";
+const V3_SIGNATURE: &str = "# v3 git bundle";
+
+/// The header of a bundle: the commits a receiver must already have and the refs it provides.
+pub struct BundleHeader {
+    pub prerequisites: Vec<String>,
+    pub refs: Vec<(String, String)>
+}
+
+fn is_hash(text: &str) -> bool {
+    text.len() == 40 && text.chars().all(|x| x.is_ascii_hexdigit())
+}
+
+/// Writes a bundle of the given refs, or of every ref if none are given.
+///
+/// Arguments starting with `^` name commits the receiver is expected to have; they are
+/// excluded from the pack and recorded as prerequisites.
+pub fn create_bundle<W: Write>(client: &GitSqlClient, specs: &[String], version: u32, out: &mut W) -> Result<usize> {
+    if version != 2 && version != 3 {
+        return Err(SimpleError::new(format!("Unsupported bundle version {}.", version)));
+    }
+
+    let mut refs: Vec<(String, String)> = Vec::new();
+    let mut prerequisites: Vec<String> = Vec::new();
+
+    for spec in specs {
+        if let Some(name) = spec.strip_prefix('^') {
+            let name = name.to_string();
+            let target = if is_hash(&name) {
+                name.clone()
+            } else {
+                client.resolve_ref_name(&name)?
+                    .ok_or_else(|| SimpleError::new(format!("Unknown reference {}.", name)))?.1
+            };
+
+            match revwalk::peel_to_commit(client, &target)? {
+                Some(commit) => prerequisites.push(commit),
+                None => return Err(SimpleError::new(format!("{} is not a commit.", name)))
+            }
+        } else {
+            let found = client.resolve_ref_name(spec)?
+                .ok_or_else(|| SimpleError::new(format!("Unknown reference {}.", spec)))?;
+            refs.push(found);
+        }
+    }
+
+    if refs.is_empty() {
+        refs = client.list_refs()?.into_iter()
+            .filter(|x| !x.0.ends_with("^{}"))
+            .collect();
+        refs.sort();
+    }
+
+    if refs.is_empty() {
+        return Err(SimpleError::new("Refusing to create an empty bundle."));
+    }
+
+    let mut walk = ObjectWalk::new(client);
+    walk.hide(&prerequisites)?;
+    let tips: Vec<String> = refs.iter().map(|x| x.1.clone()).collect();
+    walk.push(&tips)?;
+    let objects = walk.into_objects();
+
+    let mut header = String::new();
+    header.push_str(if version == 3 { V3_SIGNATURE } else { V2_SIGNATURE });
+    header.push('\n');
+    if version == 3 {
+        header.push_str("@object-format=sha1\n");
+    }
+    for prerequisite in &prerequisites {
+        header.push_str(&format!("-{}\n", prerequisite));
+    }
+    for (name, target) in &refs {
+        header.push_str(&format!("{} {}\n", target, name));
+    }
+    header.push('\n');
+    out.write_all(header.as_bytes()).map_err(SimpleError::from)?;
+
+    client.write_pack(&objects, &client.pack_options(), out)?;
+    out.flush().map_err(SimpleError::from)?;
+    Ok(objects.len())
+}
+
+fn read_line<R: BufRead>(input: &mut R) -> Result<String> {
+    let mut line = Vec::new();
+    input.read_until(b'\n', &mut line).map_err(SimpleError::from)?;
+    if line.last() != Some(&b'\n') {
+        return Err(SimpleError::new("Unexpected end of bundle header."));
+    }
+    line.pop();
+    String::from_utf8(line).map_err(|_| SimpleError::new("Bundle header is not valid UTF-8."))
+}
+
+/// Reads a bundle header, leaving the input positioned at the start of the pack.
+pub fn read_header<R: BufRead>(input: &mut R) -> Result<BundleHeader> {
+    let signature = read_line(input)?;
+    let version = match signature.as_str() {
+        V2_SIGNATURE => 2,
+        V3_SIGNATURE => 3,
+        _ => return Err(SimpleError::new("Not a v2 or v3 git bundle."))
+    };
+
+    let mut header = BundleHeader { prerequisites: Vec::new(), refs: Vec::new() };
+    loop {
+        let line = read_line(input)?;
+        if line.is_empty() {
+            return Ok(header);
+        }
+
+        if version == 3 && line.starts_with('@') {
+            if line != "@object-format=sha1" {
+                return Err(SimpleError::new(format!("Unsupported bundle capability {}.", &line[1..])));
+            }
+        } else if let Some(prerequisite) = line.strip_prefix('-') {
+            let hash = prerequisite.split(' ').next().unwrap_or("");
+            header.prerequisites.push(hash.to_string());
+        } else {
+            let mut parts = line.splitn(2, ' ');
+            let hash = parts.next().unwrap_or("").to_string();
+            let name = parts.next()
+                .ok_or_else(|| SimpleError::new(format!("Malformed bundle ref line: {}", line)))?;
+            header.refs.push((name.to_string(), hash));
+        }
+    }
+}
+
+/// Imports a bundle after checking that every prerequisite is stored, then updates its refs.
+pub fn import_bundle<R: BufRead>(client: &GitSqlClient, input: &mut R) -> Result<(usize, usize)> {
+    let header = read_header(input)?;
+
+    let mut missing = Vec::new();
+    for prerequisite in &header.prerequisites {
+        if !client.has_object(prerequisite)? {
+            missing.push(prerequisite.clone());
+        }
+    }
+
+    if !missing.is_empty() {
+        return Err(SimpleError::new(format!("Bundle requires missing commits: {}", missing.join(", "))));
+    }
+
+    let count = client.import_pack(input)?;
+
+    let mut updated = 0;
+    for (name, target) in &header.refs {
+        // HEAD is symbolic in the database; a bundle only records the commit it resolved to.
+        if name == "HEAD" || !name.starts_with("refs/") {
+            continue;
+        }
+
+        client.set_ref_peeled(name, target, "bundle: import")?;
+        updated += 1;
+    }
+
+    Ok((count, updated))
+}
@@ -1,21 +1,67 @@
 use core::{SimpleError, Result};
+use object::{self, Commit, TreeEntry};
+use pack::{PackOptions};
+use pack::builder::{self, DeltaPackWriter, PackEntry};
+use pack::reader::{PackReader, PackObject};
+use pack::writer::{IndexEntry};
+use storage::{self, StorageOptions, Offload};
 
-use postgres::{Connection, TlsMode};
+use postgres::{Connection, GenericConnection, TlsMode};
 
 use postgres::stmt::Statement;
 use postgres::transaction::Transaction;
 use postgres_openssl::OpenSsl;
 use postgres_array::Array;
 
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Write};
+use std::fs;
+use std::io;
+use std::path::{PathBuf};
 
 use git2::ObjectType;
 
 use sha1;
 
+const PACK_READ_CHUNK: usize = 500;
+const INSERT_CHUNK: usize = 500;
+const IMPORT_BATCH_BYTES: usize = 64 * 1024 * 1024;
+const REPACK_LOCK: i32 = 1;
+const PACK_LOCK: i32 = 2;
+const PACK_GRACE_PERIOD: &str = "1 hour";
+const LARGE_OBJECT_CHUNK: usize = 1024 * 1024;
+const FRESHEN_AGE: &str = "1 day";
+
+mod stream;
+
+pub use self::stream::{ObjectStream, LargeObjectReader, LargeObjectWriter};
+
 pub struct GitSqlClient {
     conn: Connection,
-    url: String
+    url: String,
+    repository: Option<(i32, String)>,
+    pack_options: PackOptions,
+    storage_options: StorageOptions,
+    actor: Option<String>
+}
+
+pub struct ReflogEntry {
+    pub id: i64,
+    pub name: String,
+    pub old_target: Option<String>,
+    pub new_target: Option<String>,
+    pub time: String,
+    pub actor: String,
+    pub message: Option<String>
+}
+
+pub struct UnreachableObject {
+    pub hash: String,
+    pub depth: usize,
+    pub encoding: String,
+    pub path: Option<String>,
+    pub bytes: usize,
+    pub expired: bool
 }
 
 #[allow(dead_code)]
@@ -31,52 +77,58 @@ impl GitSqlClient {
     }
     
     pub fn from_conn(conn: Connection, url: String) -> GitSqlClient {
-        GitSqlClient { conn, url }
+        GitSqlClient {
+            conn,
+            url,
+            repository: None,
+            pack_options: PackOptions::new(),
+            storage_options: StorageOptions::new(),
+            actor: None
+        }
     }
 
-    pub fn read_raw_object(&self, hash: &String) -> Result<Vec<u8>> {
-        let result = self.conn.query("SELECT content FROM objects WHERE hash = $1", &[hash]);
+    pub fn open_repository(&mut self, name: &String) -> Result<()> {
+        let result = self.conn.query("SELECT id FROM repositories WHERE name = $1", &[name]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
         let rows = result.unwrap();
-        if rows.len() == 0 {
-            return Err(SimpleError::new("Object not found."));
+        if rows.is_empty() {
+            return Err(SimpleError::new(format!("Repository '{}' has not been initialized.", name)));
         }
 
-        let data: Option<Vec<u8>> = rows.get(0).get(0);
-        return Ok(data.unwrap());
+        self.repository = Some((rows.get(0).get(0), name.clone()));
+        Ok(())
     }
 
-    pub fn read_object(&self, hash: &String) -> Result<(ObjectType, Vec<u8>)> {
-        let result = self.conn.query("SELECT (type)::TEXT, content FROM headers WHERE hash = $1", &[hash]);
+    pub fn create_repository(&mut self, name: &String) -> Result<()> {
+        let result = self.conn.execute(
+            "INSERT INTO repositories (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+            &[name]
+        );
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        let rows = result.unwrap();
-        if rows.len() == 0 {
-            return Err(SimpleError::new("Unknown Object."));
-        }
+        self.open_repository(name)
+    }
 
-        let row = rows.get(0);
-        let objtype: Option<String> = row.get(0);
-        let bytes: Option<Vec<u8>> = row.get(1);
-        let rtype = ObjectType::from_str(&objtype.unwrap());
+    pub fn repository_id(&self) -> Result<i32> {
+        match self.repository {
+            Some((id, _)) => Ok(id),
+            None => Err(SimpleError::new("No repository selected."))
+        }
+    }
 
-        return Ok((rtype.unwrap(), bytes.unwrap()));
+    pub fn repository_name(&self) -> Option<String> {
+        self.repository.as_ref().map(|x| x.1.clone())
     }
 
-    pub fn read_file_at(&self, path: &String, at: &String) -> Result<(ObjectType, Vec<u8>)> {
-        let result = self.conn.query(
-            "WITH real as (SELECT * FROM git_lookup_tree_item_at($1, git_resolve_ref($2)) AS hash) \
-             SELECT type::TEXT, content FROM real JOIN headers head ON (head.hash = real.hash)", &[
-            path,
-            at
-        ]);
+    pub fn read_stored_object(&self, hash: &String) -> Result<(String, Vec<u8>)> {
+        let result = self.conn.query("SELECT encoding, content FROM objects WHERE hash = $1", &[hash]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
@@ -84,306 +136,1650 @@ impl GitSqlClient {
 
         let rows = result.unwrap();
         if rows.len() == 0 {
-            return Err(SimpleError::new("Unknown Object."));
+            return Err(SimpleError::new("Object not found."));
         }
 
         let row = rows.get(0);
-        let objtype: Option<String> = row.get(0);
-        let bytes: Option<Vec<u8>> = row.get(1);
-        let rtype = ObjectType::from_str(&objtype.unwrap());
-
-        return Ok((rtype.unwrap(), bytes.unwrap()));
+        Ok((row.get(0), row.get(1)))
     }
 
-    pub fn resolve_ref(&self, input: &String) -> Result<String> {
-        let result = self.conn.query("SELECT git_resolve_ref($1)", &[input]);
+    pub fn read_object_location(&self, hash: &String) -> Result<storage::ObjectLocation> {
+        let result = self.conn.query("SELECT encoding, content, large_object, path FROM objects WHERE hash = $1", &[hash]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        let resolved: Option<String> = result.unwrap().get(0).get(0);
-
-        return Ok(resolved.unwrap());
-    }
+        let rows = result.unwrap();
+        if rows.is_empty() {
+            return Err(SimpleError::new("Object not found."));
+        }
 
-    pub fn run_sql(&self, input: &String) -> Result<()> {
-        self.conn.batch_execute(input).map_err(|x| SimpleError::from(x))
+        let row = rows.get(0);
+        Ok((row.get(0), row.get(1), row.get(2), row.get(3)))
     }
 
-    pub fn list_ref_names(&self) -> Result<Vec<String>> {
-        let mut refs: Vec<String> = Vec::new();
-        let result = self.conn.query("SELECT name FROM refs", &[]);
+    pub fn read_large_object(&self, oid: u32, offset: usize, length: usize) -> Result<Vec<u8>> {
+        let result = self.conn.query("SELECT lo_get($1, $2, $3)", &[&oid, &(offset as i64), &(length as i32)]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        for row in &result.unwrap() {
-            let name: String = row.get(0);
-            refs.push(name);
-        }
-
-        return Ok(refs);
+        return Ok(result.unwrap().get(0).get(0));
     }
 
-    pub fn list_refs(&self) -> Result<Vec<(String, String)>> {
-        let mut refs: Vec<(String, String)> = Vec::new();
-        let result = self.conn.query(
-            "SELECT name, git_resolve_ref(target) as target FROM refs",
-            &[],
-        );
+    pub fn create_large_object(&self) -> Result<u32> {
+        let result = self.conn.query("SELECT lo_create(0)", &[]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
-
-        for row in &result.unwrap() {
-            let name: String = row.get(0);
-            let target: String = row.get(1);
-            refs.push((name, target));
-        }
-
-        return Ok(refs);
+        return Ok(result.unwrap().get(0).get(0));
     }
 
-    pub fn start_object_list(&self) -> Result<(Statement)> {
-        let conn = &self.conn;
-        let mut result = conn.execute("CREATE TEMPORARY TABLE objlist(hash TEXT)", &[]);
+    pub fn write_large_object(&self, oid: u32, offset: usize, data: &[u8]) -> Result<()> {
+        let result = self.conn.execute("SELECT lo_put($1, $2, $3)", &[&oid, &(offset as i64), &data]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
+        Ok(())
+    }
 
-        result = conn.execute("TRUNCATE objlist", &[]);
+    pub fn unlink_large_object(&self, oid: u32) -> Result<()> {
+        let result = self.conn.execute("SELECT lo_unlink($1)", &[&oid]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
+        Ok(())
+    }
 
-        let stmt = conn.prepare("INSERT INTO objlist(hash) SELECT * FROM unnest($1::TEXT[])");
+    pub fn filter_offloaded(&self, hashes: &[String]) -> Result<HashSet<String>> {
+        let result = self.conn.query(
+            "SELECT hash FROM objects WHERE hash = ANY($1) AND encoding IN ('largeobject', 'file')",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
 
-        if stmt.is_err() {
-            return Err(SimpleError::from(stmt.err().unwrap()));
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        Ok(stmt.unwrap())
+        Ok(result.unwrap().iter().map(|row| row.get(0)).collect())
     }
 
-    pub fn add_hashes_to_object_list(
-        &self,
-        handle: &(Statement),
-        hashes: &Vec<String>,
-    ) -> Result<()> {
-        let stmt = handle;
-
-        let hash_vec = hashes.clone();
-        let hash_array = &Array::from_vec(hash_vec, 0);
+    pub fn open_object(&self, hash: &String) -> Result<ObjectStream<&GitSqlClient>> {
+        ObjectStream::open(self, hash)
+    }
 
-        let result = stmt.execute(&[hash_array]);
+    pub fn read_raw_object(&self, hash: &String) -> Result<Vec<u8>> {
+        let result = self.conn.query(
+            "WITH RECURSIVE chain(hash, encoding, content, base, depth) AS ( \
+               SELECT hash, encoding, content, base, depth FROM objects WHERE hash = $1 \
+               UNION ALL \
+               SELECT o.hash, o.encoding, o.content, o.base, o.depth \
+               FROM objects o JOIN chain c ON (o.hash = c.base AND o.depth = c.depth - 1) \
+             ) SELECT encoding, content FROM chain ORDER BY depth",
+            &[hash]
+        );
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        result.unwrap();
-
-        return Ok(());
-    }
-
-    pub fn diff_object_list<C>(&self, cb: C) -> Result<()>
-    where
-        C: Fn(String, usize, usize)
-    {
-        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        let chain: Vec<(String, Vec<u8>)> = result.unwrap().iter().map(|row| (row.get(0), row.get(1))).collect();
+        if chain.len() == 1 && storage::is_offloaded(&chain[0].0) {
+            let mut stream = self.open_object(hash)?;
+            let mut raw = stream.header();
+            io::Read::read_to_end(&mut stream, &mut raw).map_err(SimpleError::from)?;
+            return Ok(raw);
         }
+        storage::decode_chain(chain)
+    }
 
-        tmp_result = self.conn.execute(
-            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
-            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
-            &[]
+    pub fn read_raw_objects(&self, hashes: &[String]) -> Result<Vec<(String, Vec<u8>)>> {
+        let hash_array = &Array::from_vec(hashes.to_vec(), 0);
+        let result = self.conn.query(
+            "WITH RECURSIVE chain(hash, base, depth) AS ( \
+               SELECT hash, base, depth FROM objects WHERE hash = ANY($1) \
+               UNION \
+               SELECT o.hash, o.base, o.depth \
+               FROM objects o JOIN chain c ON (o.hash = c.base AND o.depth = c.depth - 1) \
+             ) SELECT o.hash, o.encoding, o.content, o.base FROM objects o JOIN chain c ON (c.hash = o.hash)",
+            &[hash_array]
         );
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
-        }
-
-        let result = self.conn.query("SELECT * FROM objdiff", &[]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        let rows = result.unwrap();
-        let total = rows.len();
+        let mut rows: HashMap<String, storage::StoredObject> = HashMap::new();
+        for row in &result.unwrap() {
+            rows.insert(row.get(0), (row.get(1), row.get(2), row.get(3)));
+        }
 
-        let mut index = 0;
-        for row in &rows {
-            cb(row.get(0), index, total);
-            index = index + 1;
+        let mut resolved = HashMap::new();
+        let mut objects = Vec::new();
+        for hash in hashes {
+            let raw = match rows.get(hash) {
+                Some(row) if storage::is_offloaded(&row.0) => self.read_raw_object(hash)?,
+                Some(_) => storage::resolve(hash, &rows, &mut resolved)?,
+                None => return Err(SimpleError::new("Unknown Object."))
+            };
+            objects.push((hash.clone(), raw));
         }
 
-        return Ok(());
+        Ok(objects)
     }
 
-    pub fn diff_object_list_direct(&self) -> Result<Vec<String>> {
-        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
-        }
+    pub fn read_object(&self, hash: &String) -> Result<(ObjectType, Vec<u8>)> {
+        storage::split(self.read_raw_object(hash)?)
+    }
 
-        tmp_result = self.conn.execute(
-            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
-            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
-            &[]
-        );
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
-        }
+    pub fn read_file_at(&self, path: &String, at: &String) -> Result<(ObjectType, Vec<u8>)> {
+        let (_, hash) = self.find_file_at(path, at)?;
+        self.read_object(&hash)
+    }
 
-        let result = self.conn.query("SELECT * FROM objdiff", &[]);
+    pub fn find_file_at(&self, path: &String, at: &String) -> Result<(ObjectType, String)> {
+        let result = self.conn.query(
+            "SELECT o.type::TEXT, o.hash FROM objects o \
+             WHERE o.hash = git_lookup_tree_item_at($1, git_resolve_ref($3, $2))", &[
+            path,
+            at,
+            &self.repository_id()?
+        ]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
         let rows = result.unwrap();
-        let mut hashes : Vec<String> = Vec::new();
-        for row in &rows {
-            hashes.push(row.get(0));
+        if rows.is_empty() {
+            return Err(SimpleError::new("Unknown Object."));
         }
-        Ok(hashes)
-    }
 
-    pub fn diff_object_list_chunked(&self, chunk_size: usize) -> Result<Vec<Vec<String>>> {
-        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
-        }
+        let row = rows.get(0);
+        let objtype: String = row.get(0);
+        let rtype = ObjectType::from_str(&objtype);
 
-        tmp_result = self.conn.execute(
-            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
-            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
-            &[]
-        );
-        if tmp_result.is_err() {
-            return Err(SimpleError::from(tmp_result.err().unwrap()));
-        }
+        Ok((rtype.unwrap(), row.get(1)))
+    }
 
-        let result = self.conn.query("SELECT * FROM objdiff", &[]);
+    pub fn resolve_ref(&self, input: &String) -> Result<String> {
+        let result = self.conn.query("SELECT git_resolve_ref($1, $2)", &[&self.repository_id()?, input]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        let rows = result.unwrap();
-        let mut chunks = Vec::new();
+        let resolved: Option<String> = result.unwrap().get(0).get(0);
 
-        let mut index = 0;
+        return Ok(resolved.unwrap());
+    }
 
-        let mut buff : Vec<String> = Vec::new();
+    pub fn run_sql(&self, input: &String) -> Result<()> {
+        self.conn.batch_execute(input).map_err(|x| SimpleError::from(x))
+    }
 
-        for row in &rows {
-            buff.push(row.get(0));
-            if (index % chunk_size) == 0 {
-                chunks.push(buff);
-                buff = Vec::new();
-            }
-            index = index + 1;
+    pub fn schema_version(&self) -> Result<i32> {
+        let result = self.conn.query("SELECT to_regclass('schema_version') IS NOT NULL", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        if buff.len() > 0 {
-            chunks.push(buff);
+        let versioned: bool = result.unwrap().get(0).get(0);
+        if !versioned {
+            return Ok(0);
         }
 
-        Ok(chunks)
+        let result = self.conn.query("SELECT max(version) FROM schema_version", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let version: Option<i32> = result.unwrap().get(0).get(0);
+        Ok(version.unwrap_or(0))
     }
 
-    pub fn end_object_list(&self) -> Result<()> {
-        let mut result = self.conn.execute("DROP TABLE objlist", &[]);
+    pub fn apply_migration(&self, version: i32, name: &str, sql: &str, repository: &String) -> Result<()> {
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
 
+        let mut result = transact.execute("SELECT set_config('gitsql.repository', $1, true)", &[repository]);
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        result = self.conn.execute("DROP TABLE IF EXISTS objdiff", &[]);
+        transact.batch_execute(sql).map_err(SimpleError::from)?;
 
+        result = transact.execute(
+            "INSERT INTO schema_version (version, name) VALUES ($1, $2)",
+            &[&version, &name.to_string()]
+        );
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        return Ok(());
+        transact.commit().map_err(SimpleError::from)
     }
 
-    pub fn encode_object(kind: &ObjectType, size: usize, data: &[u8]) -> Vec<u8> {
-        let mut out: Vec<u8> = Vec::new();
-        let mut header = String::new();
-        write!(&mut header, "{} {}\0", kind, size).unwrap();
-        out.extend(header.as_bytes());
-        out.extend(data);
-        return out;
-    }
+    pub fn list_ref_names(&self) -> Result<Vec<String>> {
+        let mut refs: Vec<String> = Vec::new();
+        let result = self.conn.query("SELECT name FROM refs WHERE repository = $1", &[&self.repository_id()?]);
 
-    pub fn insert_object(&self, hash: &String, kind: &ObjectType, size: usize, data: &[u8]) -> Result<()> {
-        GitSqlClient::insert_object_indirect(&self.conn, hash, kind, size, data)
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        for row in &result.unwrap() {
+            let name: String = row.get(0);
+            refs.push(name);
+        }
+
+        return Ok(refs);
     }
 
-    pub fn insert_object_indirect(conn: &Connection, hash: &String, kind: &ObjectType, size: usize, data: &[u8]) -> Result<()> {
-        let encoded = &GitSqlClient::encode_object(kind, size, data);
-        let result = conn.execute(
-            "INSERT INTO objects (hash, content) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            &[hash, encoded],
+    pub fn list_refs(&self) -> Result<Vec<(String, String)>> {
+        let mut refs: Vec<(String, String)> = Vec::new();
+        let result = self.conn.query(
+            "SELECT name, git_resolve_ref(repository, target) as target FROM refs WHERE repository = $1",
+            &[&self.repository_id()?],
         );
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        return Ok(());
+        for row in &result.unwrap() {
+            let name: String = row.get(0);
+            let target: Option<String> = row.get(1);
+            if let Some(target) = target {
+                refs.push((name, target));
+            }
+        }
+
+        Ok(refs)
     }
 
+    pub fn list_refs_with_prefixes(&self, prefixes: &[String]) -> Result<Vec<(String, String)>> {
+        let patterns: Vec<String> = prefixes.iter()
+            .map(|x| format!("{}%", x.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")))
+            .collect();
 
-    pub fn insert_object_transaction(transact: &Transaction, hash: &String, kind: &ObjectType, size: usize, data: &[u8]) -> Result<()> {
-        let encoded = &GitSqlClient::encode_object(kind, size, data);
-        let result = transact.execute(
-            "INSERT INTO objects (hash, content) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            &[hash, encoded],
+        let mut refs: Vec<(String, String)> = Vec::new();
+        let result = self.conn.query(
+            "SELECT name, git_resolve_ref(repository, target) as target FROM refs \
+             WHERE repository = $1 AND name LIKE ANY($2)",
+            &[&self.repository_id()?, &Array::from_vec(patterns, 0)],
         );
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        return Ok(());
+        for row in &result.unwrap() {
+            let name: String = row.get(0);
+            let target: Option<String> = row.get(1);
+            if let Some(target) = target {
+                refs.push((name, target));
+            }
+        }
+
+        return Ok(refs);
     }
 
-    pub fn insert_object_verify(&self, kind: &ObjectType, size: usize, data: &[u8], expected: &String) -> Result<()> {
-        let encoded = &GitSqlClient::encode_object(kind, size, data);
-        let mut sha = sha1::Sha1::new();
-        sha.update(encoded.as_slice());
-        let hash = &sha.digest().to_string();
+    pub fn shallow_walk(&self, heads: &[String], depth: Option<i32>, since: Option<i64>, excluded: &[String]) -> Result<Vec<(String, bool)>> {
+        let result = self.conn.query(
+            "SELECT hash, boundary FROM git_shallow_walk($1, $2, to_timestamp($3::BIGINT), $4)",
+            &[&Array::from_vec(heads.to_vec(), 0), &depth, &since, &Array::from_vec(excluded.to_vec(), 0)]
+        );
 
-        if hash != expected {
-            let mut msg = String::new();
-            write!(&mut msg, "Expected hash to be {}, but encoded the object into a hash of {}", expected, hash).unwrap();
-            return Err(SimpleError::new(msg));
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        let result = self.conn.execute(
-            "INSERT INTO objects (hash, content) VALUES ($1, $2) ON CONFLICT DO NOTHING",
-            &[hash, encoded],
+        let mut commits = Vec::new();
+        for row in &result.unwrap() {
+            let hash: String = row.get(0);
+            let boundary: bool = row.get(1);
+            commits.push((hash, boundary));
+        }
+
+        Ok(commits)
+    }
+
+    pub fn new_commits(&self, tips: &[String], hidden: &[String], shallow: &[String], hidden_shallow: &[String]) -> Result<Vec<String>> {
+        let result = self.conn.query(
+            "SELECT git_new_commits($1, $2, $3, $4)",
+            &[
+                &Array::from_vec(tips.to_vec(), 0),
+                &Array::from_vec(hidden.to_vec(), 0),
+                &Array::from_vec(shallow.to_vec(), 0),
+                &Array::from_vec(hidden_shallow.to_vec(), 0)
+            ]
         );
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        return Ok(());
+        Ok(result.unwrap().iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn reaches_any(&self, hash: &String, targets: &[String]) -> Result<bool> {
+        let result = self.conn.query("SELECT git_reaches_any($1, $2)", &[hash, &Array::from_vec(targets.to_vec(), 0)]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(result.unwrap().get(0).get(0))
+    }
+
+    pub fn read_commit_graph(&self, hashes: &[String]) -> Result<Vec<(String, String, Vec<String>)>> {
+        let result = self.conn.query(
+            "SELECT m.hash, m.tree, array_remove(array_agg(p.parent ORDER BY p.position), NULL)
+            FROM commit_meta m LEFT JOIN commit_parents p ON (p.hash = m.hash)
+            WHERE m.hash = ANY($1)
+            GROUP BY m.hash, m.tree",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut commits = Vec::new();
+        for row in &result.unwrap() {
+            let parents: Array<String> = row.get(2);
+            commits.push((row.get(0), row.get(1), parents.into_iter().collect()));
+        }
+
+        Ok(commits)
+    }
+
+    pub fn refs_digest(&self) -> Result<String> {
+        let mut refs = self.list_refs()?;
+        refs.sort();
+
+        let mut sha = sha1::Sha1::new();
+        for (name, target) in refs {
+            sha.update(name.as_bytes());
+            sha.update(b" ");
+            sha.update(target.as_bytes());
+            sha.update(b"\n");
+        }
+        Ok(sha.digest().to_string())
+    }
+
+    pub fn find_cached_pack(&self, refs_digest: &String) -> Result<Option<String>> {
+        let result = self.conn.query(
+            "UPDATE packs SET superseded = NULL WHERE repository = $1 AND refs_digest = $2 RETURNING name",
+            &[&self.repository_id()?, refs_digest]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        if rows.is_empty() {
+            return Ok(None);
+        }
+        return Ok(Some(rows.get(0).get(0)));
+    }
+
+    pub fn lock_packs(&self) -> Result<()> {
+        let result = self.conn.execute("SELECT pg_advisory_lock($1, $2)", &[&PACK_LOCK, &self.repository_id()?]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+        Ok(())
+    }
+
+    pub fn unlock_packs(&self) -> Result<()> {
+        let result = self.conn.execute("SELECT pg_advisory_unlock($1, $2)", &[&PACK_LOCK, &self.repository_id()?]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+        Ok(())
+    }
+
+    pub fn store_cached_pack(&self, name: &String, refs_digest: &String, large_object: u32, size: usize, index: &Vec<u8>, hashes: &[String]) -> Result<()> {
+        let repository = self.repository_id()?;
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+
+        let mut result = transact.execute(
+            "UPDATE packs SET superseded = now() WHERE repository = $1 AND refs_digest != $2 AND superseded IS NULL",
+            &[&repository, refs_digest]
+        );
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        result = transact.execute(
+            "DELETE FROM packs WHERE repository = $1 AND superseded < now() - $2::TEXT::INTERVAL",
+            &[&repository, &PACK_GRACE_PERIOD]
+        );
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        // The same objects may already have been packed for other references.
+        let stored = transact.query(
+            "INSERT INTO packs (repository, name, refs_digest, large_object, size, index) VALUES ($1, $2, $3, $4, $5, $6) \
+             ON CONFLICT (repository, name) DO UPDATE SET refs_digest = $3, superseded = NULL RETURNING large_object",
+            &[&repository, name, refs_digest, &large_object, &(size as i64), index]
+        );
+        if stored.is_err() {
+            return Err(SimpleError::from(stored.err().unwrap()));
+        }
+
+        let existing: u32 = stored.unwrap().get(0).get(0);
+        if existing != large_object {
+            result = transact.execute("SELECT lo_unlink($1)", &[&large_object]);
+            if result.is_err() {
+                return Err(SimpleError::from(result.err().unwrap()));
+            }
+        }
+
+        for chunk in hashes.chunks(INSERT_CHUNK) {
+            result = transact.execute(
+                "INSERT INTO pack_objects (repository, pack, hash) SELECT $1, $2, unnest($3::TEXT[]) \
+                 ON CONFLICT DO NOTHING",
+                &[&repository, name, &Array::from_vec(chunk.to_vec(), 0)]
+            );
+            if result.is_err() {
+                return Err(SimpleError::from(result.err().unwrap()));
+            }
+        }
+
+        transact.commit().map_err(SimpleError::from)
+    }
+
+    pub fn read_cached_pack(&self, name: &String) -> Result<(u32, usize)> {
+        let result = self.conn.query(
+            "SELECT large_object, size FROM packs WHERE repository = $1 AND name = $2",
+            &[&self.repository_id()?, name]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        if rows.is_empty() {
+            return Err(SimpleError::new("Pack not found."));
+        }
+        let size: i64 = rows.get(0).get(1);
+        return Ok((rows.get(0).get(0), size as usize));
+    }
+
+    pub fn read_cached_pack_index(&self, name: &String) -> Result<Vec<u8>> {
+        let result = self.conn.query(
+            "SELECT index FROM packs WHERE repository = $1 AND name = $2",
+            &[&self.repository_id()?, name]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        if rows.is_empty() {
+            return Err(SimpleError::new("Pack not found."));
+        }
+        return Ok(rows.get(0).get(0));
+    }
+
+    pub fn start_object_list(&self) -> Result<(Statement)> {
+        let conn = &self.conn;
+        let mut result = conn.execute("CREATE TEMPORARY TABLE objlist(hash TEXT)", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        result = conn.execute("TRUNCATE objlist", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let stmt = conn.prepare("INSERT INTO objlist(hash) SELECT * FROM unnest($1::TEXT[])");
+
+        if stmt.is_err() {
+            return Err(SimpleError::from(stmt.err().unwrap()));
+        }
+
+        Ok(stmt.unwrap())
+    }
+
+    pub fn add_hashes_to_object_list(
+        &self,
+        handle: &(Statement),
+        hashes: &Vec<String>,
+    ) -> Result<()> {
+        let stmt = handle;
+
+        let hash_vec = hashes.clone();
+        let hash_array = &Array::from_vec(hash_vec, 0);
+
+        let result = stmt.execute(&[hash_array]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        result.unwrap();
+
+        return Ok(());
+    }
+
+    pub fn diff_object_list<C>(&self, cb: C) -> Result<()>
+    where
+        C: Fn(String, usize, usize)
+    {
+        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        tmp_result = self.conn.execute(
+            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
+            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
+            &[]
+        );
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        let result = self.conn.query("SELECT * FROM objdiff", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        let total = rows.len();
+
+        let mut index = 0;
+        for row in &rows {
+            cb(row.get(0), index, total);
+            index = index + 1;
+        }
+
+        return Ok(());
+    }
+
+    pub fn diff_object_list_direct(&self) -> Result<Vec<String>> {
+        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        tmp_result = self.conn.execute(
+            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
+            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
+            &[]
+        );
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        let result = self.conn.query("SELECT * FROM objdiff", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        let mut hashes : Vec<String> = Vec::new();
+        for row in &rows {
+            hashes.push(row.get(0));
+        }
+        Ok(hashes)
+    }
+
+    pub fn diff_object_list_chunked(&self, chunk_size: usize) -> Result<Vec<Vec<String>>> {
+        let mut tmp_result = self.conn.execute("CREATE TEMPORARY TABLE objdiff (hash TEXT)", &[]);
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        tmp_result = self.conn.execute(
+            "INSERT INTO objdiff (hash) SELECT hash FROM objlist c WHERE NOT EXISTS \
+            (SELECT 1 FROM objects s WHERE s.hash = c.hash)",
+            &[]
+        );
+        if tmp_result.is_err() {
+            return Err(SimpleError::from(tmp_result.err().unwrap()));
+        }
+
+        let result = self.conn.query("SELECT * FROM objdiff", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        let mut chunks = Vec::new();
+
+        let mut index = 0;
+
+        let mut buff : Vec<String> = Vec::new();
+
+        for row in &rows {
+            buff.push(row.get(0));
+            if (index % chunk_size) == 0 {
+                chunks.push(buff);
+                buff = Vec::new();
+            }
+            index = index + 1;
+        }
+
+        if buff.len() > 0 {
+            chunks.push(buff);
+        }
+
+        Ok(chunks)
+    }
+
+    pub fn end_object_list(&self) -> Result<()> {
+        let mut result = self.conn.execute("DROP TABLE objlist", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        result = self.conn.execute("DROP TABLE IF EXISTS objdiff", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        return Ok(());
+    }
+
+    pub fn encode_object(kind: &ObjectType, size: usize, data: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut header = String::new();
+        write!(&mut header, "{} {}\0", kind, size).unwrap();
+        out.extend(header.as_bytes());
+        out.extend(data);
+        return out;
+    }
+
+    pub fn insert_object(&self, hash: &String, kind: &ObjectType, size: usize, data: &[u8]) -> Result<()> {
+        GitSqlClient::insert_object_indirect(&self.conn, hash, kind, size, data, &self.storage_options)
+    }
+
+    pub fn insert_object_indirect(conn: &Connection, hash: &String, kind: &ObjectType, size: usize, data: &[u8], options: &StorageOptions) -> Result<()> {
+        GitSqlClient::insert_raw_object(conn, hash, GitSqlClient::encode_object(kind, size, data), options)
+    }
+
+
+    pub fn insert_object_transaction(transact: &Transaction, hash: &String, kind: &ObjectType, size: usize, data: &[u8], options: &StorageOptions) -> Result<()> {
+        GitSqlClient::insert_raw_object(transact, hash, GitSqlClient::encode_object(kind, size, data), options)
+    }
+
+    fn insert_raw_object(conn: &dyn GenericConnection, hash: &String, raw: Vec<u8>, options: &StorageOptions) -> Result<()> {
+        let (kind, size) = storage::read_header(storage::ENCODING_RAW, &raw)?;
+        match kind {
+            ObjectType::Commit => {
+                // Indexed in the transaction of the insert, so the indexing triggers skip it.
+                let commit = object::parse_commit(&raw[raw.len() - size..])?;
+                let transact = conn.transaction().map_err(SimpleError::from)?;
+                GitSqlClient::insert_raw_object_data(&transact, hash, raw, options)?;
+                GitSqlClient::insert_commits(&transact, &[(hash.clone(), commit)])?;
+                transact.commit().map_err(SimpleError::from)
+            },
+            ObjectType::Tree => {
+                let entries = object::parse_tree(&raw[raw.len() - size..])?;
+                let transact = conn.transaction().map_err(SimpleError::from)?;
+                GitSqlClient::insert_raw_object_data(&transact, hash, raw, options)?;
+                GitSqlClient::insert_tree_entries(&transact, &[(hash.clone(), entries)])?;
+                transact.commit().map_err(SimpleError::from)
+            },
+            _ => GitSqlClient::insert_raw_object_data(conn, hash, raw, options)
+        }
+    }
+
+    fn insert_raw_object_data(conn: &dyn GenericConnection, hash: &String, raw: Vec<u8>, options: &StorageOptions) -> Result<()> {
+        if options.should_offload(raw.len()) {
+            return GitSqlClient::offload_object(conn, hash, raw, options).map(|_| ());
+        }
+
+        let (kind, size) = storage::read_header(storage::ENCODING_RAW, &raw)?;
+        let (encoding, stored) = storage::encode(hash, raw, options)?;
+        let result = conn.execute(
+            "INSERT INTO objects (hash, content, encoding, type, size) \
+             VALUES ($1, $2, $3, $4::TEXT::objtype, $5) \
+             ON CONFLICT (hash) DO UPDATE SET created = now() WHERE objects.created < now() - $6::TEXT::INTERVAL",
+            &[hash, &stored, &encoding, &kind.str(), &(size as i64), &FRESHEN_AGE],
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        return Ok(());
+    }
+
+    fn offload_object(conn: &dyn GenericConnection, hash: &String, raw: Vec<u8>, options: &StorageOptions) -> Result<Option<PathBuf>> {
+        storage::verify(hash, &raw)?;
+
+        // An object stored again is freshened, so gc does not delete it as unreachable.
+        let existing = conn.query(
+            "SELECT created < now() - $2::TEXT::INTERVAL FROM objects WHERE hash = $1",
+            &[hash, &FRESHEN_AGE]
+        ).map_err(SimpleError::from)?;
+        if !existing.is_empty() {
+            let stale: bool = existing.get(0).get(0);
+            if stale {
+                conn.execute("UPDATE objects SET created = now() WHERE hash = $1", &[hash]).map_err(SimpleError::from)?;
+            }
+            return Ok(None);
+        }
+
+        let (kind, size) = storage::read_header(storage::ENCODING_RAW, &raw)?;
+        let header = GitSqlClient::encode_object(&kind, size, &[]);
+
+        match options.offload {
+            Offload::LargeObject => {
+                let transact = conn.transaction().map_err(SimpleError::from)?;
+                let created = transact.query("SELECT lo_create(0)", &[]).map_err(SimpleError::from)?;
+                let oid: u32 = created.get(0).get(0);
+
+                for (index, chunk) in raw.chunks(LARGE_OBJECT_CHUNK).enumerate() {
+                    let offset = (index * LARGE_OBJECT_CHUNK) as i64;
+                    transact.execute("SELECT lo_put($1, $2, $3)", &[&oid, &offset, &chunk])
+                        .map_err(SimpleError::from)?;
+                }
+
+                let inserted = transact.execute(
+                    "INSERT INTO objects (hash, content, encoding, type, size, large_object) \
+                     VALUES ($1, $2, $3, $4::TEXT::objtype, $5, $6) ON CONFLICT DO NOTHING",
+                    &[hash, &header, &storage::ENCODING_LARGE_OBJECT, &kind.str(), &(size as i64), &oid]
+                ).map_err(SimpleError::from)?;
+
+                if inserted == 0 {
+                    transact.execute("SELECT lo_unlink($1)", &[&oid]).map_err(SimpleError::from)?;
+                }
+                transact.commit().map_err(SimpleError::from)?;
+                Ok(None)
+            },
+            Offload::Directory(_) => {
+                let path = options.offload_path(hash)?;
+                let location = path.to_str()
+                    .ok_or_else(|| SimpleError::new(format!("Invalid offload path {}.", path.display())))?
+                    .to_string();
+
+                let written = !path.exists();
+                if written {
+                    // Written under a temporary name first, so a partial file is never taken for the object.
+                    let partial = path.with_file_name(format!("{}.partial", &hash[2..]));
+                    fs::create_dir_all(path.parent().unwrap()).map_err(SimpleError::from)?;
+                    fs::write(&partial, &raw).map_err(SimpleError::from)?;
+                    fs::rename(&partial, &path).map_err(SimpleError::from)?;
+                }
+
+                let result = conn.execute(
+                    "INSERT INTO objects (hash, content, encoding, type, size, path) \
+                     VALUES ($1, $2, $3, $4::TEXT::objtype, $5, $6) ON CONFLICT DO NOTHING",
+                    &[hash, &header, &storage::ENCODING_FILE, &kind.str(), &(size as i64), &location]
+                );
+
+                if result.is_err() {
+                    if written {
+                        let _ = fs::remove_file(&path);
+                    }
+                    return Err(SimpleError::from(result.err().unwrap()));
+                }
+                Ok(if written { Some(path) } else { None })
+            }
+        }
+    }
+
+    fn remove_offloaded_files(&self, files: &[PathBuf]) {
+        let paths: Vec<String> = files.iter().filter_map(|x| x.to_str()).map(|x| x.to_string()).collect();
+        let kept: HashSet<String> = match self.conn.query("SELECT path FROM objects WHERE path = ANY($1)", &[&Array::from_vec(paths, 0)]) {
+            Ok(rows) => rows.iter().map(|row| row.get(0)).collect(),
+            Err(_) => return
+        };
+
+        for file in files {
+            if !file.to_str().is_some_and(|x| kept.contains(x)) {
+                let _ = fs::remove_file(file);
+            }
+        }
+    }
+
+    pub fn insert_object_verify(&self, kind: &ObjectType, size: usize, data: &[u8], expected: &String) -> Result<()> {
+        let encoded = GitSqlClient::encode_object(kind, size, data);
+        let mut sha = sha1::Sha1::new();
+        sha.update(encoded.as_slice());
+        let hash = &sha.digest().to_string();
+
+        if hash != expected {
+            let mut msg = String::new();
+            write!(&mut msg, "Expected hash to be {}, but encoded the object into a hash of {}", expected, hash).unwrap();
+            return Err(SimpleError::new(msg));
+        }
+
+        GitSqlClient::insert_raw_object(&self.conn, hash, encoded, &self.storage_options)
+    }
+
+    pub fn read_delta_info(&self, hashes: &[String]) -> Result<Vec<(String, usize, usize, bool)>> {
+        let result = self.conn.query(
+            "SELECT o.hash, o.depth, octet_length(o.content), \
+             EXISTS (SELECT 1 FROM objects d WHERE d.base = o.hash) \
+             FROM objects o WHERE o.hash = ANY($1) AND o.encoding NOT IN ('largeobject', 'file')",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut info = Vec::new();
+        for row in &result.unwrap() {
+            let hash: String = row.get(0);
+            let depth: i32 = row.get(1);
+            let size: i32 = row.get(2);
+            info.push((hash, depth as usize, size as usize, row.get(3)));
+        }
+        Ok(info)
+    }
+
+    pub fn store_delta(&self, hash: &String, base: &String, depth: usize, delta: &Vec<u8>) -> Result<bool> {
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+
+        // Serializes repacks, so that two objects cannot become deltas against each other.
+        let mut result = transact.execute("SELECT pg_advisory_xact_lock($1, 0)", &[&REPACK_LOCK]);
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        result = transact.execute(
+            "UPDATE objects SET encoding = 'delta', base = $2, depth = $3, content = $4 \
+             WHERE hash = $1 AND encoding IN ('raw', 'zlib') \
+             AND NOT EXISTS (SELECT 1 FROM objects d WHERE d.base = $1) \
+             AND EXISTS (SELECT 1 FROM objects b WHERE b.hash = $2 AND b.depth = $3 - 1 AND b.encoding IN ('raw', 'zlib', 'delta'))",
+            &[hash, base, &(depth as i32), delta]
+        );
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let stored = result.unwrap() > 0;
+        transact.commit().map_err(SimpleError::from)?;
+        Ok(stored)
+    }
+
+    pub fn has_object(&self, hash: &String) -> Result<bool> {
+        let result = self.conn.query("SELECT 1 FROM objects WHERE hash = $1", &[hash]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(!result.unwrap().is_empty())
+    }
+
+    pub fn filter_missing(&self, hashes: &[String]) -> Result<Vec<String>> {
+        let result = self.conn.query(
+            "SELECT h FROM unnest($1::TEXT[]) h WHERE NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = h)",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(result.unwrap().iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn is_closure_complete(&self, hashes: &[String], commits: &[String]) -> Result<bool> {
+        // Tags and trees are followed from hashes, while commits only add their own tree and parents.
+        let result = self.conn.query(
+            "WITH RECURSIVE closure(hash) AS (
+                SELECT h FROM unnest($1::TEXT[]) h
+              UNION
+                SELECT m.tree FROM commit_meta m WHERE m.hash = ANY($2)
+              UNION
+                SELECT p.parent FROM commit_parents p WHERE p.hash = ANY($2)
+              UNION
+                SELECT x.child FROM closure c CROSS JOIN LATERAL (
+                    SELECT g.object COLLATE \"default\" FROM tags g WHERE g.hash = c.hash
+                  UNION ALL
+                    SELECT e.child FROM tree_entries e WHERE e.tree = c.hash AND e.mode <> '160000'
+                ) x(child)
+            )
+            SELECT NOT EXISTS (SELECT 1 FROM closure c WHERE NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = c.hash))",
+            &[&Array::from_vec(hashes.to_vec(), 0), &Array::from_vec(commits.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(result.unwrap().get(0).get(0))
+    }
+
+    pub fn filter_reachable(&self, hashes: &[String]) -> Result<HashSet<String>> {
+        let hash_array = &Array::from_vec(hashes.to_vec(), 0);
+        let result = self.conn.query("SELECT git_filter_reachable($1, $2)", &[&self.repository_id()?, hash_array]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(result.unwrap().iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn read_objects(&self, hashes: &[String]) -> Result<Vec<(String, ObjectType, Vec<u8>)>> {
+        let mut objects = Vec::new();
+        for (hash, raw) in self.read_raw_objects(hashes)? {
+            let (kind, data) = storage::split(raw)?;
+            objects.push((hash, kind, data));
+        }
+
+        Ok(objects)
+    }
+
+    pub fn read_object_types(&self, hashes: &[String]) -> Result<HashMap<String, ObjectType>> {
+        let result = self.conn.query(
+            "SELECT hash, type::TEXT FROM objects WHERE hash = ANY($1)",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut kinds = HashMap::new();
+        for row in &result.unwrap() {
+            let kind: String = row.get(1);
+            kinds.insert(row.get(0), ObjectType::from_str(&kind).unwrap());
+        }
+        Ok(kinds)
+    }
+
+    pub fn read_tree_entries(&self, hash: &String) -> Result<Vec<TreeEntry>> {
+        let result = self.conn.query(
+            "SELECT name, mode, child FROM tree_entries WHERE tree = $1 ORDER BY position",
+            &[hash]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        if rows.is_empty() {
+            // Empty trees have no entries, and missing trees must still fail to read.
+            let (_, data) = self.read_object(hash)?;
+            return object::parse_tree(&data);
+        }
+
+        let mut entries = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let mode: String = row.get(1);
+            entries.push(TreeEntry {
+                name: row.get(0),
+                mode: u32::from_str_radix(&mode, 8).map_err(|_| SimpleError::new("Malformed tree entry mode."))?,
+                hash: row.get(2)
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn read_object_sizes(&self, hashes: &[String]) -> Result<Vec<(String, ObjectType, usize)>> {
+        let hash_array = &Array::from_vec(hashes.to_vec(), 0);
+        let result = self.conn.query(
+            "SELECT hash, type::TEXT, size FROM objects WHERE hash = ANY($1)",
+            &[hash_array]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let rows = result.unwrap();
+        if rows.len() != hashes.len() {
+            return Err(SimpleError::new("Unknown Object."));
+        }
+
+        let mut sizes = Vec::new();
+        for row in &rows {
+            let hash: String = row.get(0);
+            let kind: String = row.get(1);
+            let size: i64 = row.get(2);
+            sizes.push((hash, ObjectType::from_str(&kind).unwrap(), size as usize));
+        }
+
+        Ok(sizes)
+    }
+
+    pub fn write_pack(&self, objects: &[(String, String)], options: &PackOptions, out: &mut dyn io::Write) -> Result<(String, Vec<IndexEntry>)> {
+        let paths: HashMap<&String, &String> = objects.iter().map(|x| (&x.0, &x.1)).collect();
+        let hashes: Vec<String> = objects.iter().map(|x| x.0.clone()).collect();
+
+        let mut entries = Vec::with_capacity(hashes.len());
+        for chunk in hashes.chunks(PACK_READ_CHUNK) {
+            for (hash, kind, size) in self.read_object_sizes(chunk)? {
+                let path = paths[&hash].clone();
+                entries.push(PackEntry { hash, kind, size, path });
+            }
+        }
+        builder::sort_for_deltas(&mut entries);
+
+        let mut writer = DeltaPackWriter::new(out, entries.len() as u32, *options)?;
+        for chunk in entries.chunks(PACK_READ_CHUNK) {
+            let ordered: Vec<String> = chunk.iter().map(|x| x.hash.clone()).collect();
+            let offloaded = self.filter_offloaded(&ordered)?;
+            let stored: Vec<String> = ordered.iter().filter(|x| !offloaded.contains(*x)).cloned().collect();
+            let mut loaded: HashMap<String, (ObjectType, Vec<u8>)> = self.read_objects(&stored)?
+                .into_iter()
+                .map(|(hash, kind, data)| (hash, (kind, data)))
+                .collect();
+
+            for hash in &ordered {
+                if offloaded.contains(hash) {
+                    let mut stream = self.open_object(hash)?;
+                    let (kind, size) = (stream.kind(), stream.size());
+                    writer.add_from(hash, kind, size, &mut stream)?;
+                    continue;
+                }
+
+                let (kind, data) = loaded.remove(hash).unwrap();
+                writer.add(hash, kind, data)?;
+            }
+        }
+
+        writer.finish()
+    }
+
+    pub fn pack_options(&self) -> PackOptions {
+        self.pack_options
+    }
+
+    pub fn set_pack_options(&mut self, options: PackOptions) {
+        self.pack_options = options;
+    }
+
+    pub fn storage_options(&self) -> StorageOptions {
+        self.storage_options.clone()
+    }
+
+    pub fn set_storage_options(&mut self, options: StorageOptions) {
+        self.storage_options = options;
+    }
+
+    pub fn set_actor(&mut self, actor: Option<String>) {
+        self.actor = actor;
+    }
+
+    pub fn insert_objects(&self, objects: &[PackObject]) -> Result<()> {
+        let mut written = Vec::new();
+        let result = self.insert_objects_offloading(objects, &mut written);
+        if result.is_err() && !written.is_empty() {
+            self.remove_offloaded_files(&written);
+        }
+        result
+    }
+
+    fn insert_objects_offloading(&self, objects: &[PackObject], written: &mut Vec<PathBuf>) -> Result<()> {
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        let mut inserted = Vec::new();
+
+        for chunk in objects.chunks(INSERT_CHUNK) {
+            let mut hashes = Vec::with_capacity(chunk.len());
+            let mut contents = Vec::with_capacity(chunk.len());
+            let mut encodings = Vec::with_capacity(chunk.len());
+            let mut kinds = Vec::with_capacity(chunk.len());
+            let mut sizes = Vec::with_capacity(chunk.len());
+            let mut commits = Vec::new();
+            let mut trees = Vec::new();
+            for object in chunk {
+                match object.kind {
+                    ObjectType::Commit => commits.push((object.hash.clone(), object::parse_commit(&object.data)?)),
+                    ObjectType::Tree => trees.push((object.hash.clone(), object::parse_tree(&object.data)?)),
+                    _ => {}
+                }
+
+                let encoded = GitSqlClient::encode_object(&object.kind, object.data.len(), &object.data);
+                if self.storage_options.should_offload(encoded.len()) {
+                    written.extend(GitSqlClient::offload_object(&transact, &object.hash, encoded, &self.storage_options)?);
+                    continue;
+                }
+
+                let (encoding, stored) = storage::encode(&object.hash, encoded, &self.storage_options)?;
+                hashes.push(object.hash.clone());
+                contents.push(stored);
+                encodings.push(encoding);
+                kinds.push(object.kind.str().to_string());
+                sizes.push(object.data.len() as i64);
+            }
+
+            let result = transact.execute(
+                "INSERT INTO objects (hash, content, encoding, type, size) \
+                 SELECT DISTINCT ON (hash) hash, content, encoding, type::objtype, size \
+                 FROM unnest($1::TEXT[], $2::BYTEA[], $3::TEXT[], $4::TEXT[], $5::BIGINT[]) \
+                 AS new (hash, content, encoding, type, size) \
+                 ON CONFLICT (hash) DO UPDATE SET created = now() WHERE objects.created < now() - $6::TEXT::INTERVAL",
+                &[
+                    &Array::from_vec(hashes, 0),
+                    &Array::from_vec(contents, 0),
+                    &Array::from_vec(encodings, 0),
+                    &Array::from_vec(kinds, 0),
+                    &Array::from_vec(sizes, 0),
+                    &FRESHEN_AGE
+                ]
+            );
+
+            if result.is_err() {
+                return Err(SimpleError::from(result.err().unwrap()));
+            }
+            GitSqlClient::insert_commits(&transact, &commits)?;
+            GitSqlClient::insert_tree_entries(&transact, &trees)?;
+            inserted.extend(commits.into_iter().map(|x| x.0));
+        }
+
+        GitSqlClient::update_commit_generations(&transact, Some(&inserted))?;
+        transact.commit().map_err(SimpleError::from)
+    }
+
+    fn insert_commits(conn: &dyn GenericConnection, commits: &[(String, Commit)]) -> Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+
+        let mut hashes = Vec::with_capacity(commits.len());
+        let mut trees = Vec::with_capacity(commits.len());
+        let mut authors = Vec::with_capacity(commits.len());
+        let mut committers = Vec::with_capacity(commits.len());
+        let mut author_times = Vec::with_capacity(commits.len());
+        let mut commit_times = Vec::with_capacity(commits.len());
+        let mut messages = Vec::with_capacity(commits.len());
+        let mut signatures = Vec::with_capacity(commits.len());
+        let mut parent_hashes = Vec::new();
+        let mut parent_positions = Vec::new();
+        let mut parents = Vec::new();
+
+        for (hash, commit) in commits {
+            hashes.push(hash.clone());
+            trees.push(commit.tree.clone());
+            authors.push(commit.author.as_ref().map(|x| x.identity.clone()));
+            committers.push(commit.committer.as_ref().map(|x| x.identity.clone()));
+            author_times.push(commit.author.as_ref().and_then(|x| x.time));
+            commit_times.push(commit.committer.as_ref().and_then(|x| x.time));
+            messages.push(commit.message.clone());
+            signatures.push(commit.signature.clone());
+
+            for (position, parent) in commit.parents.iter().enumerate() {
+                parent_hashes.push(hash.clone());
+                parent_positions.push(position as i32);
+                parents.push(parent.clone());
+            }
+        }
+
+        let result = conn.execute(
+            "INSERT INTO commit_meta (hash, tree, author, committer, author_time, commit_time, message, pgp) \
+             SELECT hash, tree, author, committer, to_timestamp(author_time), to_timestamp(commit_time), message, pgp \
+             FROM unnest($1::TEXT[], $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::BIGINT[], $6::BIGINT[], $7::TEXT[], $8::TEXT[]) \
+             AS new (hash, tree, author, committer, author_time, commit_time, message, pgp) ON CONFLICT DO NOTHING",
+            &[
+                &Array::from_vec(hashes, 0),
+                &Array::from_vec(trees, 0),
+                &Array::from_vec(authors, 0),
+                &Array::from_vec(committers, 0),
+                &Array::from_vec(author_times, 0),
+                &Array::from_vec(commit_times, 0),
+                &Array::from_vec(messages, 0),
+                &Array::from_vec(signatures, 0)
+            ]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let result = conn.execute(
+            "INSERT INTO commit_parents (hash, position, parent) \
+             SELECT * FROM unnest($1::TEXT[], $2::INTEGER[], $3::TEXT[]) ON CONFLICT DO NOTHING",
+            &[&Array::from_vec(parent_hashes, 0), &Array::from_vec(parent_positions, 0), &Array::from_vec(parents, 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(())
+    }
+
+    fn insert_tree_entries(conn: &dyn GenericConnection, trees: &[(String, Vec<TreeEntry>)]) -> Result<()> {
+        let mut hashes = Vec::new();
+        let mut names = Vec::new();
+        let mut modes = Vec::new();
+        let mut children = Vec::new();
+        let mut positions = Vec::new();
+        for (hash, entries) in trees {
+            for (position, entry) in entries.iter().enumerate() {
+                hashes.push(hash.clone());
+                names.push(entry.name.clone());
+                modes.push(format!("{:o}", entry.mode));
+                children.push(entry.hash.clone());
+                positions.push(position as i32);
+            }
+        }
+
+        if hashes.is_empty() {
+            return Ok(());
+        }
+
+        let result = conn.execute(
+            "INSERT INTO tree_entries (tree, name, mode, child, position) \
+             SELECT * FROM unnest($1::TEXT[], $2::BYTEA[], $3::TEXT[], $4::TEXT[], $5::INTEGER[]) ON CONFLICT DO NOTHING",
+            &[
+                &Array::from_vec(hashes, 0),
+                &Array::from_vec(names, 0),
+                &Array::from_vec(modes, 0),
+                &Array::from_vec(children, 0),
+                &Array::from_vec(positions, 0)
+            ]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(())
+    }
+
+    fn update_commit_generations(conn: &dyn GenericConnection, only: Option<&[String]>) -> Result<usize> {
+        let only = only.map(|x| Array::from_vec(x.to_vec(), 0));
+        let result = conn.query(
+            "SELECT m.hash, p.parent, g.hash IS NOT NULL, g.generation FROM commit_meta m \
+             LEFT JOIN commit_parents p ON (p.hash = m.hash) \
+             LEFT JOIN commit_meta g ON (g.hash = p.parent) \
+             WHERE m.generation IS NULL AND ($1::TEXT[] IS NULL OR m.hash = ANY($1))",
+            &[&only]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        // Parents that are pending themselves are resolved first. Any other parent either has a
+        // generation or is missing or left out, which leaves the generations of its descendants unknown.
+        let mut pending: HashMap<String, Vec<String>> = HashMap::new();
+        let mut known: HashMap<String, Option<i32>> = HashMap::new();
+        for row in &result.unwrap() {
+            let hash: String = row.get(0);
+            let parent: Option<String> = row.get(1);
+            let indexed: bool = row.get(2);
+            let generation: Option<i32> = row.get(3);
+
+            let parents = pending.entry(hash).or_default();
+            if let Some(parent) = parent {
+                if !indexed {
+                    known.insert(parent.clone(), None);
+                } else if generation.is_some() {
+                    known.insert(parent.clone(), generation);
+                }
+                parents.push(parent);
+            }
+        }
+
+        let unresolved: Vec<String> = pending.values().flatten()
+            .filter(|x| !pending.contains_key(*x) && !known.contains_key(*x))
+            .cloned()
+            .collect();
+        for parent in unresolved {
+            known.insert(parent, None);
+        }
+
+        let mut computed: Vec<(String, i32)> = Vec::new();
+        let mut visited: HashSet<String> = HashSet::new();
+        for start in pending.keys() {
+            let mut stack = vec![(start.clone(), false)];
+            while let Some((hash, expanded)) = stack.pop() {
+                if known.contains_key(&hash) {
+                    continue;
+                }
+
+                if !expanded {
+                    if !visited.insert(hash.clone()) {
+                        continue;
+                    }
+                    stack.push((hash.clone(), true));
+                    for parent in &pending[&hash] {
+                        if !known.contains_key(parent) {
+                            stack.push((parent.clone(), false));
+                        }
+                    }
+                    continue;
+                }
+
+                let mut generation = Some(1);
+                for parent in &pending[&hash] {
+                    generation = match (generation, known[parent]) {
+                        (Some(current), Some(parent)) => Some(current.max(parent + 1)),
+                        _ => None
+                    };
+                }
+
+                if let Some(generation) = generation {
+                    computed.push((hash.clone(), generation));
+                }
+                known.insert(hash, generation);
+            }
+        }
+
+        let count = computed.len();
+        for chunk in computed.chunks(INSERT_CHUNK) {
+            let hashes: Vec<String> = chunk.iter().map(|x| x.0.clone()).collect();
+            let generations: Vec<i32> = chunk.iter().map(|x| x.1).collect();
+            let result = conn.execute(
+                "UPDATE commit_meta SET generation = new.generation \
+                 FROM unnest($1::TEXT[], $2::INTEGER[]) AS new (hash, generation) \
+                 WHERE commit_meta.hash = new.hash",
+                &[&Array::from_vec(hashes, 0), &Array::from_vec(generations, 0)]
+            );
+
+            if result.is_err() {
+                return Err(SimpleError::from(result.err().unwrap()));
+            }
+        }
+
+        Ok(count)
+    }
+
+    pub fn update_commit_graph(&self) -> Result<usize> {
+        let result = self.conn.query(
+            "SELECT o.hash FROM objects o WHERE o.type = 'commit' \
+             AND NOT EXISTS (SELECT 1 FROM commit_meta m WHERE m.hash = o.hash)",
+            &[]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let hashes: Vec<String> = result.unwrap().iter().map(|row| row.get(0)).collect();
+        for chunk in hashes.chunks(PACK_READ_CHUNK) {
+            let mut commits = Vec::with_capacity(chunk.len());
+            for (hash, _, data) in self.read_objects(chunk)? {
+                commits.push((hash, object::parse_commit(&data)?));
+            }
+            GitSqlClient::insert_commits(&self.conn, &commits)?;
+        }
+
+        GitSqlClient::update_commit_generations(&self.conn, None)?;
+        Ok(hashes.len())
+    }
+
+    pub fn update_tree_entries(&self) -> Result<usize> {
+        let result = self.conn.query(
+            "SELECT o.hash FROM objects o WHERE o.type = 'tree' AND o.size > 0 \
+             AND NOT EXISTS (SELECT 1 FROM tree_entries e WHERE e.tree = o.hash)",
+            &[]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let hashes: Vec<String> = result.unwrap().iter().map(|row| row.get(0)).collect();
+        for chunk in hashes.chunks(PACK_READ_CHUNK) {
+            let mut trees = Vec::with_capacity(chunk.len());
+            for (hash, _, data) in self.read_objects(chunk)? {
+                trees.push((hash, object::parse_tree(&data)?));
+            }
+            GitSqlClient::insert_tree_entries(&self.conn, &trees)?;
+        }
+
+        Ok(hashes.len())
+    }
+
+    pub fn update_offload_paths(&self) -> Result<usize> {
+        if let Offload::LargeObject = self.storage_options.offload {
+            return Ok(0);
+        }
+
+        let result = self.conn.query("SELECT hash FROM objects WHERE encoding = 'file' AND path IS NULL", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut hashes = Vec::new();
+        let mut paths = Vec::new();
+        for row in &result.unwrap() {
+            let hash: String = row.get(0);
+            let path = self.storage_options.offload_path(&hash)?;
+            if let (true, Some(path)) = (path.is_file(), path.to_str()) {
+                paths.push(path.to_string());
+                hashes.push(hash);
+            }
+        }
+
+        let result = self.conn.execute(
+            "UPDATE objects o SET path = new.path FROM unnest($1::TEXT[], $2::TEXT[]) AS new (hash, path) \
+             WHERE o.hash = new.hash AND o.path IS NULL",
+            &[&Array::from_vec(hashes, 0), &Array::from_vec(paths, 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+        Ok(result.unwrap() as usize)
+    }
+
+    pub fn list_objects(&self) -> Result<Vec<(String, String, usize)>> {
+        let result = self.conn.query("SELECT hash, encoding, size FROM objects ORDER BY hash", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut objects = Vec::new();
+        for row in &result.unwrap() {
+            let size: i64 = row.get(2);
+            objects.push((row.get(0), row.get(1), size as usize));
+        }
+        Ok(objects)
+    }
+
+    pub fn check_connectivity(&self) -> Result<Vec<(String, String, String, String)>> {
+        let result = self.conn.query(
+            "SELECT 'missing-tree', m.hash, m.tree, '' FROM commit_meta m \
+             WHERE NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = m.tree) \
+             UNION ALL \
+             SELECT 'wrong-type', m.hash, m.tree, 'commit tree is a ' || o.type FROM commit_meta m \
+             JOIN objects o ON (o.hash = m.tree) WHERE o.type != 'tree' \
+             UNION ALL \
+             SELECT 'missing-parent', p.hash, p.parent, '' FROM commit_parents p \
+             WHERE NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = p.parent) \
+             AND NOT EXISTS (SELECT 1 FROM shallow s WHERE s.hash = p.hash) \
+             UNION ALL \
+             SELECT 'wrong-type', p.hash, p.parent, 'commit parent is a ' || o.type FROM commit_parents p \
+             JOIN objects o ON (o.hash = p.parent) WHERE o.type != 'commit' \
+             UNION ALL \
+             SELECT 'missing-entry', e.tree, e.child, git_tree_entry_name(e.name) FROM tree_entries e \
+             WHERE e.mode != '160000' AND NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = e.child) \
+             UNION ALL \
+             SELECT 'wrong-type', e.tree, e.child, git_tree_entry_name(e.name) || ' has mode ' || e.mode || ' but is a ' || o.type \
+             FROM tree_entries e JOIN objects o ON (o.hash = e.child) \
+             WHERE o.type::TEXT != CASE e.mode WHEN '40000' THEN 'tree' WHEN '160000' THEN 'commit' ELSE 'blob' END \
+             UNION ALL \
+             SELECT 'missing-ref-target', r.name, r.target, p.name FROM refs r \
+             JOIN repositories p ON (p.id = r.repository) \
+             WHERE r.target NOT LIKE '%/%' AND NOT EXISTS (SELECT 1 FROM objects o WHERE o.hash = r.target) \
+             UNION ALL \
+             SELECT 'unindexed-commit', o.hash, '', '' FROM objects o \
+             WHERE o.type = 'commit' AND NOT EXISTS (SELECT 1 FROM commit_meta m WHERE m.hash = o.hash) \
+             UNION ALL \
+             SELECT 'unindexed-tree', o.hash, '', '' FROM objects o \
+             WHERE o.type = 'tree' AND o.size > 0 AND NOT EXISTS (SELECT 1 FROM tree_entries e WHERE e.tree = o.hash)",
+            &[]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let problems = result.unwrap().iter().map(|row| (row.get(0), row.get(1), row.get(2), row.get(3))).collect();
+        Ok(problems)
+    }
+
+    pub fn list_tag_objects(&self) -> Result<Vec<String>> {
+        let result = self.conn.query("SELECT hash FROM objects WHERE type = 'tag' ORDER BY hash", &[]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(result.unwrap().iter().map(|row| row.get(0)).collect())
+    }
+
+    pub fn add_shallow(&self, hashes: &[String]) -> Result<usize> {
+        let result = self.conn.execute(
+            "INSERT INTO shallow (hash) SELECT unnest($1::TEXT[]) ON CONFLICT DO NOTHING",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+        Ok(result.unwrap() as usize)
+    }
+
+    pub fn find_unreachable_objects(&self, prune: Option<&String>) -> Result<Vec<UnreachableObject>> {
+        let result = self.conn.query(
+            "WITH RECURSIVE reachable(hash) AS ( \
+                 SELECT git_reachable_objects() \
+             ), kept(hash) AS ( \
+                 SELECT hash FROM reachable \
+               UNION \
+                 SELECT hash FROM objects WHERE $1::TEXT::INTERVAL IS NULL OR created >= now() - $1::TEXT::INTERVAL \
+               UNION \
+                 SELECT o.base FROM kept k JOIN objects o ON (o.hash = k.hash) WHERE o.base IS NOT NULL \
+             ) \
+             SELECT o.hash, o.depth, o.encoding, \
+             octet_length(o.content)::BIGINT + CASE WHEN o.encoding IN ('largeobject', 'file') THEN o.size ELSE 0 END, \
+             NOT EXISTS (SELECT 1 FROM kept k WHERE k.hash = o.hash), o.path \
+             FROM objects o WHERE NOT EXISTS (SELECT 1 FROM reachable r WHERE r.hash = o.hash) \
+             ORDER BY o.depth DESC, o.hash",
+            &[&prune]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut objects = Vec::new();
+        for row in &result.unwrap() {
+            let depth: i32 = row.get(1);
+            let bytes: i64 = row.get(3);
+            objects.push(UnreachableObject {
+                hash: row.get(0),
+                depth: depth as usize,
+                encoding: row.get(2),
+                path: row.get(5),
+                bytes: bytes as usize,
+                expired: row.get(4)
+            });
+        }
+        Ok(objects)
+    }
+
+    pub fn delete_unreachable_objects(&self, hashes: &[String], prune: &String) -> Result<Vec<(String, String, Option<String>)>> {
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        let result = transact.query(
+            "DELETE FROM objects o WHERE o.hash = ANY($1) AND o.created < now() - $2::TEXT::INTERVAL \
+             AND NOT EXISTS (SELECT 1 FROM objects d WHERE d.base = o.hash) \
+             RETURNING o.hash, o.encoding, o.path",
+            &[&Array::from_vec(hashes.to_vec(), 0), prune]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let deleted = result.unwrap().iter().map(|row| (row.get(0), row.get(1), row.get(2))).collect();
+        transact.commit().map_err(SimpleError::from)?;
+        Ok(deleted)
+    }
+
+    pub fn clear_cached_packs(&self, hashes: &[String]) -> Result<usize> {
+        let result = self.conn.execute(
+            "DELETE FROM packs p WHERE EXISTS ( \
+                 SELECT 1 FROM pack_objects o \
+                 WHERE o.repository = p.repository AND o.pack = p.name AND o.hash = ANY($1) \
+             )",
+            &[&Array::from_vec(hashes.to_vec(), 0)]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+        Ok(result.unwrap() as usize)
+    }
+
+    pub fn import_pack(&self, input: &mut dyn io::Read) -> Result<usize> {
+        let mut batch: Vec<PackObject> = Vec::new();
+        let mut batch_bytes = 0;
+        let mut batches = 0;
+        let mut commits = Vec::new();
+
+        let count = PackReader::new(input)?.read_objects(
+            |hash| {
+                if !self.has_object(hash)? {
+                    return Ok(None);
+                }
+                self.read_object(hash).map(Some)
+            },
+            |object| {
+                if object.kind == ObjectType::Commit {
+                    commits.push(object.hash.clone());
+                }
+                batch_bytes += object.data.len();
+                batch.push(object);
+
+                if batch.len() >= INSERT_CHUNK || batch_bytes >= IMPORT_BATCH_BYTES {
+                    self.insert_objects(&batch)?;
+                    batch.clear();
+                    batch_bytes = 0;
+                    batches += 1;
+                }
+                Ok(())
+            }
+        )?;
+
+        if !batch.is_empty() {
+            self.insert_objects(&batch)?;
+            batches += 1;
+        }
+
+        // Commits are resolved in no particular order, so a batch can hold commits whose parents
+        // only came in a later one.
+        if batches > 1 {
+            GitSqlClient::update_commit_generations(&self.conn, Some(&commits))?;
+        }
+        Ok(count)
     }
 
     pub fn has_ref(&self, name: &String) -> Result<bool> {
-        let result = self.conn.query("SELECT COUNT(*) FROM refs WHERE name = $1", &[
+        let result = self.conn.query("SELECT COUNT(*) FROM refs WHERE repository = $1 AND name = $2", &[
+            &self.repository_id()?,
             name
         ]);
 
@@ -398,7 +1794,8 @@ impl GitSqlClient {
     }
 
     pub fn read_ref(&self, name: &String) -> Result<String> {
-        let result = self.conn.query("SELECT target FROM refs WHERE name = $1", &[
+        let result = self.conn.query("SELECT target FROM refs WHERE repository = $1 AND name = $2", &[
+            &self.repository_id()?,
             name
         ]);
 
@@ -413,26 +1810,192 @@ impl GitSqlClient {
         return Err(SimpleError::new("Reference not found."));
     }
 
-    pub fn set_ref(&self, name: &String, target: &String) -> Result<bool> {
-        let mut result;
+    pub fn set_ref(&self, name: &String, target: &String, message: &str) -> Result<bool> {
+        let repository = self.repository_id()?;
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        self.set_reflog_context(&transact, message)?;
+
+        let result = transact.execute(
+            "INSERT INTO refs (repository, name, target) VALUES ($1, $2, $3) \
+             ON CONFLICT (repository, name) DO UPDATE SET target = $3 WHERE refs.target != $3",
+            &[&repository, name, target]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        if result.unwrap() == 0 {
+            return Ok(false);
+        }
+
+        let result = transact.execute("SELECT pg_notify('git_ref_update', $1)", &[&self.notify_payload(name)]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        transact.commit().map_err(SimpleError::from)?;
+        return Ok(true);
+    }
+
+    pub fn compare_and_set_ref(&self, name: &String, old: Option<&String>, new: Option<&String>, message: &str) -> Result<bool> {
+        let repository = self.repository_id()?;
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        self.set_reflog_context(&transact, message)?;
+
+        let result = match (old, new) {
+            (Some(old), Some(new)) => transact.execute(
+                "UPDATE refs SET target = $4 WHERE repository = $1 AND name = $2 AND target = $3",
+                &[&repository, name, old, new]
+            ),
+            (None, Some(new)) => transact.execute(
+                "INSERT INTO refs (repository, name, target) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                &[&repository, name, new]
+            ),
+            (Some(old), None) => transact.execute(
+                "DELETE FROM refs WHERE repository = $1 AND name = $2 AND target = $3",
+                &[&repository, name, old]
+            ),
+            (None, None) => transact.execute(
+                "SELECT 1 WHERE NOT EXISTS (SELECT 1 FROM refs WHERE repository = $1 AND name = $2)",
+                &[&repository, name]
+            )
+        };
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        if result.unwrap() != 1 {
+            return Ok(false);
+        }
+
+        self.write_peeled_ref(&transact, repository, name, new)?;
+
+        let result = transact.execute("SELECT pg_notify('git_ref_update', $1)", &[&self.notify_payload(name)]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        transact.commit().map_err(SimpleError::from)?;
+        Ok(true)
+    }
+
+    fn write_peeled_ref(&self, transact: &Transaction, repository: i32, name: &String, target: Option<&String>) -> Result<()> {
+        let peeled_name = format!("{}^{{}}", name);
+        let peeled = match target {
+            Some(target) => {
+                let result = transact.query("SELECT git_peel($1)", &[target]);
+
+                if result.is_err() {
+                    return Err(SimpleError::from(result.err().unwrap()));
+                }
+
+                let peeled: Option<String> = result.unwrap().get(0).get(0);
+                peeled.filter(|x| x != target)
+            },
+            None => None
+        };
+
+        let result = match peeled {
+            Some(peeled) => transact.execute(
+                "INSERT INTO refs (repository, name, target) VALUES ($1, $2, $3) \
+                 ON CONFLICT (repository, name) DO UPDATE SET target = $3 WHERE refs.target != $3",
+                &[&repository, &peeled_name, &peeled]
+            ),
+            None => transact.execute("DELETE FROM refs WHERE repository = $1 AND name = $2", &[&repository, &peeled_name])
+        };
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(())
+    }
+
+    fn set_reflog_context(&self, transact: &Transaction, message: &str) -> Result<()> {
+        let actor = self.actor.clone().unwrap_or_default();
+        let result = transact.execute(
+            "SELECT set_config('gitsql.actor', $1, true), set_config('gitsql.reflog_message', $2, true)",
+            &[&actor, &message]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        Ok(())
+    }
+
+    pub fn resolve_ref_name(&self, name: &String) -> Result<Option<(String, String)>> {
+        let refs = self.list_refs()?;
+        let candidates = vec![
+            name.clone(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name)
+        ];
+
+        for candidate in candidates {
+            if let Some(found) = refs.iter().find(|x| x.0 == candidate) {
+                return Ok(Some(found.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn set_ref_peeled(&self, name: &String, target: &String, message: &str) -> Result<bool> {
+        let repository = self.repository_id()?;
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        self.set_reflog_context(&transact, message)?;
+
+        let result = transact.execute(
+            "INSERT INTO refs (repository, name, target) VALUES ($1, $2, $3) \
+             ON CONFLICT (repository, name) DO UPDATE SET target = $3 WHERE refs.target != $3",
+            &[&repository, name, target]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let changed = result.unwrap() > 0;
+        self.write_peeled_ref(&transact, repository, name, Some(target))?;
+
+        if changed {
+            let result = transact.execute("SELECT pg_notify('git_ref_update', $1)", &[&self.notify_payload(name)]);
 
-        if self.has_ref(name)? {
-            if self.read_ref(name)? == *target {
-                return Ok(false);
+            if result.is_err() {
+                return Err(SimpleError::from(result.err().unwrap()));
             }
+        }
 
-            result = self.conn.execute("UPDATE refs SET target = $2 WHERE name = $1", &[
-                name,
-                target
-            ]);
-        } else {
-            result = self.conn.execute(
-                "INSERT INTO refs (name, target) VALUES ($1, $2) \
-                 ON CONFLICT (name) DO UPDATE SET target = $3",
-                &[name, target, target]
-            );
+        transact.commit().map_err(SimpleError::from)?;
+        Ok(changed)
+    }
+
+    pub fn peel(&self, hash: &String) -> Result<String> {
+        let result = self.conn.query("SELECT git_peel($1)", &[hash]);
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
         }
 
+        return Ok(result.unwrap().get(0).get(0));
+    }
+
+    pub fn delete_ref(&self, name: &String, message: &str) -> Result<bool> {
+        let transact = self.conn.transaction().map_err(SimpleError::from)?;
+        self.set_reflog_context(&transact, message)?;
+
+        let mut result = transact.execute("DELETE FROM refs WHERE repository = $1 AND name = $2", &[
+            &self.repository_id()?,
+            name
+        ]);
+
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
@@ -441,13 +2004,68 @@ impl GitSqlClient {
             return Ok(false);
         }
 
-        result = self.conn.execute("SELECT pg_notify('git_ref_update', $1)", &[name]);
+        result = transact.execute("SELECT pg_notify('git_ref_update', $1)", &[&self.notify_payload(name)]);
 
         if result.is_err() {
             return Err(SimpleError::from(result.err().unwrap()));
         }
 
-        return Ok(true);
+        transact.commit().map_err(SimpleError::from)?;
+        Ok(true)
+    }
+
+    pub fn read_reflog(&self, name: &String) -> Result<Vec<ReflogEntry>> {
+        let result = self.conn.query(
+            "SELECT id, name, old_target, new_target, time::TEXT, actor, message FROM reflog \
+             WHERE repository = $1 AND name = $2 ORDER BY id DESC",
+            &[&self.repository_id()?, name]
+        );
+
+        if result.is_err() {
+            return Err(SimpleError::from(result.err().unwrap()));
+        }
+
+        let mut entries = Vec::new();
+        for row in &result.unwrap() {
+            entries.push(ReflogEntry {
+                id: row.get(0),
+                name: row.get(1),
+                old_target: row.get(2),
+                new_target: row.get(3),
+                time: row.get(4),
+                actor: row.get(5),
+                message: row.get(6)
+            });
+        }
+        Ok(entries)
+    }
+
+    pub fn restore_ref(&self, name: &String, index: usize) -> Result<String> {
+        let entries = self.read_reflog(name)?;
+        let entry = entries.get(index)
+            .ok_or_else(|| SimpleError::new(format!("{}@{{{}}} is not in the reflog.", name, index)))?;
+
+        let target = match entry.new_target {
+            Some(ref target) => target.clone(),
+            None => return Err(SimpleError::new(format!("{}@{{{}}} is the deletion of the reference.", name, index)))
+        };
+
+        // Symbolic references such as HEAD point at other references rather than objects.
+        if !target.contains('/') && !self.has_object(&target)? {
+            return Err(SimpleError::new(format!("{}@{{{}}} points at {}, which no longer exists.", name, index, target)));
+        }
+
+        let message = format!("restore: moving to {}@{{{}}}", name, index);
+        if target.contains('/') {
+            self.set_ref(name, &target, &message)?;
+        } else {
+            self.set_ref_peeled(name, &target, &message)?;
+        }
+        Ok(target)
+    }
+
+    fn notify_payload(&self, name: &String) -> String {
+        format!("{}:{}", self.repository_name().unwrap_or_default(), name)
     }
 
     pub fn url(&self) -> String {
@@ -0,0 +1,152 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use storage::{self};
+
+use std::borrow::Borrow;
+use std::fs::File;
+use std::io::{self, Read, Write, Seek, SeekFrom, BufReader, Cursor};
+
+use git2::ObjectType;
+
+/// Bytes fetched from a large object per query.
+const LARGE_OBJECT_CHUNK: usize = 1024 * 1024;
+
+enum Source<C: Borrow<GitSqlClient>> {
+    Memory(Cursor<Vec<u8>>),
+    File(BufReader<File>),
+    LargeObject(LargeObjectReader<C>)
+}
+
+/// Reads part of a large object incrementally, one query per chunk.
+pub struct LargeObjectReader<C: Borrow<GitSqlClient>> {
+    client: C,
+    oid: u32,
+    offset: usize,
+    end: usize,
+    chunk: Cursor<Vec<u8>>
+}
+
+impl<C: Borrow<GitSqlClient>> LargeObjectReader<C> {
+    /// Reads the bytes of large object `oid` from `offset` up to `end`.
+    pub fn new(client: C, oid: u32, offset: usize, end: usize) -> LargeObjectReader<C> {
+        LargeObjectReader { client, oid, offset, end, chunk: Cursor::new(Vec::new()) }
+    }
+}
+
+impl<C: Borrow<GitSqlClient>> Read for LargeObjectReader<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk.position() as usize >= self.chunk.get_ref().len() {
+            if self.offset >= self.end {
+                return Ok(0);
+            }
+
+            let length = LARGE_OBJECT_CHUNK.min(self.end - self.offset);
+            let data = self.client.borrow().read_large_object(self.oid, self.offset, length)
+                .map_err(io::Error::other)?;
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Large object is truncated."));
+            }
+            self.offset += data.len();
+            self.chunk = Cursor::new(data);
+        }
+        self.chunk.read(buf)
+    }
+}
+
+/// Writes a large object in chunks, so that its content is never held in memory whole.
+pub struct LargeObjectWriter<'a> {
+    client: &'a GitSqlClient,
+    oid: u32,
+    offset: usize,
+    buffer: Vec<u8>
+}
+
+impl<'a> LargeObjectWriter<'a> {
+    pub fn new(client: &'a GitSqlClient, oid: u32) -> LargeObjectWriter<'a> {
+        LargeObjectWriter { client, oid, offset: 0, buffer: Vec::new() }
+    }
+
+    /// Number of bytes written so far.
+    pub fn size(&self) -> usize {
+        self.offset + self.buffer.len()
+    }
+}
+
+impl<'a> Write for LargeObjectWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= LARGE_OBJECT_CHUNK {
+            self.flush()?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.client.write_large_object(self.oid, self.offset, &self.buffer)
+            .map_err(io::Error::other)?;
+        self.offset += self.buffer.len();
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Reads the content of an object without loading offloaded objects into memory.
+///
+/// Objects stored in the `objects` table are small enough to be read whole, while objects
+/// offloaded to large objects or files are read incrementally. The client may be borrowed, or
+/// owned when the stream outlives the caller, as for an HTTP response body.
+pub struct ObjectStream<C: Borrow<GitSqlClient>> {
+    kind: ObjectType,
+    size: usize,
+    source: Source<C>
+}
+
+impl<C: Borrow<GitSqlClient>> ObjectStream<C> {
+    pub fn open(client: C, hash: &String) -> Result<ObjectStream<C>> {
+        let (encoding, header, large_object, path) = client.borrow().read_object_location(hash)?;
+        if !storage::is_offloaded(&encoding) {
+            let (kind, data) = client.borrow().read_object(hash)?;
+            return Ok(ObjectStream { kind, size: data.len(), source: Source::Memory(Cursor::new(data)) });
+        }
+
+        let (kind, size) = storage::read_header(&encoding, &header)?;
+        let source = match large_object {
+            Some(oid) => Source::LargeObject(LargeObjectReader::new(client, oid, header.len(), header.len() + size)),
+            None => {
+                let path = client.borrow().storage_options().offloaded_file(hash, path)?;
+                let mut file = File::open(&path)
+                    .map_err(|x| SimpleError::new(format!("Failed to open {}: {}", path.display(), x)))?;
+                file.seek(SeekFrom::Start(header.len() as u64)).map_err(SimpleError::from)?;
+                Source::File(BufReader::new(file))
+            }
+        };
+        Ok(ObjectStream { kind, size, source })
+    }
+
+    pub fn kind(&self) -> ObjectType {
+        self.kind
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The `"<type> <size>\0"` header that precedes the content in the raw form of the object.
+    pub fn header(&self) -> Vec<u8> {
+        GitSqlClient::encode_object(&self.kind, self.size, &[])
+    }
+}
+
+impl<C: Borrow<GitSqlClient>> Read for ObjectStream<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.source {
+            Source::Memory(ref mut cursor) => cursor.read(buf),
+            Source::File(ref mut file) => file.read(buf),
+            Source::LargeObject(ref mut reader) => reader.read(buf)
+        }
+    }
+}
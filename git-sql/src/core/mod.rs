@@ -55,7 +55,7 @@ impl GitSqlConfig {
         }
 
         let name = repo.as_str();
-        let rcfg = repositories.get(name).unwrap();
+        let rcfg = repositories.get(name)?;
         if !rcfg.is_table() {
             None
         } else {
@@ -63,6 +63,14 @@ impl GitSqlConfig {
         }
     }
 
+    /// Names of all configured repositories.
+    pub fn get_repo_names(&self) -> Vec<String> {
+        match self.root.get("repositories").and_then(|x| x.as_table()) {
+            Some(table) => table.iter().filter(|x| x.1.is_table()).map(|x| x.0.clone()).collect(),
+            None => Vec::new()
+        }
+    }
+
     pub fn get_repo_cfg_str(&self, repo: &String, opt: &str) -> Option<String> {
         let cfg = self.get_repo_cfg(repo)?;
         let result = cfg.get(opt)?;
@@ -74,6 +82,16 @@ impl GitSqlConfig {
         }
     }
 
+    pub fn get_repo_cfg_int(&self, repo: &String, opt: &str) -> Option<i64> {
+        let cfg = self.get_repo_cfg(repo)?;
+        cfg.get(opt)?.as_integer()
+    }
+
+    pub fn get_repo_cfg_bool(&self, repo: &String, opt: &str) -> Option<bool> {
+        let cfg = self.get_repo_cfg(repo)?;
+        cfg.get(opt)?.as_bool()
+    }
+
     pub fn get_server_cfg(&self) -> Option<&Value> {
         let root = &self.root;
         let server = root.get("server")?;
@@ -97,3 +115,12 @@ impl Clone for GitSqlConfig {
         GitSqlConfig::with(&self.root.clone())
     }
 }
+
+/// Maps a request path such as `/mygitrepo.git` onto a configured repository name.
+pub fn repo_name_of(path: &str) -> String {
+    let mut name = path.trim_start_matches('~').trim_matches('/');
+    if name.ends_with(".git") {
+        name = &name[..name.len() - 4];
+    }
+    name.to_string()
+}
@@ -0,0 +1,133 @@
+use core::{SimpleError, Result, repo_name_of};
+use client::{GitSqlClient};
+use pktline::{PktLineReader, PktLineWriter};
+use uploadpack::{self, UploadPack};
+use uploadpack::v2::{UploadPackV2};
+
+use std::io::{BufReader, BufWriter};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Number of connections served at once, as git daemon allows by default.
+const MAX_CONNECTIONS: usize = 32;
+
+/// How long a connection may wait on a client to read or write before it is dropped.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Serves repositories over the native `git://` protocol.
+///
+/// Only `git-upload-pack` is offered, so the daemon is always read-only.
+pub struct GitDaemon {
+    loader: fn(String) -> Option<GitSqlClient>
+}
+
+/// A parsed daemon request line: `<service> <path>\0host=<host>\0[\0<extra parameter>\0...]`.
+struct DaemonRequest {
+    service: String,
+    repo: String,
+    version: u32
+}
+
+fn parse_request(line: &str) -> Result<DaemonRequest> {
+    let mut fields = line.split('\0');
+    let command = fields.next().unwrap_or("");
+    let version = fields.map(uploadpack::protocol_version).max().unwrap_or(0);
+    let mut parts = command.splitn(2, ' ');
+    let service = parts.next().unwrap_or("").to_string();
+    let path = parts.next()
+        .ok_or_else(|| SimpleError::new(format!("Malformed daemon request: {}", command)))?;
+
+    Ok(DaemonRequest { service, repo: repo_name_of(path), version })
+}
+
+impl GitDaemon {
+    pub fn new(loader: fn(String) -> Option<GitSqlClient>) -> GitDaemon {
+        GitDaemon { loader }
+    }
+
+    /// Accepts connections on the given address, serving each on its own thread, up to
+    /// `MAX_CONNECTIONS` at once.
+    pub fn listen(&self, bind: &str) -> Result<()> {
+        let listener = TcpListener::bind(bind).map_err(SimpleError::from)?;
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        for stream in listener.incoming() {
+            let stream = match stream.and_then(|x| {
+                x.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+                x.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+                Ok(x)
+            }) {
+                Ok(stream) => stream,
+                Err(error) => {
+                    println!("[ERROR] Failed to accept git daemon connection: {}", error);
+                    continue;
+                }
+            };
+
+            let slot = ConnectionSlot(connections.clone());
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                let mut output = PktLineWriter::new(stream);
+                let _ = output.write_line("ERR too many connections, try again later");
+                continue;
+            }
+
+            let loader = self.loader;
+            thread::spawn(move || {
+                let _slot = slot;
+                let daemon = GitDaemon::new(loader);
+                if let Err(error) = daemon.handle(stream) {
+                    println!("[ERROR] git daemon: {}", error);
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> Result<()> {
+        let read_half = stream.try_clone().map_err(SimpleError::from)?;
+        let mut input = PktLineReader::new(BufReader::new(read_half));
+        let mut output = PktLineWriter::new(BufWriter::new(stream));
+
+        let line = match input.expect_packet()?.text() {
+            Some(line) => line,
+            None => return Err(SimpleError::new("Expected a daemon request line."))
+        };
+        let request = parse_request(&line)?;
+
+        if request.service != "git-upload-pack" {
+            output.write_line(&format!("ERR service not enabled: {}", request.service))?;
+            return output.flush();
+        }
+
+        let client = match (self.loader)(request.repo.clone()) {
+            Some(client) => client,
+            None => {
+                output.write_line(&format!("ERR access denied or repository not exported: /{}", request.repo))?;
+                return output.flush();
+            }
+        };
+
+        if request.version == 2 {
+            let upload_pack = UploadPackV2::new(&client);
+            upload_pack.advertise(&mut output)?;
+            return upload_pack.serve(&mut input, &mut output);
+        }
+
+        let upload_pack = UploadPack::new(&client, false);
+        upload_pack.advertise(&mut output)?;
+        upload_pack.serve(&mut input, &mut output)
+    }
+}
+
+/// Counts a connection as served until it is dropped, even if serving it panics.
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,169 @@
+use core::{Result};
+use client::{GitSqlClient};
+use object;
+use storage;
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use sha1;
+
+/// Number of objects loaded from the database at once.
+const FSCK_READ_CHUNK: usize = 256;
+
+/// A problem found in the database, printed by `git-sql fsck` as a tab-separated line.
+pub struct Problem {
+    /// What is wrong (ex: `missing-parent` or `size-mismatch`).
+    pub kind: String,
+    /// The object, or the reference, that has the problem.
+    pub object: String,
+    /// The object it points at, if any.
+    pub target: String,
+    pub detail: String
+}
+
+impl Problem {
+    fn new(kind: &str, object: &str, detail: String) -> Problem {
+        Problem { kind: kind.to_string(), object: object.to_string(), target: String::new(), detail }
+    }
+
+    /// Formats the problem as `<kind>\t<object>\t<target>\t<detail>`, leaving unknown fields empty.
+    pub fn to_line(&self) -> String {
+        format!("{}\t{}\t{}\t{}", self.kind, self.object, self.target, self.detail.replace(&['\t', '\n'][..], " "))
+    }
+}
+
+/// The outcome of an fsck.
+pub struct FsckReport {
+    /// Number of objects whose content was checked.
+    pub objects: usize,
+    pub problems: Vec<Problem>
+}
+
+/// Compares what was read back of an object with its hash, and with the size in its header and in the size column.
+fn check_content(hash: &String, digest: String, declared: usize, actual: usize, recorded: usize, problems: &mut Vec<Problem>) {
+    if &digest != hash {
+        problems.push(Problem { target: digest, ..Problem::new("hash-mismatch", hash, String::new()) });
+    }
+    if declared != actual {
+        problems.push(Problem::new("size-mismatch", hash, format!("header declares {} bytes, content has {}", declared, actual)));
+    }
+    if declared != recorded {
+        problems.push(Problem::new("size-mismatch", hash, format!("header declares {} bytes, size column has {}", declared, recorded)));
+    }
+}
+
+/// Checks that the content of an object in its raw form matches its hash and the sizes recorded for it.
+fn check_raw(hash: &String, recorded: usize, raw: &[u8], problems: &mut Vec<Problem>) {
+    let mut sha = sha1::Sha1::new();
+    sha.update(raw);
+
+    let (_, declared) = match storage::read_header(storage::ENCODING_RAW, raw) {
+        Ok(header) => header,
+        Err(error) => return problems.push(Problem::new("unreadable", hash, error.to_string()))
+    };
+    let actual = raw.len() - raw.iter().position(|x| *x == 0).unwrap() - 1;
+    check_content(hash, sha.digest().to_string(), declared, actual, recorded, problems);
+}
+
+/// Checks an offloaded object without loading it into memory.
+fn check_offloaded(client: &GitSqlClient, hash: &String, recorded: usize, problems: &mut Vec<Problem>) -> io::Result<()> {
+    let mut stream = client.open_object(hash).map_err(io::Error::other)?;
+    let header = stream.header();
+    let declared = stream.size();
+
+    let mut sha = sha1::Sha1::new();
+    sha.update(&header);
+    let mut actual = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let count = stream.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        sha.update(&buffer[..count]);
+        actual += count;
+    }
+
+    check_content(hash, sha.digest().to_string(), declared, actual, recorded, problems);
+    Ok(())
+}
+
+/// Reads objects in their raw form, reading each again on its own when the chunk fails, so that
+/// an object that cannot be decoded is reported without hiding the others.
+fn read_chunk(client: &GitSqlClient, hashes: &[String]) -> Vec<(String, Result<Vec<u8>>)> {
+    match client.read_raw_objects(hashes) {
+        Ok(loaded) => loaded.into_iter().map(|(hash, raw)| (hash, Ok(raw))).collect(),
+        Err(_) => hashes.iter().map(|hash| (hash.clone(), client.read_raw_object(hash))).collect()
+    }
+}
+
+/// Checks that annotated tags point at existing objects of the type they declare.
+fn check_tags(client: &GitSqlClient, problems: &mut Vec<Problem>) -> Result<()> {
+    let hashes = client.list_tag_objects()?;
+
+    for chunk in hashes.chunks(FSCK_READ_CHUNK) {
+        let mut tags = Vec::with_capacity(chunk.len());
+        for (hash, raw) in read_chunk(client, chunk) {
+            match raw.and_then(storage::split).and_then(|(_, data)| object::parse_tag(&data)) {
+                Ok(tag) => tags.push((hash, tag.object, tag.kind)),
+                Err(error) => problems.push(Problem::new("unreadable", &hash, error.to_string()))
+            }
+        }
+
+        let targets: Vec<String> = tags.iter().map(|x| x.1.clone()).collect();
+        let kinds = client.read_object_types(&targets)?;
+
+        for (hash, target, kind) in tags {
+            let problem = match kinds.get(&target) {
+                None => Problem::new("missing-tag-target", &hash, String::new()),
+                Some(actual) if actual.str() != kind => {
+                    Problem::new("wrong-type", &hash, format!("tag target is a {}, not a {}", actual, kind))
+                },
+                Some(_) => continue
+            };
+            problems.push(Problem { target, ..problem });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the integrity and connectivity of the objects and references of every repository in the database.
+///
+/// Commits, trees and tags must point at existing objects of the right type, and references at
+/// existing objects. Unless `connectivity_only` is set, every object is also read back, and must
+/// match its hash and the size declared in its header. Commits and trees that were never indexed,
+/// as after an interrupted import, and objects that cannot be decoded are reported rather than checked.
+pub fn fsck(client: &GitSqlClient, connectivity_only: bool) -> Result<FsckReport> {
+    let mut problems: Vec<Problem> = client.check_connectivity()?.into_iter()
+        .map(|(kind, object, target, detail)| Problem { kind, object, target, detail })
+        .collect();
+    check_tags(client, &mut problems)?;
+
+    if connectivity_only {
+        return Ok(FsckReport { objects: 0, problems });
+    }
+
+    let objects = client.list_objects()?;
+    let recorded: HashMap<&String, usize> = objects.iter().map(|x| (&x.0, x.2)).collect();
+    let (offloaded, stored): (Vec<_>, Vec<_>) = objects.iter().partition(|x| storage::is_offloaded(&x.1));
+
+    for chunk in stored.chunks(FSCK_READ_CHUNK) {
+        let hashes: Vec<String> = chunk.iter().map(|x| x.0.clone()).collect();
+        for (hash, raw) in read_chunk(client, &hashes) {
+            match raw {
+                Ok(raw) => check_raw(&hash, recorded[&hash], &raw, &mut problems),
+                Err(error) => problems.push(Problem::new("unreadable", &hash, error.to_string()))
+            }
+        }
+    }
+
+    for (hash, _, recorded) in offloaded {
+        if let Err(error) = check_offloaded(client, hash, *recorded, &mut problems) {
+            problems.push(Problem::new("unreadable", hash, error.to_string()));
+        }
+    }
+
+    Ok(FsckReport { objects: objects.len(), problems })
+}
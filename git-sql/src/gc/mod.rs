@@ -0,0 +1,191 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient, UnreachableObject};
+use storage;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of objects deleted per transaction.
+const GC_DELETE_CHUNK: usize = 500;
+
+/// How long unreachable objects are kept when no prune age is given, as with git gc.
+pub const DEFAULT_PRUNE: &str = "2 weeks";
+
+/// The outcome of a gc.
+pub struct GcStats {
+    /// Number of objects not reachable from any reference or reflog.
+    pub unreachable: usize,
+    /// Number of unreachable objects old enough to delete.
+    pub expired: usize,
+    /// Bytes stored for the expired objects.
+    pub reclaimable: usize,
+    /// Number of objects deleted.
+    pub deleted: usize,
+    /// Bytes stored for the deleted objects.
+    pub reclaimed: usize
+}
+
+/// Converts a prune age as given to git (ex: `now`, `never`, `2.weeks.ago` or a date) into a
+/// PostgreSQL interval, or `None` for `never`. Other ages are taken as intervals (ex: `3 days`).
+pub fn parse_prune(age: &str) -> Result<Option<String>> {
+    let age = age.trim();
+    match age {
+        "" => Err(SimpleError::new("Invalid prune age.")),
+        "never" => Ok(None),
+        "now" | "all" => Ok(Some("0 seconds".to_string())),
+        _ if age.ends_with(".ago") => Ok(Some(age.trim_end_matches(".ago").replace('.', " "))),
+        _ => match parse_date(age) {
+            Some(date) => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| SimpleError::new("Invalid system time."))?;
+                Ok(Some(format!("{} seconds", now.as_secs() as i64 - date)))
+            },
+            None => Ok(Some(age.to_string()))
+        }
+    }
+}
+
+/// Converts a date in one of git's formats into seconds since the epoch: `@1706702400`, RFC 2822
+/// (ex: `Wed, 31 Jan 2024 12:00:00 +0100`) or ISO 8601 (ex: `2024-01-31` or `2024-01-31T12:00:00Z`).
+/// Dates without a time zone are taken as UTC.
+fn parse_date(date: &str) -> Option<i64> {
+    if let Some(seconds) = date.strip_prefix('@') {
+        return seconds.parse().ok();
+    }
+
+    let date = match date.find(", ") {
+        Some(index) => &date[index + 2..],
+        None => date
+    };
+    let parts: Vec<&str> = date.split([' ', 'T']).filter(|x| !x.is_empty()).collect();
+    let (year, month, day, rest) = if parts.first()?.contains('-') {
+        let ymd: Vec<i64> = parts[0].split('-').map(|x| x.parse().ok()).collect::<Option<Vec<i64>>>()?;
+        if ymd.len() != 3 {
+            return None;
+        }
+        (ymd[0], ymd[1], ymd[2], &parts[1..])
+    } else {
+        let months = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+        let month = months.iter().position(|x| parts.get(1).is_some_and(|y| y.to_ascii_lowercase().starts_with(x)))?;
+        (parts.get(2)?.parse().ok()?, month as i64 + 1, parts[0].parse().ok()?, parts.get(3..)?)
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || rest.len() > 2 {
+        return None;
+    }
+
+    let mut seconds = days_from_civil(year, month, day) * 86400;
+    let (time, mut zone) = match rest.first() {
+        Some(time) => match time.find(['+', '-', 'Z']) {
+            Some(index) => (&time[..index], Some(&time[index..])),
+            None => (*time, None)
+        },
+        None => ("", None)
+    };
+    if !time.is_empty() {
+        let hms: Vec<i64> = time.split(':').map(|x| x.parse().ok()).collect::<Option<Vec<i64>>>()?;
+        if hms.len() < 2 || hms.len() > 3 {
+            return None;
+        }
+        seconds += hms[0] * 3600 + hms[1] * 60 + hms.get(2).unwrap_or(&0);
+    }
+    if rest.len() == 2 {
+        zone = Some(rest[1]);
+    }
+
+    match zone {
+        None | Some("Z") => Some(seconds),
+        Some(zone) => {
+            let digits = zone[1..].replace(':', "");
+            if digits.len() != 4 || !zone.starts_with(['+', '-']) {
+                return None;
+            }
+            let offset = digits[..2].parse::<i64>().ok()? * 3600 + digits[2..].parse::<i64>().ok()? * 60;
+            Some(if zone.starts_with('-') { seconds + offset } else { seconds - offset })
+        }
+    }
+}
+
+/// Counts the days from 1970-01-01 to a date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Deletes objects that no reference or reflog entry of any repository in the database reaches,
+/// once they were stored longer than `prune` ago.
+///
+/// Recently stored objects are kept, so objects written by a push or import whose references are
+/// not updated yet survive, and storing an object again makes it recent once it is a day old, so
+/// prune ages below a day are only safe while nothing is pushed, as with `git gc --prune=now`.
+/// Objects are deleted in
+/// batches, each in its own transaction, with deltas deleted before their bases. With `dry_run`,
+/// or without `prune`, nothing is written, and objects only reached from commits or trees that are
+/// not indexed yet are counted as unreachable.
+pub fn gc(client: &GitSqlClient, prune: Option<&String>, dry_run: bool) -> Result<GcStats> {
+    let deleting = !dry_run && prune.is_some();
+
+    // Commits and trees that are not indexed would hide the objects they reach.
+    if deleting {
+        client.update_commit_graph()?;
+        client.update_tree_entries()?;
+    }
+
+    let objects = client.find_unreachable_objects(prune)?;
+    let expired: Vec<&UnreachableObject> = objects.iter().filter(|x| x.expired).collect();
+    let mut stats = GcStats {
+        unreachable: objects.len(),
+        expired: expired.len(),
+        reclaimable: expired.iter().map(|x| x.bytes).sum(),
+        deleted: 0,
+        reclaimed: 0
+    };
+
+    if !deleting || expired.is_empty() {
+        return Ok(stats);
+    }
+    let prune = prune.unwrap();
+
+    // Fail before deleting anything if offloaded files could not be removed.
+    let options = client.storage_options();
+    if let Some(object) = expired.iter().find(|x| x.encoding == storage::ENCODING_FILE && x.path.is_none()) {
+        options.offload_path(&object.hash)?;
+    }
+
+    let sizes: HashMap<&String, usize> = expired.iter().map(|x| (&x.hash, x.bytes)).collect();
+    let mut deleted = Vec::new();
+    let mut batch: Vec<String> = Vec::with_capacity(GC_DELETE_CHUNK);
+    for (index, object) in expired.iter().enumerate() {
+        batch.push(object.hash.clone());
+
+        // Batches never mix depths, so deltas are deleted in an earlier transaction than their bases.
+        let next = expired.get(index + 1);
+        if batch.len() < GC_DELETE_CHUNK && next.is_some_and(|x| x.depth == object.depth) {
+            continue;
+        }
+
+        for (hash, encoding, path) in client.delete_unreachable_objects(&batch, prune)? {
+            if encoding == storage::ENCODING_FILE {
+                match fs::remove_file(options.offloaded_file(&hash, path)?) {
+                    Err(ref error) if error.kind() != io::ErrorKind::NotFound => {
+                        return Err(SimpleError::new(format!("Failed to remove offloaded object {}: {}", hash, error)));
+                    },
+                    _ => {}
+                }
+            }
+            stats.deleted += 1;
+            stats.reclaimed += sizes[&hash];
+            deleted.push(hash);
+        }
+        batch.clear();
+    }
+
+    if !deleted.is_empty() {
+        client.clear_cached_packs(&deleted)?;
+    }
+    Ok(stats)
+}
@@ -37,15 +37,36 @@ mod core;
 mod server;
 mod client;
 mod updater;
+mod pktline;
+mod object;
+mod revwalk;
+mod uploadpack;
+mod receivepack;
+mod pack;
+mod daemon;
+mod shell;
+mod bundle;
+mod schema;
+mod storage;
+mod repack;
+mod gc;
+mod fsck;
 
 use std::process::exit;
 use std::sync::{Mutex};
 use std::fs::File;
+use std::env;
+use std::io::{self, Read, BufReader, BufWriter};
+use std::thread;
 
-use core::GitSqlConfig;
+use core::{GitSqlConfig, SimpleError};
 use client::GitSqlClient;
 use server::GitSqlServer;
+use daemon::GitDaemon;
+use shell::ShellCommand;
 use updater::RepositoryUpdater;
+use pack::PackOptions;
+use storage::{StorageOptions, Offload};
 
 use git2::Repository;
 use clap::App;
@@ -62,7 +83,67 @@ lazy_static! {
 pub fn load_client_by_repo_name(_repo: String) -> Option<GitSqlClient> {
     let config = DB_CONFIG.lock().unwrap().clone();
 
-    config.get_repo_db_url(&_repo).map(|x| GitSqlClient::new(x).unwrap())
+    let mut client = connect_repo(&config, &_repo)?;
+    if let Err(error) = client.open_repository(&_repo) {
+        eprintln!("[ERROR] {}", error);
+        return None;
+    }
+    Some(client)
+}
+
+/// Connects to the database of a configured repository without selecting the repository.
+fn connect_repo(config: &GitSqlConfig, repo: &String) -> Option<GitSqlClient> {
+    let url = config.get_repo_db_url(repo)?;
+    let mut client = match GitSqlClient::new(url) {
+        Ok(client) => client,
+        Err(error) => {
+            eprintln!("[ERROR] Failed to connect to the database of '{}': {}", repo, error);
+            return None;
+        }
+    };
+    client.set_pack_options(load_pack_options(config, repo));
+    client.set_storage_options(load_storage_options(config, repo));
+    Some(client)
+}
+
+fn load_pack_options(config: &GitSqlConfig, repo: &String) -> PackOptions {
+    let mut options = PackOptions::new();
+
+    if let Some(window) = config.get_repo_cfg_int(repo, "pack-window") {
+        options.window = window as usize;
+    }
+
+    if let Some(depth) = config.get_repo_cfg_int(repo, "pack-depth") {
+        options.depth = depth as usize;
+    }
+
+    options
+}
+
+fn load_storage_options(config: &GitSqlConfig, repo: &String) -> StorageOptions {
+    let mut options = StorageOptions::new();
+
+    if let Some(compress) = config.get_repo_cfg_bool(repo, "compress-objects") {
+        options.compress = compress;
+    }
+
+    if let Some(window) = config.get_repo_cfg_int(repo, "delta-window") {
+        options.delta_window = window as usize;
+    }
+
+    if let Some(depth) = config.get_repo_cfg_int(repo, "delta-depth") {
+        options.delta_depth = depth as usize;
+    }
+
+    if let Some(threshold) = config.get_repo_cfg_int(repo, "offload-threshold") {
+        options.offload_threshold = Some(threshold as usize);
+    }
+
+    if let Some(directory) = config.get_repo_cfg_str(repo, "offload-directory") {
+        options.offload = Offload::Directory(directory.into());
+    }
+
+    options
 }
 
 fn set_db_config(cfg: &GitSqlConfig) {
@@ -90,13 +171,25 @@ fn main() {
     set_db_config(&conf);
 
     if let Some(the_repo_name) = args.value_of("repository") {
-        maybe_client = load_client_by_repo_name(the_repo_name.into());
         repo_name = the_repo_name.into();
+        maybe_client = connect_repo(&conf, &repo_name);
 
         if maybe_client.is_none() {
             println!("[ERROR] Repository '{}' is not configured.", repo_name);
             exit(1);
         }
+        maybe_client.as_mut().unwrap().set_actor(env::var("USER").ok());
+
+        // The repository is registered by init and migrate, so every other command expects
+        // a current schema that already knows about it.
+        if args.subcommand_matches("init").is_none() && args.subcommand_matches("migrate").is_none() {
+            let client = maybe_client.as_mut().unwrap();
+            let opened = schema::check(client).and_then(|_| client.open_repository(&repo_name));
+            if let Err(error) = opened {
+                println!("[ERROR] {}", error);
+                exit(1);
+            }
+        }
     }
 
     if let Some(_) = args.subcommand_matches("list-refs") {
@@ -144,38 +237,218 @@ fn main() {
         } else {
             updater.update_objects(&repo).expect("Failed to update objects.");
         }
+
+        if !cmd.is_present("copy-import-file") {
+            client.update_commit_graph().expect("Failed to update the commit graph.");
+            client.update_tree_entries().expect("Failed to update tree entries.");
+        }
+        updater.update_shallow(&repo).expect("Failed to record shallow commits.");
         updater.update_refs(&repo).expect("Failed to update references");
     } else if let Some(cmd) = args.subcommand_matches("init") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let mut client = maybe_client.unwrap();
+
+        let applied = schema::migrate(&client, &repo_name, cmd.is_present("no-python")).unwrap();
+        for migration in applied {
+            println!("Applied migration {:04}_{}", migration.version, migration.name);
+        }
+        client.create_repository(&repo_name).unwrap();
+        println!("Completed.");
+    } else if let Some(cmd) = args.subcommand_matches("migrate") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let mut client = maybe_client.unwrap();
+
+        if cmd.is_present("dry-run") {
+            let pending = schema::pending(&client).unwrap();
+            println!("Schema is at version {} of {}.", client.schema_version().unwrap(), schema::latest_version());
+            for migration in pending {
+                println!("Would apply migration {:04}_{}", migration.version, migration.name);
+            }
+            println!("Would reload types, functions and views.");
+            return;
+        }
+
+        let applied = schema::migrate(&client, &repo_name, cmd.is_present("no-python")).unwrap_or_else(|error| {
+            println!("[ERROR] {}", error);
+            exit(1);
+        });
+        for migration in &applied {
+            println!("Applied migration {:04}_{}", migration.version, migration.name);
+        }
+        client.create_repository(&repo_name).unwrap();
+        println!("Schema is at version {}.", client.schema_version().unwrap());
+    } else if let Some(cmd) = args.subcommand_matches("import-pack") {
         if maybe_client.is_none() {
             println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
             exit(1);
         }
         let client = maybe_client.unwrap();
 
-        let sql_file_content = String::from(include_str!(concat!(env!("OUT_DIR"), "/git.rs.sql")));
-
-        let mut used_file_content = String::new();
-        if cmd.is_present("no-python") {
-            let mut inside_python_section = false;
-            for line in sql_file_content.lines() {
-                if line.contains("<PYTHON ONLY>") {
-                    inside_python_section = true;
-                } else if line.contains("</PYTHON ONLY>") {
-                    inside_python_section = false;
-                    continue;
-                }
+        let mut input: Box<dyn Read> = match cmd.value_of("pack-file") {
+            Some(path) if path != "-" => Box::new(File::open(path).expect("Failed to open pack file.")),
+            _ => Box::new(io::stdin())
+        };
+
+        let count = client.import_pack(&mut input).expect("Failed to import pack.");
+        println!("Imported {} objects.", count);
+    } else if let Some(cmd) = args.subcommand_matches("repack") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let client = maybe_client.unwrap();
 
-                if !inside_python_section {
-                    used_file_content.push_str(line);
-                    used_file_content.push('\n');
+        let mut options = client.storage_options();
+        if let Some(window) = cmd.value_of("window") {
+            options.delta_window = window.parse::<usize>().expect("Invalid delta window.");
+        }
+        if let Some(depth) = cmd.value_of("depth") {
+            options.delta_depth = depth.parse::<usize>().expect("Invalid delta depth.");
+        }
+
+        let stats = repack::repack(&client, &options).expect("Failed to repack objects.");
+        println!("Stored {} of {} blobs as deltas, saving {} bytes.", stats.deltified, stats.blobs, stats.saved);
+    } else if let Some(cmd) = args.subcommand_matches("gc") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let client = maybe_client.unwrap();
+
+        let prune = gc::parse_prune(cmd.value_of("prune").unwrap_or(gc::DEFAULT_PRUNE)).expect("Invalid prune age.");
+        let dry_run = cmd.is_present("dry-run");
+        let stats = gc::gc(&client, prune.as_ref(), dry_run).expect("Failed to collect garbage.");
+
+        println!("Found {} unreachable objects, {} of them old enough to delete ({} bytes).",
+            stats.unreachable, stats.expired, stats.reclaimable);
+        if !dry_run && prune.is_some() {
+            println!("Deleted {} objects, reclaiming {} bytes.", stats.deleted, stats.reclaimed);
+        }
+    } else if let Some(cmd) = args.subcommand_matches("fsck") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let client = maybe_client.unwrap();
+
+        // Problems are printed one per line for scripts, with the summary kept out of the way on stderr.
+        let report = match fsck::fsck(&client, cmd.is_present("connectivity-only")) {
+            Ok(report) => report,
+            Err(error) => {
+                println!("[ERROR] Failed to check objects: {}", error);
+                exit(1);
+            }
+        };
+        for problem in &report.problems {
+            println!("{}", problem.to_line());
+        }
+        eprintln!("Checked {} objects, found {} problems.", report.objects, report.problems.len());
+        if !report.problems.is_empty() {
+            exit(1);
+        }
+    } else if let Some(cmd) = args.subcommand_matches("reflog") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let client = maybe_client.unwrap();
+
+        // Deleted references keep their reflog, so names that no longer resolve are used as given.
+        let given = cmd.value_of("ref").unwrap().to_string();
+        let name = client.resolve_ref_name(&given).expect("Failed to resolve reference.")
+            .map(|x| x.0)
+            .unwrap_or(given);
+
+        if let Some(index) = cmd.value_of("restore") {
+            let index = index.parse::<usize>().expect("Invalid reflog entry.");
+            match client.restore_ref(&name, index) {
+                Ok(target) => println!("{} restored to {}", name, target),
+                Err(error) => {
+                    println!("[ERROR] {}", error);
+                    exit(1);
                 }
             }
+            return;
+        }
+
+        for (index, entry) in client.read_reflog(&name).unwrap().iter().enumerate() {
+            let target = match entry.new_target {
+                Some(ref target) if !target.contains('/') => target[..7.min(target.len())].to_string(),
+                Some(ref target) => target.clone(),
+                None => String::from("(deleted)")
+            };
+            println!(
+                "{} {}@{{{}}}: {} {}: {}",
+                target, name, index, entry.time, entry.actor, entry.message.clone().unwrap_or_default()
+            );
+        }
+    } else if let Some(cmd) = args.subcommand_matches("bundle") {
+        if maybe_client.is_none() {
+            println!("[ERROR] Please specify a repository to operate on (-r myrepo)");
+            exit(1);
+        }
+        let client = maybe_client.unwrap();
+
+        if let Some(create) = cmd.subcommand_matches("create") {
+            let path = create.value_of("bundle-file").unwrap();
+            let refs: Vec<String> = create.values_of("refs")
+                .map(|x| x.map(|r| r.to_string()).collect())
+                .unwrap_or_default();
+            let version = create.value_of("bundle-version").unwrap_or("2").parse::<u32>()
+                .expect("Invalid bundle version.");
+
+            let mut file = BufWriter::new(File::create(path).expect("Failed to create bundle file."));
+            let count = bundle::create_bundle(&client, &refs, version, &mut file).expect("Failed to create bundle.");
+            println!("Wrote {} objects to {}.", count, path);
+        } else if let Some(import) = cmd.subcommand_matches("import") {
+            let path = import.value_of("bundle-file").unwrap();
+            let mut file = BufReader::new(File::open(path).expect("Failed to open bundle file."));
+            let (count, refs) = bundle::import_bundle(&client, &mut file).expect("Failed to import bundle.");
+            println!("Imported {} objects and {} references.", count, refs);
         } else {
-            used_file_content.push_str(sql_file_content.as_str());
+            println!("{}", cmd.usage());
+            exit(1);
         }
+    } else if args.subcommand_matches("shell").is_some() {
+        let original_command = match env::var("SSH_ORIGINAL_COMMAND") {
+            Ok(command) => command,
+            Err(_) => {
+                eprintln!("[ERROR] SSH_ORIGINAL_COMMAND is not set; git-sql shell only runs git commands over SSH.");
+                exit(1);
+            }
+        };
 
-        client.run_sql(&used_file_content).unwrap();
-        println!("Completed.");
+        let command = match ShellCommand::parse(&original_command) {
+            Ok(command) => command,
+            Err(error) => {
+                eprintln!("[ERROR] {}", error);
+                exit(1);
+            }
+        };
+
+        let mut client = match load_client_by_repo_name(command.repo.clone()) {
+            Some(client) => client,
+            None => {
+                eprintln!("[ERROR] Repository '{}' is not configured.", command.repo);
+                exit(1);
+            }
+        };
+
+        // Every key shares the git user, so pushes are attributed to the address they come from.
+        let ssh_client = env::var("SSH_CONNECTION").ok().and_then(|x| x.split_whitespace().next().map(String::from));
+        client.set_actor(ssh_client.map(|x| format!("ssh {}", x)));
+
+        let version = env::var("GIT_PROTOCOL").map(|x| uploadpack::protocol_version(&x)).unwrap_or(0);
+        if let Err(error) = command.run(&client, version) {
+            eprintln!("[ERROR] {}", error);
+            exit(1);
+        }
     } else if let Some(_) = args.subcommand_matches("serve") {
         let maybe_server_cfg = conf.get_server_cfg();
 
@@ -192,7 +465,32 @@ fn main() {
             exit(1);
         }
 
-        let server = GitSqlServer::new(load_client_by_repo_name);
+        for name in conf.get_repo_names() {
+            let checked = connect_repo(&conf, &name)
+                .ok_or_else(|| match conf.get_repo_db_url(&name) {
+                    Some(_) => SimpleError::new("Failed to connect to its database."),
+                    None => SimpleError::new("Missing 'postgres-url' option.")
+                })
+                .and_then(|client| schema::check(&client));
+            if let Err(error) = checked {
+                println!("[ERROR] Repository '{}': {}", name, error);
+                exit(1);
+            }
+        }
+
+        if let Some(daemon_bind) = server_cfg.get("git-daemon-bind").and_then(|x| x.as_str()) {
+            let daemon_bind = daemon_bind.to_string();
+            println!("Serving git:// on {}", daemon_bind);
+            thread::spawn(move || {
+                GitDaemon::new(load_client_by_repo_name).listen(&daemon_bind).expect("Failed to run the git daemon.");
+            });
+        }
+
+        let mut server = GitSqlServer::new(load_client_by_repo_name);
+        server.set_receive_pack(server_cfg.get("receive-pack").and_then(|x| x.as_bool()).unwrap_or(false));
+        if let Some(size) = server_cfg.get("max-request-size").and_then(|x| x.as_integer()) {
+            server.set_max_request_size(size as usize);
+        }
         let router = server.router();
         let mut chain = Chain::new(router);
         chain.link_before(server);
@@ -0,0 +1,172 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+
+use git2::ObjectType;
+
+use hex;
+use sha1;
+
+pub const MODE_TREE: u32 = 0o040000;
+pub const MODE_GITLINK: u32 = 0o160000;
+
+pub struct Commit {
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub author: Option<Signature>,
+    pub committer: Option<Signature>,
+    /// The signature of a signed commit, such as an armored PGP signature.
+    pub signature: Option<String>,
+    pub message: String
+}
+
+/// The identity and time of an author or committer line.
+pub struct Signature {
+    /// The name and email, as `Name <email>`.
+    pub identity: String,
+    /// Seconds since the Unix epoch.
+    pub time: Option<i64>
+}
+
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: Vec<u8>,
+    pub hash: String
+}
+
+impl TreeEntry {
+    pub fn is_tree(&self) -> bool {
+        self.mode == MODE_TREE
+    }
+
+    pub fn is_gitlink(&self) -> bool {
+        self.mode == MODE_GITLINK
+    }
+}
+
+pub struct Tag {
+    pub object: String,
+    pub kind: String
+}
+
+/// Computes the object id of an object with the given type and content.
+pub fn hash_object(kind: &ObjectType, data: &[u8]) -> String {
+    let encoded = GitSqlClient::encode_object(kind, data.len(), data);
+    let mut sha = sha1::Sha1::new();
+    sha.update(&encoded);
+    sha.digest().to_string()
+}
+
+/// Iterates over the `key value` header lines of a commit or tag, stopping at the message.
+fn header_lines(data: &[u8]) -> Vec<(&str, String)> {
+    let mut headers: Vec<(&str, String)> = Vec::new();
+    for raw in data.split(|x| *x == b'\n') {
+        if raw.is_empty() {
+            break;
+        }
+
+        // Continuation lines of multi-line headers (such as signatures) start with a space.
+        if raw[0] == b' ' {
+            if let Some(last) = headers.last_mut() {
+                last.1.push('\n');
+                last.1.push_str(&String::from_utf8_lossy(&raw[1..]));
+            }
+            continue;
+        }
+
+        if let Ok(line) = std::str::from_utf8(raw) {
+            match line.find(' ') {
+                Some(index) => headers.push((&line[..index], line[index + 1..].to_string())),
+                None => headers.push((line, String::new()))
+            }
+        }
+    }
+    headers
+}
+
+/// Returns the message following the headers of a commit or tag.
+fn message(data: &[u8]) -> String {
+    let start = data.windows(2).position(|x| x == b"\n\n").map(|x| x + 2).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[start..]).replace('\0', "")
+}
+
+fn parse_signature(value: &str) -> Signature {
+    match value.rfind('>') {
+        Some(end) => Signature {
+            identity: value[..end + 1].to_string(),
+            time: value[end + 1..].split_whitespace().next().and_then(|x| x.parse::<i64>().ok())
+        },
+        None => Signature { identity: value.to_string(), time: None }
+    }
+}
+
+pub fn parse_commit(data: &[u8]) -> Result<Commit> {
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut author = None;
+    let mut committer = None;
+    let mut signature = None;
+
+    for (key, value) in header_lines(data) {
+        match key {
+            "tree" => tree = Some(value),
+            "parent" => parents.push(value),
+            "author" => author = Some(parse_signature(&value)),
+            "committer" => committer = Some(parse_signature(&value)),
+            "gpgsig" => signature = Some(value),
+            _ => {}
+        }
+    }
+
+    match tree {
+        Some(tree) => Ok(Commit { tree, parents, author, committer, signature, message: message(data) }),
+        None => Err(SimpleError::new("Commit is missing a tree header."))
+    }
+}
+
+pub fn parse_tag(data: &[u8]) -> Result<Tag> {
+    let mut object = None;
+    let mut kind = None;
+
+    for (key, value) in header_lines(data) {
+        match key {
+            "object" => object = Some(value),
+            "type" => kind = Some(value),
+            _ => {}
+        }
+    }
+
+    match (object, kind) {
+        (Some(object), Some(kind)) => Ok(Tag { object, kind }),
+        _ => Err(SimpleError::new("Tag is missing an object or type header."))
+    }
+}
+
+pub fn parse_tree(data: &[u8]) -> Result<Vec<TreeEntry>> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+
+    while position < data.len() {
+        let space = data[position..].iter().position(|x| *x == b' ')
+            .ok_or_else(|| SimpleError::new("Malformed tree entry mode."))?;
+        let mode_text = std::str::from_utf8(&data[position..position + space])
+            .map_err(|_| SimpleError::new("Malformed tree entry mode."))?;
+        let mode = u32::from_str_radix(mode_text, 8)
+            .map_err(|_| SimpleError::new("Malformed tree entry mode."))?;
+        position += space + 1;
+
+        let nul = data[position..].iter().position(|x| *x == 0)
+            .ok_or_else(|| SimpleError::new("Malformed tree entry name."))?;
+        let name = data[position..position + nul].to_vec();
+        position += nul + 1;
+
+        if position + 20 > data.len() {
+            return Err(SimpleError::new("Truncated tree entry hash."));
+        }
+        let hash = hex::encode(&data[position..position + 20]);
+        position += 20;
+
+        entries.push(TreeEntry { mode, name, hash });
+    }
+
+    Ok(entries)
+}
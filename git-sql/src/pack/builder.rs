@@ -0,0 +1,141 @@
+use core::{Result};
+use pack::{self, PackOptions};
+use pack::delta::{DeltaIndex};
+use pack::writer::{PackWriter, IndexEntry};
+
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use git2::ObjectType;
+
+/// Objects smaller than this are never worth deltifying.
+const MIN_DELTA_SIZE: usize = 50;
+
+/// An object scheduled for a pack, described before its content is loaded.
+pub struct PackEntry {
+    pub hash: String,
+    pub kind: ObjectType,
+    pub size: usize,
+    pub path: String
+}
+
+/// Hashes a path so that files with the same name sort next to each other, weighting the last characters most.
+pub fn name_hash(path: &str) -> u32 {
+    let mut hash = 0u32;
+    for byte in path.bytes() {
+        if byte.is_ascii_whitespace() {
+            continue;
+        }
+        hash = (hash >> 2).wrapping_add((byte as u32) << 24);
+    }
+    hash
+}
+
+/// Orders entries so that likely delta pairs end up within the same window.
+pub fn sort_for_deltas(entries: &mut [PackEntry]) {
+    entries.sort_by(|a, b| {
+        let a_code = pack::type_to_code(a.kind).unwrap_or(0);
+        let b_code = pack::type_to_code(b.kind).unwrap_or(0);
+        a_code.cmp(&b_code)
+            .then_with(|| name_hash(&a.path).cmp(&name_hash(&b.path)))
+            .then_with(|| b.size.cmp(&a.size))
+            .then_with(|| a.hash.cmp(&b.hash))
+    });
+}
+
+struct WindowEntry {
+    hash: String,
+    kind: ObjectType,
+    data: Vec<u8>,
+    index: Option<DeltaIndex>,
+    offset: u64,
+    depth: usize
+}
+
+/// Writes objects into a pack, deltifying each against a sliding window of preceding objects.
+pub struct DeltaPackWriter<W: Write> {
+    writer: PackWriter<W>,
+    options: PackOptions,
+    window: VecDeque<WindowEntry>
+}
+
+impl<W: Write> DeltaPackWriter<W> {
+    pub fn new(inner: W, count: u32, options: PackOptions) -> Result<DeltaPackWriter<W>> {
+        Ok(DeltaPackWriter {
+            writer: PackWriter::new(inner, count)?,
+            options,
+            window: VecDeque::new()
+        })
+    }
+
+    fn is_candidate(base: &WindowEntry, kind: ObjectType, size: usize, depth: usize) -> bool {
+        if base.kind != kind || base.depth >= depth {
+            return false;
+        }
+
+        let (small, large) = match base.data.len().cmp(&size) {
+            Ordering::Less => (base.data.len(), size),
+            _ => (size, base.data.len())
+        };
+        small * 32 >= large
+    }
+
+    pub fn add(&mut self, hash: &String, kind: ObjectType, data: Vec<u8>) -> Result<()> {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+
+        if self.options.window > 0 && data.len() >= MIN_DELTA_SIZE {
+            let mut max_size = (data.len() / 2).saturating_sub(20);
+
+            for position in (0..self.window.len()).rev() {
+                let base = &mut self.window[position];
+                if !DeltaPackWriter::<W>::is_candidate(base, kind, data.len(), self.options.depth) {
+                    continue;
+                }
+
+                if base.index.is_none() {
+                    base.index = Some(DeltaIndex::new(&base.data));
+                }
+
+                let delta = base.index.as_ref().unwrap().create_delta(&base.data, &data, max_size);
+                if let Some(delta) = delta {
+                    max_size = delta.len();
+                    best = Some((position, delta));
+                }
+            }
+        }
+
+        let (offset, depth) = match best {
+            Some((position, delta)) => {
+                let base = &self.window[position];
+                let offset = if self.options.ofs_delta {
+                    self.writer.write_ofs_delta(hash, base.offset, &delta)?
+                } else {
+                    self.writer.write_ref_delta(hash, &base.hash, &delta)?
+                };
+                (offset, base.depth + 1)
+            },
+            None => (self.writer.write_object(hash, kind, &data)?, 0)
+        };
+
+        if self.options.window > 0 {
+            self.window.push_back(WindowEntry { hash: hash.clone(), kind, data, index: None, offset, depth });
+            while self.window.len() > self.options.window {
+                self.window.pop_front();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes an object too large to load as a full object, read as it is written, and leaves it
+    /// out of the window.
+    pub fn add_from(&mut self, hash: &String, kind: ObjectType, size: usize, data: &mut dyn Read) -> Result<()> {
+        self.writer.write_object_from(hash, kind, size, data)?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<(String, Vec<IndexEntry>)> {
+        self.writer.finish()
+    }
+}
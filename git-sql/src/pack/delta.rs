@@ -0,0 +1,252 @@
+use core::{SimpleError, Result};
+
+use std::collections::HashMap;
+
+/// Reads a little-endian base-128 size from the start of a delta.
+fn read_size(delta: &[u8], position: &mut usize) -> Result<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        if *position >= delta.len() {
+            return Err(SimpleError::new("Truncated delta size."));
+        }
+
+        let byte = delta[*position];
+        *position += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+    }
+}
+
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut position = 0;
+    let base_size = read_size(delta, &mut position)?;
+    let result_size = read_size(delta, &mut position)?;
+
+    if base_size != base.len() {
+        return Err(SimpleError::new(format!(
+            "Delta base size mismatch: expected {} but base is {} bytes.", base_size, base.len()
+        )));
+    }
+
+    let mut out = Vec::with_capacity(result_size);
+
+    while position < delta.len() {
+        let op = delta[position];
+        position += 1;
+
+        if op & 0x80 != 0 {
+            let mut offset = 0usize;
+            let mut size = 0usize;
+
+            for i in 0..4 {
+                if op & (1 << i) != 0 {
+                    let byte = *delta.get(position).ok_or_else(|| SimpleError::new("Truncated delta copy."))?;
+                    offset |= (byte as usize) << (8 * i);
+                    position += 1;
+                }
+            }
+
+            for i in 0..3 {
+                if op & (0x10 << i) != 0 {
+                    let byte = *delta.get(position).ok_or_else(|| SimpleError::new("Truncated delta copy."))?;
+                    size |= (byte as usize) << (8 * i);
+                    position += 1;
+                }
+            }
+
+            if size == 0 {
+                size = 0x10000;
+            }
+
+            if offset + size > base.len() {
+                return Err(SimpleError::new("Delta copy exceeds the base object."));
+            }
+            out.extend_from_slice(&base[offset..offset + size]);
+        } else if op != 0 {
+            let size = op as usize;
+            if position + size > delta.len() {
+                return Err(SimpleError::new("Truncated delta insert."));
+            }
+            out.extend_from_slice(&delta[position..position + size]);
+            position += size;
+        } else {
+            return Err(SimpleError::new("Invalid delta opcode 0."));
+        }
+    }
+
+    if out.len() != result_size {
+        return Err(SimpleError::new(format!(
+            "Delta result size mismatch: expected {} but produced {} bytes.", result_size, out.len()
+        )));
+    }
+
+    Ok(out)
+}
+
+const BLOCK_SIZE: usize = 16;
+const HASH_MULTIPLIER: u32 = 0x01000193;
+const MAX_COPY_SIZE: usize = 0x10000;
+const MAX_INSERT_SIZE: usize = 0x7f;
+const MAX_CANDIDATES: usize = 8;
+
+fn block_hash(data: &[u8]) -> u32 {
+    let mut hash = 0u32;
+    for byte in data {
+        hash = hash.wrapping_mul(HASH_MULTIPLIER).wrapping_add(*byte as u32);
+    }
+    hash
+}
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn flush_insert(out: &mut Vec<u8>, pending: &[u8]) {
+    for chunk in pending.chunks(MAX_INSERT_SIZE) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let length = std::cmp::min(size, MAX_COPY_SIZE);
+        let mut op = 0x80u8;
+        let mut args = Vec::with_capacity(7);
+
+        for i in 0..4 {
+            let byte = ((offset >> (8 * i)) & 0xff) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                args.push(byte);
+            }
+        }
+
+        // A size of 0x10000 is encoded by omitting all size bytes.
+        if length != MAX_COPY_SIZE {
+            for i in 0..3 {
+                let byte = ((length >> (8 * i)) & 0xff) as u8;
+                if byte != 0 {
+                    op |= 0x10 << i;
+                    args.push(byte);
+                }
+            }
+        }
+
+        out.push(op);
+        out.extend_from_slice(&args);
+        offset += length;
+        size -= length;
+    }
+}
+
+/// Indexes the blocks of a base object so deltas against it can be computed repeatedly.
+pub struct DeltaIndex {
+    blocks: HashMap<u32, Vec<usize>>
+}
+
+impl DeltaIndex {
+    pub fn new(base: &[u8]) -> DeltaIndex {
+        let mut blocks: HashMap<u32, Vec<usize>> = HashMap::new();
+        let mut position = 0;
+        while position + BLOCK_SIZE <= base.len() {
+            let candidates = blocks.entry(block_hash(&base[position..position + BLOCK_SIZE])).or_default();
+            if candidates.len() < MAX_CANDIDATES {
+                candidates.push(position);
+            }
+            position += BLOCK_SIZE;
+        }
+        DeltaIndex { blocks }
+    }
+
+    /// Computes a delta producing `target` from `base`, which must be the data this index was built from.
+    ///
+    /// Returns `None` if the delta would be larger than `max_size` bytes.
+    pub fn create_delta(&self, base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        let mut out = Vec::new();
+        write_size(&mut out, base.len());
+        write_size(&mut out, target.len());
+
+        let mut pending: Vec<u8> = Vec::new();
+        let mut position = 0;
+
+        let mut power = 1u32;
+        for _ in 1..BLOCK_SIZE {
+            power = power.wrapping_mul(HASH_MULTIPLIER);
+        }
+
+        let mut hash = if target.len() >= BLOCK_SIZE { block_hash(&target[..BLOCK_SIZE]) } else { 0 };
+        let mut hash_position = 0;
+
+        while position < target.len() {
+            let mut best_offset = 0;
+            let mut best_length = 0;
+
+            if position + BLOCK_SIZE <= target.len() {
+                while hash_position < position {
+                    hash = hash.wrapping_sub((target[hash_position] as u32).wrapping_mul(power))
+                        .wrapping_mul(HASH_MULTIPLIER)
+                        .wrapping_add(target[hash_position + BLOCK_SIZE] as u32);
+                    hash_position += 1;
+                }
+
+                if let Some(candidates) = self.blocks.get(&hash) {
+                    for candidate in candidates {
+                        let mut length = 0;
+                        while candidate + length < base.len() && position + length < target.len()
+                            && base[candidate + length] == target[position + length] {
+                            length += 1;
+                        }
+
+                        if length > best_length {
+                            best_offset = *candidate;
+                            best_length = length;
+                        }
+                    }
+                }
+            }
+
+            if best_length < BLOCK_SIZE {
+                pending.push(target[position]);
+                position += 1;
+            } else {
+                let forward = best_length;
+
+                // Extend the match backwards into bytes that would otherwise be inserted.
+                while best_offset > 0 && !pending.is_empty() && base[best_offset - 1] == pending[pending.len() - 1] {
+                    best_offset -= 1;
+                    best_length += 1;
+                    pending.pop();
+                }
+
+                flush_insert(&mut out, &pending);
+                pending.clear();
+                write_copy(&mut out, best_offset, best_length);
+                position += forward;
+            }
+
+            if out.len() + pending.len() > max_size {
+                return None;
+            }
+        }
+
+        flush_insert(&mut out, &pending);
+        if out.len() > max_size {
+            return None;
+        }
+        Some(out)
+    }
+}
@@ -0,0 +1,70 @@
+use core::{SimpleError, Result};
+use pack::writer::{IndexEntry, u32_bytes};
+
+use std::io::{Write};
+
+use hex;
+use sha1;
+
+const INDEX_SIGNATURE: &[u8] = b"\xfftOc";
+const INDEX_VERSION: u32 = 2;
+
+/// Writes a version 2 pack index for the given entries and pack checksum.
+pub fn write_index<W: Write>(entries: &mut [IndexEntry], pack_checksum: &String, out: &mut W) -> Result<()> {
+    entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let mut data = Vec::with_capacity(8 + 256 * 4 + entries.len() * 28 + 40);
+    data.extend_from_slice(INDEX_SIGNATURE);
+    data.extend_from_slice(&u32_bytes(INDEX_VERSION));
+
+    let mut raw_hashes = Vec::with_capacity(entries.len());
+    for entry in entries.iter() {
+        let raw = hex::decode(&entry.hash).map_err(|_| SimpleError::new(format!("Invalid object id {}.", entry.hash)))?;
+        if raw.len() != 20 {
+            return Err(SimpleError::new(format!("Invalid object id {}.", entry.hash)));
+        }
+        raw_hashes.push(raw);
+    }
+
+    let mut fanout = [0u32; 256];
+    for raw in &raw_hashes {
+        fanout[raw[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout.iter() {
+        total += *count;
+        data.extend_from_slice(&u32_bytes(total));
+    }
+
+    for raw in &raw_hashes {
+        data.extend_from_slice(raw);
+    }
+
+    for entry in entries.iter() {
+        data.extend_from_slice(&u32_bytes(entry.crc));
+    }
+
+    let mut large_offsets = Vec::new();
+    for entry in entries.iter() {
+        if entry.offset < 0x8000_0000 {
+            data.extend_from_slice(&u32_bytes(entry.offset as u32));
+        } else {
+            data.extend_from_slice(&u32_bytes(0x8000_0000 | large_offsets.len() as u32));
+            large_offsets.push(entry.offset);
+        }
+    }
+
+    for offset in large_offsets {
+        data.extend_from_slice(&u32_bytes((offset >> 32) as u32));
+        data.extend_from_slice(&u32_bytes(offset as u32));
+    }
+
+    let checksum = hex::decode(pack_checksum).map_err(|_| SimpleError::new("Invalid pack checksum."))?;
+    data.extend_from_slice(&checksum);
+
+    let mut sha = sha1::Sha1::new();
+    sha.update(&data);
+    data.extend_from_slice(&sha.digest().bytes());
+
+    out.write_all(&data).map_err(SimpleError::from)
+}
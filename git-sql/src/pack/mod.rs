@@ -0,0 +1,54 @@
+pub mod builder;
+pub mod delta;
+pub mod index;
+pub mod reader;
+pub mod writer;
+
+use git2::ObjectType;
+
+pub const PACK_SIGNATURE: &[u8] = b"PACK";
+pub const PACK_VERSION: u32 = 2;
+
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
+
+/// Controls how objects are deltified when generating packs.
+#[derive(Clone, Copy)]
+pub struct PackOptions {
+    /// Number of preceding objects considered as delta bases; zero disables deltas.
+    pub window: usize,
+    /// Maximum length of a delta chain.
+    pub depth: usize,
+    /// Whether deltas may reference their base by offset rather than by object id.
+    pub ofs_delta: bool
+}
+
+impl PackOptions {
+    pub fn new() -> PackOptions {
+        PackOptions { window: 10, depth: 50, ofs_delta: true }
+    }
+}
+
+pub fn type_to_code(kind: ObjectType) -> Option<u8> {
+    match kind {
+        ObjectType::Commit => Some(OBJ_COMMIT),
+        ObjectType::Tree => Some(OBJ_TREE),
+        ObjectType::Blob => Some(OBJ_BLOB),
+        ObjectType::Tag => Some(OBJ_TAG),
+        _ => None
+    }
+}
+
+pub fn code_to_type(code: u8) -> Option<ObjectType> {
+    match code {
+        OBJ_COMMIT => Some(ObjectType::Commit),
+        OBJ_TREE => Some(ObjectType::Tree),
+        OBJ_BLOB => Some(ObjectType::Blob),
+        OBJ_TAG => Some(ObjectType::Tag),
+        _ => None
+    }
+}
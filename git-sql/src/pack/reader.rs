@@ -0,0 +1,391 @@
+use core::{SimpleError, Result};
+use client::GitSqlClient;
+use object::{self};
+use pack::{self, delta};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+
+use git2::ObjectType;
+
+use flate2::bufread::ZlibDecoder;
+
+use sha1;
+
+use hex;
+
+use rand;
+
+/// Reader that copies everything it reads to the spooled pack.
+struct Tee<R: Read> {
+    inner: R,
+    spool: BufWriter<File>
+}
+
+impl<R: Read> Read for Tee<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.spool.write_all(&buf[..count])?;
+        Ok(count)
+    }
+}
+
+/// Buffered pack input that tracks the stream offset and the running pack checksum.
+struct PackInput<R: Read> {
+    inner: BufReader<Tee<R>>,
+    offset: u64,
+    sha: sha1::Sha1
+}
+
+impl<R: Read> Read for PackInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = {
+            let available = self.fill_buf()?;
+            let count = std::cmp::min(available.len(), buf.len());
+            buf[..count].copy_from_slice(&available[..count]);
+            count
+        };
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<R: Read> BufRead for PackInput<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.sha.update(&self.inner.buffer()[..amount]);
+        self.offset += amount as u64;
+        self.inner.consume(amount);
+    }
+}
+
+impl<R: Read> PackInput<R> {
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0u8; 1];
+        self.read_exact(&mut byte).map_err(SimpleError::from)?;
+        Ok(byte[0])
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut bytes = [0u8; 4];
+        self.read_exact(&mut bytes).map_err(SimpleError::from)?;
+        Ok(((bytes[0] as u32) << 24) | ((bytes[1] as u32) << 16) | ((bytes[2] as u32) << 8) | (bytes[3] as u32))
+    }
+
+    /// Inflates an entry without keeping its data, returning the object id for full objects.
+    fn skip_inflated(&mut self, kind: Option<ObjectType>, size: usize) -> Result<Option<String>> {
+        let mut sink = Inflated { sha: None, size: 0 };
+        if let Some(kind) = kind {
+            let mut sha = sha1::Sha1::new();
+            sha.update(&GitSqlClient::encode_object(&kind, size, &[]));
+            sink.sha = Some(sha);
+        }
+
+        io::copy(&mut ZlibDecoder::new(&mut *self), &mut sink).map_err(SimpleError::from)?;
+        if sink.size != size {
+            return Err(SimpleError::new(format!(
+                "Pack entry inflated to {} bytes, expected {}.", sink.size, size
+            )));
+        }
+        Ok(sink.sha.map(|x| x.digest().to_string()))
+    }
+}
+
+/// Counts, and for full objects hashes, the inflated data of an entry.
+struct Inflated {
+    sha: Option<sha1::Sha1>,
+    size: usize
+}
+
+impl Write for Inflated {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref mut sha) = self.sha {
+            sha.update(buf);
+        }
+        self.size += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Temporary copy of the pack being read, removed when dropped.
+struct Spool {
+    file: File,
+    path: PathBuf
+}
+
+impl Spool {
+    fn create() -> Result<Spool> {
+        let path = env::temp_dir().join(format!("git-sql-pack-{}-{:016x}", process::id(), rand::random::<u64>()));
+        let file = OpenOptions::new().read(true).write(true).create_new(true).open(&path)
+            .map_err(SimpleError::from)?;
+        Ok(Spool { file, path })
+    }
+
+    fn read_entry(&mut self, entry: &Entry) -> Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(entry.data_offset)).map_err(SimpleError::from)?;
+
+        let mut data = Vec::with_capacity(entry.size);
+        ZlibDecoder::new(BufReader::new(&mut self.file)).read_to_end(&mut data).map_err(SimpleError::from)?;
+        if data.len() != entry.size {
+            return Err(SimpleError::new(format!(
+                "Pack entry inflated to {} bytes, expected {}.", data.len(), entry.size
+            )));
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+enum Base {
+    Full(ObjectType, String),
+    Offset(u64),
+    Hash(String)
+}
+
+/// Where an entry starts in the pack, where its compressed data starts and what it is resolved from.
+struct Entry {
+    offset: u64,
+    data_offset: u64,
+    size: usize,
+    base: Base
+}
+
+/// A delta base being resolved, with the entries that are deltas against it.
+struct Frame {
+    kind: ObjectType,
+    data: Vec<u8>,
+    children: Vec<usize>
+}
+
+/// A fully resolved object read from a pack.
+pub struct PackObject {
+    pub hash: String,
+    pub kind: ObjectType,
+    pub data: Vec<u8>
+}
+
+pub struct PackReader<R: Read> {
+    input: PackInput<R>,
+    spool: Spool,
+    by_offset: HashMap<u64, Vec<usize>>,
+    by_hash: HashMap<String, Vec<usize>>
+}
+
+impl<R: Read> PackReader<R> {
+    pub fn new(inner: R) -> Result<PackReader<R>> {
+        let spool = Spool::create()?;
+        let copy = spool.file.try_clone().map_err(SimpleError::from)?;
+        Ok(PackReader {
+            input: PackInput {
+                inner: BufReader::with_capacity(64 * 1024, Tee { inner, spool: BufWriter::new(copy) }),
+                offset: 0,
+                sha: sha1::Sha1::new()
+            },
+            spool,
+            by_offset: HashMap::new(),
+            by_hash: HashMap::new()
+        })
+    }
+
+    fn read_header(&mut self) -> Result<u32> {
+        let mut signature = [0u8; 4];
+        self.input.read_exact(&mut signature).map_err(SimpleError::from)?;
+
+        if &signature[..] != pack::PACK_SIGNATURE {
+            return Err(SimpleError::new("Invalid pack signature."));
+        }
+
+        let version = self.input.read_u32()?;
+        if version != 2 && version != 3 {
+            return Err(SimpleError::new(format!("Unsupported pack version {}.", version)));
+        }
+
+        self.input.read_u32()
+    }
+
+    fn read_entry(&mut self) -> Result<Entry> {
+        let offset = self.input.offset;
+        let mut byte = self.input.read_byte()?;
+        let code = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+
+        while byte & 0x80 != 0 {
+            byte = self.input.read_byte()?;
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let base = match code {
+            pack::OBJ_OFS_DELTA => {
+                byte = self.input.read_byte()?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = self.input.read_byte()?;
+                    distance = ((distance + 1) << 7) | ((byte & 0x7f) as u64);
+                }
+
+                if distance > offset {
+                    return Err(SimpleError::new("Delta base offset points before the pack."));
+                }
+                Base::Offset(offset - distance)
+            },
+            pack::OBJ_REF_DELTA => {
+                let mut base = [0u8; 20];
+                self.input.read_exact(&mut base).map_err(SimpleError::from)?;
+                Base::Hash(hex::encode(base))
+            },
+            _ => {
+                let kind = pack::code_to_type(code)
+                    .ok_or_else(|| SimpleError::new(format!("Unknown pack object type {}.", code)))?;
+                Base::Full(kind, String::new())
+            }
+        };
+
+        let data_offset = self.input.offset;
+        let base = match base {
+            Base::Full(kind, _) => Base::Full(kind, self.input.skip_inflated(Some(kind), size)?.unwrap()),
+            base => {
+                self.input.skip_inflated(None, size)?;
+                base
+            }
+        };
+
+        Ok(Entry { offset, data_offset, size, base })
+    }
+
+    fn verify_trailer(&mut self) -> Result<()> {
+        let expected = self.input.sha.digest().bytes();
+        let mut trailer = [0u8; 20];
+        self.input.inner.read_exact(&mut trailer).map_err(SimpleError::from)?;
+
+        if trailer != expected {
+            return Err(SimpleError::new("Pack checksum mismatch."));
+        }
+        self.input.inner.get_mut().spool.flush().map_err(SimpleError::from)
+    }
+
+    /// Reads every object in the pack and passes it to `sink` as soon as it is resolved.
+    ///
+    /// The pack is spooled to a temporary file, so that only the delta bases being resolved are held
+    /// in memory. Deltas against objects that are not in the pack (thin packs) are resolved through
+    /// `lookup`. Returns the number of objects read.
+    pub fn read_objects<L, S>(mut self, mut lookup: L, mut sink: S) -> Result<usize>
+    where
+        L: FnMut(&String) -> Result<Option<(ObjectType, Vec<u8>)>>,
+        S: FnMut(PackObject) -> Result<()>
+    {
+        let count = self.read_header()?;
+        let mut entries = Vec::with_capacity(count as usize);
+
+        for index in 0..count as usize {
+            let entry = self.read_entry()?;
+            match entry.base {
+                Base::Offset(offset) => self.by_offset.entry(offset).or_default().push(index),
+                Base::Hash(ref hash) => self.by_hash.entry(hash.clone()).or_default().push(index),
+                Base::Full(_, _) => {}
+            }
+            entries.push(entry);
+        }
+
+        self.verify_trailer()?;
+
+        let mut resolved = 0;
+        for index in 0..entries.len() {
+            if let Base::Full(kind, ref hash) = entries[index].base {
+                let data = self.spool.read_entry(&entries[index])?;
+                let children = self.take_children(Some(entries[index].offset), hash);
+                resolved += 1;
+
+                if children.is_empty() {
+                    sink(PackObject { hash: hash.clone(), kind, data })?;
+                } else {
+                    sink(PackObject { hash: hash.clone(), kind, data: data.clone() })?;
+                    resolved += self.resolve_deltas(&entries, Frame { kind, data, children }, &mut sink)?;
+                }
+            }
+        }
+
+        while !self.by_hash.is_empty() {
+            let bases: Vec<String> = self.by_hash.keys().cloned().collect();
+            let mut fetched = false;
+
+            for hash in bases {
+                if let Some((kind, data)) = lookup(&hash)? {
+                    let children = self.take_children(None, &hash);
+                    resolved += self.resolve_deltas(&entries, Frame { kind, data, children }, &mut sink)?;
+                    fetched = true;
+                }
+            }
+
+            if !fetched {
+                break;
+            }
+        }
+
+        if resolved != entries.len() {
+            return Err(SimpleError::new(format!("Pack has {} unresolved deltas.", entries.len() - resolved)));
+        }
+        Ok(resolved)
+    }
+
+    fn take_children(&mut self, offset: Option<u64>, hash: &str) -> Vec<usize> {
+        let mut children = offset.and_then(|x| self.by_offset.remove(&x)).unwrap_or_default();
+        children.extend(self.by_hash.remove(hash).unwrap_or_default());
+        children
+    }
+
+    /// Resolves the deltas against a base, and against those in turn, depth first.
+    fn resolve_deltas<S>(&mut self, entries: &[Entry], base: Frame, sink: &mut S) -> Result<usize>
+    where
+        S: FnMut(PackObject) -> Result<()>
+    {
+        let mut resolved = 0;
+        let mut stack = vec![base];
+
+        while let Some(index) = stack.last_mut().map(|x| x.children.pop()) {
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+
+            let delta_data = self.spool.read_entry(&entries[index])?;
+            let (kind, data) = {
+                let frame = stack.last().unwrap();
+                (frame.kind, delta::apply_delta(&frame.data, &delta_data)?)
+            };
+
+            let hash = object::hash_object(&kind, &data);
+            let children = self.take_children(Some(entries[index].offset), &hash);
+            resolved += 1;
+
+            if children.is_empty() {
+                sink(PackObject { hash, kind, data })?;
+            } else {
+                sink(PackObject { hash, kind, data: data.clone() })?;
+                stack.push(Frame { kind, data, children });
+            }
+        }
+
+        Ok(resolved)
+    }
+}
@@ -0,0 +1,188 @@
+use core::{SimpleError, Result};
+use pack::{self};
+
+use std::io::{Read, Write};
+use std::mem;
+
+use git2::ObjectType;
+
+use flate2::{Compression, Crc};
+use flate2::write::ZlibEncoder;
+
+use hex;
+use sha1;
+
+/// Location of an object inside a written pack, as needed for its index.
+pub struct IndexEntry {
+    pub hash: String,
+    pub offset: u64,
+    pub crc: u32
+}
+
+pub struct PackWriter<W: Write> {
+    inner: W,
+    sha: sha1::Sha1,
+    crc: Crc,
+    offset: u64,
+    remaining: u32,
+    entries: Vec<IndexEntry>
+}
+
+impl<W: Write> PackWriter<W> {
+    /// Starts a pack that will contain exactly `count` objects.
+    pub fn new(inner: W, count: u32) -> Result<PackWriter<W>> {
+        let mut writer = PackWriter {
+            inner,
+            sha: sha1::Sha1::new(),
+            crc: Crc::new(),
+            offset: 0,
+            remaining: count,
+            entries: Vec::with_capacity(count as usize)
+        };
+
+        let mut header = Vec::with_capacity(12);
+        header.extend_from_slice(pack::PACK_SIGNATURE);
+        header.extend_from_slice(&u32_bytes(pack::PACK_VERSION));
+        header.extend_from_slice(&u32_bytes(count));
+        writer.emit(&header)?;
+        Ok(writer)
+    }
+
+    fn emit(&mut self, data: &[u8]) -> Result<()> {
+        self.inner.write_all(data).map_err(SimpleError::from)?;
+        self.sha.update(data);
+        self.crc.update(data);
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    fn begin_entry(&mut self, hash: &str, code: u8, size: usize) -> Result<()> {
+        if self.remaining == 0 {
+            return Err(SimpleError::new("Pack already contains the declared number of objects."));
+        }
+        self.remaining -= 1;
+
+        self.crc.reset();
+        self.entries.push(IndexEntry { hash: hash.to_string(), offset: self.offset, crc: 0 });
+
+        let mut header = Vec::with_capacity(10);
+        let mut size = size;
+        let mut byte = (code << 4) | ((size & 0x0f) as u8);
+        size >>= 4;
+        while size != 0 {
+            header.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        header.push(byte);
+        self.emit(&header)
+    }
+
+    fn end_entry(&mut self, data: &[u8]) -> Result<()> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).map_err(SimpleError::from)?;
+        let compressed = encoder.finish().map_err(SimpleError::from)?;
+        self.emit(&compressed)?;
+
+        let crc = self.crc.sum();
+        self.entries.last_mut().unwrap().crc = crc;
+        Ok(())
+    }
+
+    /// Writes a full object, returning the offset of its entry.
+    pub fn write_object(&mut self, hash: &String, kind: ObjectType, data: &[u8]) -> Result<u64> {
+        let code = pack::type_to_code(kind)
+            .ok_or_else(|| SimpleError::new(format!("Object {} has no pack type.", hash)))?;
+        let offset = self.offset;
+        self.begin_entry(hash, code, data.len())?;
+        self.end_entry(data)?;
+        Ok(offset)
+    }
+
+    /// Writes a full object of `size` bytes read from `data`, compressing it as it is read, and
+    /// returns the offset of its entry.
+    pub fn write_object_from(&mut self, hash: &String, kind: ObjectType, size: usize, data: &mut dyn Read) -> Result<u64> {
+        let code = pack::type_to_code(kind)
+            .ok_or_else(|| SimpleError::new(format!("Object {} has no pack type.", hash)))?;
+        let offset = self.offset;
+        self.begin_entry(hash, code, size)?;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut copied = 0;
+        loop {
+            let count = data.read(&mut buffer).map_err(SimpleError::from)?;
+            if count == 0 {
+                break;
+            }
+            copied += count;
+            encoder.write_all(&buffer[..count]).map_err(SimpleError::from)?;
+            let compressed = mem::take(encoder.get_mut());
+            self.emit(&compressed)?;
+        }
+
+        if copied != size {
+            return Err(SimpleError::new(format!("Object {} has {} bytes, expected {}.", hash, copied, size)));
+        }
+
+        let compressed = encoder.finish().map_err(SimpleError::from)?;
+        self.emit(&compressed)?;
+
+        let crc = self.crc.sum();
+        self.entries.last_mut().unwrap().crc = crc;
+        Ok(offset)
+    }
+
+    /// Writes an object as a delta against an earlier entry of this pack, returning its offset.
+    pub fn write_ofs_delta(&mut self, hash: &str, base_offset: u64, delta: &[u8]) -> Result<u64> {
+        let offset = self.offset;
+        if base_offset >= offset {
+            return Err(SimpleError::new("Delta base must precede the delta in the pack."));
+        }
+
+        self.begin_entry(hash, pack::OBJ_OFS_DELTA, delta.len())?;
+
+        let mut distance = offset - base_offset;
+        let mut encoded = vec![(distance & 0x7f) as u8];
+        distance >>= 7;
+        while distance != 0 {
+            distance -= 1;
+            encoded.insert(0, 0x80 | (distance & 0x7f) as u8);
+            distance >>= 7;
+        }
+        self.emit(&encoded)?;
+
+        self.end_entry(delta)?;
+        Ok(offset)
+    }
+
+    /// Writes an object as a delta against the object with the given id, returning its offset.
+    pub fn write_ref_delta(&mut self, hash: &str, base: &String, delta: &[u8]) -> Result<u64> {
+        let offset = self.offset;
+        let raw = hex::decode(base).map_err(|_| SimpleError::new(format!("Invalid object id {}.", base)))?;
+        if raw.len() != 20 {
+            return Err(SimpleError::new(format!("Invalid object id {}.", base)));
+        }
+
+        self.begin_entry(hash, pack::OBJ_REF_DELTA, delta.len())?;
+        self.emit(&raw)?;
+        self.end_entry(delta)?;
+        Ok(offset)
+    }
+
+    /// Writes the trailing checksum, returning it along with the index entries of every object.
+    pub fn finish(mut self) -> Result<(String, Vec<IndexEntry>)> {
+        if self.remaining != 0 {
+            return Err(SimpleError::new(format!("Pack is missing {} declared objects.", self.remaining)));
+        }
+
+        let checksum = self.sha.digest().bytes();
+        self.inner.write_all(&checksum).map_err(SimpleError::from)?;
+        self.inner.flush().map_err(SimpleError::from)?;
+        Ok((hex::encode(&checksum[..]), self.entries))
+    }
+}
+
+pub fn u32_bytes(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
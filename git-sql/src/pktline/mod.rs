@@ -0,0 +1,471 @@
+use core::{SimpleError, Result};
+
+use std::io::{self, Read, Write};
+
+/// Largest payload that fits in a single pkt-line (65520 minus the 4 byte length prefix).
+pub const MAX_PACKET_DATA: usize = 65516;
+
+/// Largest payload of a side-band-64k packet, leaving room for the band byte.
+pub const MAX_SIDEBAND_DATA: usize = MAX_PACKET_DATA - 1;
+
+/// Largest payload of a (non-64k) side-band packet: 1000 bytes minus the length prefix and band byte.
+pub const MAX_SIDEBAND_SMALL_DATA: usize = 1000 - 4 - 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    Flush,
+    Delim,
+    ResponseEnd
+}
+
+impl Packet {
+    #[cfg(test)]
+    pub fn is_flush(&self) -> bool {
+        *self == Packet::Flush
+    }
+
+    /// Returns the payload of a data packet with a single trailing newline removed.
+    pub fn line(&self) -> Option<&[u8]> {
+        match self {
+            Packet::Data(data) => {
+                if data.last() == Some(&b'\n') {
+                    Some(&data[..data.len() - 1])
+                } else {
+                    Some(&data[..])
+                }
+            },
+            _ => None
+        }
+    }
+
+    /// Returns the payload of a data packet as a string, without the trailing newline.
+    pub fn text(&self) -> Option<String> {
+        self.line().map(|x| String::from_utf8_lossy(x).into_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Band {
+    Data = 1,
+    Progress = 2,
+    Error = 3
+}
+
+fn parse_length(prefix: &[u8; 4]) -> Result<usize> {
+    let text = std::str::from_utf8(prefix).map_err(|_| SimpleError::new("Invalid pkt-line length prefix."))?;
+    usize::from_str_radix(text, 16).map_err(|_| {
+        SimpleError::new(format!("Invalid pkt-line length prefix '{}'.", text))
+    })
+}
+
+pub struct PktLineReader<R: Read> {
+    inner: R
+}
+
+impl<R: Read> PktLineReader<R> {
+    pub fn new(inner: R) -> PktLineReader<R> {
+        PktLineReader { inner }
+    }
+
+    /// Reads the next packet, returning `None` when the stream ends cleanly between packets.
+    pub fn read_packet(&mut self) -> Result<Option<Packet>> {
+        let mut prefix = [0u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            let count = self.inner.read(&mut prefix[filled..]).map_err(SimpleError::from)?;
+            if count == 0 {
+                if filled == 0 {
+                    return Ok(None);
+                }
+                return Err(SimpleError::new("Unexpected end of stream inside a pkt-line length."));
+            }
+            filled += count;
+        }
+
+        match parse_length(&prefix)? {
+            0 => Ok(Some(Packet::Flush)),
+            1 => Ok(Some(Packet::Delim)),
+            2 => Ok(Some(Packet::ResponseEnd)),
+            3 => Err(SimpleError::new("Invalid pkt-line length of 3.")),
+            len => {
+                let mut data = vec![0u8; len - 4];
+                self.inner.read_exact(&mut data).map_err(SimpleError::from)?;
+                Ok(Some(Packet::Data(data)))
+            }
+        }
+    }
+
+    /// Reads the next packet, treating the end of the stream as an error.
+    pub fn expect_packet(&mut self) -> Result<Packet> {
+        match self.read_packet()? {
+            Some(packet) => Ok(packet),
+            None => Err(SimpleError::new("Unexpected end of stream while reading a pkt-line."))
+        }
+    }
+
+    /// Reads data packets as lines until a flush or delimiter packet.
+    pub fn read_section(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let packet = self.expect_packet()?;
+            match packet.text() {
+                Some(line) => lines.push(line),
+                None => return Ok(lines)
+            }
+        }
+    }
+
+    /// Reads side-band multiplexed data, passing progress messages to `progress`.
+    #[cfg(test)]
+    pub fn sideband_reader<'a, P: FnMut(&[u8])>(&'a mut self, progress: P) -> SideBandReader<'a, R, P> {
+        SideBandReader { reader: self, progress, buffer: Vec::new(), position: 0, done: false }
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+}
+
+/// A `Read` adapter over band 1 of a side-band stream.
+#[cfg(test)]
+pub struct SideBandReader<'a, R: Read + 'a, P: FnMut(&[u8])> {
+    reader: &'a mut PktLineReader<R>,
+    progress: P,
+    buffer: Vec<u8>,
+    position: usize,
+    done: bool
+}
+
+#[cfg(test)]
+impl<'a, R: Read, P: FnMut(&[u8])> Read for SideBandReader<'a, R, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position >= self.buffer.len() {
+            if self.done {
+                return Ok(0);
+            }
+
+            let mut data = match self.reader.read_packet().map_err(to_io_error)? {
+                Some(Packet::Data(data)) => data,
+                _ => {
+                    self.done = true;
+                    continue;
+                }
+            };
+
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Empty side-band packet."));
+            }
+
+            match data[0] {
+                1 => {
+                    data.remove(0);
+                    self.buffer = data;
+                    self.position = 0;
+                },
+                2 => (self.progress)(&data[1..]),
+                3 => {
+                    let message = String::from_utf8_lossy(&data[1..]).trim_end().to_string();
+                    return Err(io::Error::other(format!("remote error: {}", message)));
+                },
+                band => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown side-band {}.", band)));
+                }
+            }
+        }
+
+        let count = std::cmp::min(buf.len(), self.buffer.len() - self.position);
+        buf[..count].copy_from_slice(&self.buffer[self.position..self.position + count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+pub struct PktLineWriter<W: Write> {
+    inner: W
+}
+
+impl<W: Write> PktLineWriter<W> {
+    pub fn new(inner: W) -> PktLineWriter<W> {
+        PktLineWriter { inner }
+    }
+
+    pub fn write_packet(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() > MAX_PACKET_DATA {
+            return Err(SimpleError::new(format!("Packet of {} bytes exceeds the pkt-line limit.", data.len())));
+        }
+
+        let prefix = format!("{:04x}", data.len() + 4);
+        self.inner.write_all(prefix.as_bytes()).map_err(SimpleError::from)?;
+        self.inner.write_all(data).map_err(SimpleError::from)
+    }
+
+    /// Writes a text packet, appending a newline if it does not already end with one.
+    pub fn write_line(&mut self, line: &str) -> Result<()> {
+        if line.ends_with('\n') {
+            self.write_packet(line.as_bytes())
+        } else {
+            let mut data = Vec::with_capacity(line.len() + 1);
+            data.extend_from_slice(line.as_bytes());
+            data.push(b'\n');
+            self.write_packet(&data)
+        }
+    }
+
+    pub fn write_flush(&mut self) -> Result<()> {
+        self.inner.write_all(b"0000").map_err(SimpleError::from)
+    }
+
+    pub fn write_delim(&mut self) -> Result<()> {
+        self.inner.write_all(b"0001").map_err(SimpleError::from)
+    }
+
+    #[cfg(test)]
+    pub fn write_response_end(&mut self) -> Result<()> {
+        self.inner.write_all(b"0002").map_err(SimpleError::from)
+    }
+
+    #[cfg(test)]
+    pub fn write(&mut self, packet: &Packet) -> Result<()> {
+        match packet {
+            Packet::Data(data) => self.write_packet(data),
+            Packet::Flush => self.write_flush(),
+            Packet::Delim => self.write_delim(),
+            Packet::ResponseEnd => self.write_response_end()
+        }
+    }
+
+    /// Writes `data` on the given side-band, splitting it across as many packets as needed.
+    pub fn write_band(&mut self, band: Band, data: &[u8], max_data: usize) -> Result<()> {
+        let mut packet = Vec::with_capacity(std::cmp::min(data.len(), max_data) + 1);
+        for chunk in data.chunks(max_data) {
+            packet.clear();
+            packet.push(band as u8);
+            packet.extend_from_slice(chunk);
+            self.write_packet(&packet)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a `Write` adapter multiplexing everything written onto the given side-band.
+    #[cfg(test)]
+    pub fn sideband<'a>(&'a mut self, band: Band, large: bool) -> SideBandWriter<'a, W> {
+        let max_data = if large { MAX_SIDEBAND_DATA } else { MAX_SIDEBAND_SMALL_DATA };
+        SideBandWriter { writer: self, band, max_data }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush().map_err(SimpleError::from)
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(test)]
+pub struct SideBandWriter<'a, W: Write + 'a> {
+    writer: &'a mut PktLineWriter<W>,
+    band: Band,
+    max_data: usize
+}
+
+#[cfg(test)]
+impl<'a, W: Write> Write for SideBandWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let count = std::cmp::min(buf.len(), self.max_data);
+        self.writer.write_band(self.band, &buf[..count], self.max_data).map_err(to_io_error)?;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.get_mut().flush()
+    }
+}
+
+/// Writes progress or data through the side-band if one was negotiated, or directly otherwise.
+pub struct ProgressSink<'a, W: Write + 'a> {
+    writer: &'a mut PktLineWriter<W>,
+    sideband: Option<usize>,
+    quiet: bool
+}
+
+impl<'a, W: Write> ProgressSink<'a, W> {
+    pub fn new(writer: &'a mut PktLineWriter<W>, sideband: Option<usize>, quiet: bool) -> ProgressSink<'a, W> {
+        ProgressSink { writer, sideband, quiet }
+    }
+
+    pub fn progress(&mut self, message: &str) -> Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+
+        match self.sideband {
+            Some(max_data) => self.writer.write_band(Band::Progress, message.as_bytes(), max_data),
+            None => Ok(())
+        }
+    }
+
+    pub fn error(&mut self, message: &str) -> Result<()> {
+        match self.sideband {
+            Some(max_data) => self.writer.write_band(Band::Error, message.as_bytes(), max_data),
+            None => self.writer.write_line(&format!("ERR {}", message))
+        }
+    }
+
+    pub fn data(&mut self, data: &[u8]) -> Result<()> {
+        match self.sideband {
+            Some(max_data) => self.writer.write_band(Band::Data, data, max_data),
+            None => self.writer.get_mut().write_all(data).map_err(SimpleError::from)
+        }
+    }
+}
+
+impl<'a, W: Write> Write for ProgressSink<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data(buf).map_err(to_io_error)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.get_mut().flush()
+    }
+}
+
+fn to_io_error(error: SimpleError) -> io::Error {
+    io::Error::other(error)
+}
+
+/// Splits a first-line `name\0capabilities` payload into its parts.
+#[cfg(test)]
+pub fn split_capabilities(line: &str) -> (&str, Vec<&str>) {
+    match line.find('\0') {
+        Some(index) => {
+            let caps = line[index + 1..].split(' ').filter(|x| !x.is_empty()).collect();
+            (&line[..index], caps)
+        },
+        None => (line, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from git 2.39 cloning a two commit repository with --progress.
+    const V0_REQUEST: &[u8] = include_bytes!("testdata/upload-pack-v0.request");
+    const V0_RESPONSE: &[u8] = include_bytes!("testdata/upload-pack-v0.response");
+    const V2_REQUEST: &[u8] = include_bytes!("testdata/upload-pack-v2.request");
+    const V2_RESPONSE: &[u8] = include_bytes!("testdata/upload-pack-v2.response");
+    // The same clone from a server that did not advertise side-band-64k.
+    const SMALL_REQUEST: &[u8] = include_bytes!("testdata/upload-pack-v0-side-band.request");
+    const SMALL_RESPONSE: &[u8] = include_bytes!("testdata/upload-pack-v0-side-band.response");
+
+    fn read_packets(transcript: &[u8]) -> Vec<Packet> {
+        let mut reader = PktLineReader::new(transcript);
+        let mut packets = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            packets.push(packet);
+        }
+        packets
+    }
+
+    /// Reads a v0 response past the advertisement and the NAK, returning the pack and the progress.
+    fn read_pack(transcript: &[u8]) -> (Vec<u8>, String) {
+        let mut reader = PktLineReader::new(transcript);
+        reader.read_section().unwrap();
+        assert_eq!(reader.expect_packet().unwrap().text().unwrap(), "NAK");
+
+        let mut progress = Vec::new();
+        let mut pack = Vec::new();
+        reader.sideband_reader(|x| progress.extend_from_slice(x)).read_to_end(&mut pack).unwrap();
+        assert!(reader.read_packet().unwrap().is_none());
+        (pack, String::from_utf8(progress).unwrap())
+    }
+
+    #[test]
+    fn round_trips_transcripts() {
+        for transcript in &[V0_REQUEST, V0_RESPONSE, V2_REQUEST, V2_RESPONSE, SMALL_REQUEST, SMALL_RESPONSE] {
+            let mut writer = PktLineWriter::new(Vec::new());
+            for packet in read_packets(transcript) {
+                writer.write(&packet).unwrap();
+            }
+            assert_eq!(&writer.into_inner()[..], *transcript);
+        }
+
+        let packets = read_packets(V2_REQUEST);
+        assert_eq!(packets[0].text().unwrap(), "command=ls-refs");
+        assert_eq!(packets.iter().filter(|x| **x == Packet::Delim).count(), 2);
+        assert_eq!(packets.iter().filter(|x| x.is_flush()).count(), 2);
+
+        let packets = read_packets(V0_RESPONSE);
+        let first = packets[0].text().unwrap();
+        let (head, caps) = split_capabilities(&first);
+        assert_eq!(head, "b9f50f3656cd09eca5a46886554666026f9aa5c6 HEAD");
+        assert!(caps.contains(&"side-band-64k"));
+
+        let packets = read_packets(V0_REQUEST);
+        assert!(packets[0].text().unwrap().starts_with("want b9f50f3656cd09eca5a46886554666026f9aa5c6 "));
+        assert_eq!(packets.last().unwrap().text().unwrap(), "done");
+    }
+
+    #[test]
+    fn demultiplexes_side_bands() {
+        let (pack, progress) = read_pack(V0_RESPONSE);
+        assert!(pack.starts_with(b"PACK"));
+        let mut sha = sha1::Sha1::new();
+        sha.update(&pack[..pack.len() - 20]);
+        assert_eq!(&sha.digest().bytes()[..], &pack[pack.len() - 20..]);
+        assert!(progress.contains("Enumerating objects: 6, done."));
+
+        let (small_pack, _) = read_pack(SMALL_RESPONSE);
+        assert_eq!(small_pack, pack);
+
+        let mut reader = PktLineReader::new(&b"0012\x03access denied\n0000"[..]);
+        let error = reader.sideband_reader(|_| {}).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.to_string(), "remote error: access denied");
+    }
+
+    #[test]
+    fn splits_side_bands_like_git() {
+        let (pack, _) = read_pack(V0_RESPONSE);
+        let captured: Vec<usize> = read_packets(SMALL_RESPONSE).iter()
+            .filter_map(|x| match x {
+                Packet::Data(data) if data[0] == Band::Data as u8 => Some(data.len() + 4),
+                _ => None
+            })
+            .collect();
+        assert_eq!(captured.iter().max(), Some(&1000));
+
+        // git wrote the last byte of the pack separately.
+        for &large in &[false, true] {
+            let mut writer = PktLineWriter::new(Vec::new());
+            writer.sideband(Band::Data, large).write_all(&pack[..pack.len() - 1]).unwrap();
+            writer.sideband(Band::Data, large).write_all(&pack[pack.len() - 1..]).unwrap();
+            writer.write_flush().unwrap();
+
+            let written = writer.into_inner();
+            let sizes: Vec<usize> = read_packets(&written).iter()
+                .filter_map(|x| match x { Packet::Data(data) => Some(data.len() + 4), _ => None })
+                .collect();
+            if large {
+                assert_eq!(sizes, vec![pack.len() - 1 + 5, 6]);
+            } else {
+                assert_eq!(sizes, captured);
+            }
+
+            let mut reader = PktLineReader::new(&written[..]);
+            let mut data = Vec::new();
+            reader.sideband_reader(|_| panic!("Unexpected progress.")).read_to_end(&mut data).unwrap();
+            assert_eq!(data, pack);
+        }
+    }
+}
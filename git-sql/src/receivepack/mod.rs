@@ -0,0 +1,172 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use pktline::{PktLineReader, PktLineWriter, Band, MAX_SIDEBAND_DATA, MAX_SIDEBAND_SMALL_DATA};
+use uploadpack::{self, NULL_ID};
+
+use std::io::{Read, Write};
+
+const CAPABILITIES: &[&str] = &[
+    "report-status",
+    "delete-refs",
+    "side-band-64k",
+    "quiet",
+    "ofs-delta"
+];
+
+struct Command {
+    old: String,
+    new: String,
+    name: String
+}
+
+pub struct ReceivePack<'a> {
+    client: &'a GitSqlClient
+}
+
+impl<'a> ReceivePack<'a> {
+    pub fn new(client: &'a GitSqlClient) -> ReceivePack<'a> {
+        ReceivePack { client }
+    }
+
+    pub fn advertise<W: Write>(&self, output: &mut PktLineWriter<W>) -> Result<()> {
+        let refs: Vec<(String, String)> = uploadpack::advertised_refs(self.client)?
+            .into_iter()
+            .filter(|x| x.0 != "HEAD" && !x.0.ends_with("^{}"))
+            .collect();
+
+        let mut caps: Vec<String> = CAPABILITIES.iter().map(|x| x.to_string()).collect();
+        caps.push(uploadpack::agent());
+        let caps = caps.join(" ");
+
+        if refs.is_empty() {
+            output.write_line(&format!("{} capabilities^{{}}\0{}", NULL_ID, caps))?;
+        }
+
+        for (index, (name, target)) in refs.iter().enumerate() {
+            if index == 0 {
+                output.write_line(&format!("{} {}\0{}", target, name, caps))?;
+            } else {
+                output.write_line(&format!("{} {}", target, name))?;
+            }
+        }
+
+        output.write_flush()?;
+        output.flush()
+    }
+
+    pub fn serve<R: Read, W: Write>(&self, input: &mut PktLineReader<R>, output: &mut PktLineWriter<W>) -> Result<()> {
+        let mut commands = Vec::new();
+        let mut caps: Vec<String> = Vec::new();
+
+        loop {
+            let line = match input.read_packet()? {
+                Some(packet) => match packet.text() {
+                    Some(line) => line,
+                    None => break
+                },
+                None => return Ok(())
+            };
+
+            let command_text = match line.find('\0') {
+                Some(index) => {
+                    if commands.is_empty() {
+                        caps = line[index + 1..].split(' ').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect();
+                    }
+                    &line[..index]
+                },
+                None => &line[..]
+            };
+
+            let parts: Vec<&str> = command_text.splitn(3, ' ').collect();
+            if parts.len() != 3 {
+                return Err(SimpleError::new(format!("Malformed ref update command: {}", command_text)));
+            }
+
+            commands.push(Command {
+                old: parts[0].to_string(),
+                new: parts[1].to_string(),
+                name: parts[2].to_string()
+            });
+        }
+
+        if commands.is_empty() {
+            return Ok(());
+        }
+
+        let unpack_result = if commands.iter().any(|x| x.new != NULL_ID) {
+            self.unpack(input.get_mut())
+        } else {
+            Ok(0)
+        };
+
+        let mut report = PktLineWriter::new(Vec::new());
+        match unpack_result {
+            Ok(_) => report.write_line("unpack ok")?,
+            Err(ref error) => report.write_line(&format!("unpack {}", error))?
+        }
+
+        for command in &commands {
+            let status = if unpack_result.is_err() {
+                Some(String::from("unpacker error"))
+            } else {
+                self.update_ref(command)?
+            };
+
+            match status {
+                None => report.write_line(&format!("ok {}", command.name))?,
+                Some(reason) => report.write_line(&format!("ng {} {}", command.name, reason))?
+            }
+        }
+        report.write_flush()?;
+
+        if !caps.iter().any(|x| x == "report-status") {
+            return output.flush();
+        }
+
+        let report = report.into_inner();
+        if caps.iter().any(|x| x == "side-band-64k") {
+            output.write_band(Band::Data, &report, MAX_SIDEBAND_DATA)?;
+            output.write_flush()?;
+        } else if caps.iter().any(|x| x == "side-band") {
+            output.write_band(Band::Data, &report, MAX_SIDEBAND_SMALL_DATA)?;
+            output.write_flush()?;
+        } else {
+            output.get_mut().write_all(&report).map_err(SimpleError::from)?;
+        }
+        output.flush()
+    }
+
+    /// Reads the pushed pack, verifying and inserting every object it contains.
+    fn unpack<R: Read>(&self, input: &mut R) -> Result<usize> {
+        self.client.import_pack(input)
+    }
+
+    /// Applies a single ref update, returning the reason if it was rejected.
+    ///
+    /// The reference is only moved if it still points at the old target the client sent, so of
+    /// two concurrent pushes from the same old target, one is rejected.
+    fn update_ref(&self, command: &Command) -> Result<Option<String>> {
+        if !command.name.starts_with("refs/") || command.name.ends_with("^{}") {
+            return Ok(Some(String::from("funny refname")));
+        }
+
+        if command.new != NULL_ID && !self.is_connected(&command.new)? {
+            return Ok(Some(String::from("missing necessary objects")));
+        }
+
+        let old = if command.old == NULL_ID { None } else { Some(&command.old) };
+        let new = if command.new == NULL_ID { None } else { Some(&command.new) };
+        if !self.client.compare_and_set_ref(&command.name, old, new, "push")? {
+            return Ok(Some(String::from("stale info")));
+        }
+        Ok(None)
+    }
+
+    /// Whether `target` and every object it reaches are stored, walking only the commits the
+    /// current references of the repository do not already reach.
+    fn is_connected(&self, target: &String) -> Result<bool> {
+        let tips: Vec<String> = self.client.list_refs()?.into_iter().map(|x| x.1).collect();
+        let commits = self.client.new_commits(std::slice::from_ref(target), &tips, &[], &[])?;
+        self.client.is_closure_complete(std::slice::from_ref(target), &commits)
+    }
+}
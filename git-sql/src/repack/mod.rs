@@ -0,0 +1,139 @@
+use core::{Result};
+use client::{GitSqlClient};
+use revwalk::{ObjectWalk};
+use storage::{StorageOptions};
+use pack::builder::{self, PackEntry};
+use pack::delta::{self, DeltaIndex};
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+
+use git2::ObjectType;
+
+/// Number of objects loaded from the database at once.
+const REPACK_READ_CHUNK: usize = 256;
+
+/// Blobs smaller than this are never worth storing as deltas.
+const MIN_DELTA_SIZE: usize = 50;
+
+/// The outcome of a repack.
+pub struct RepackStats {
+    /// Number of blobs considered.
+    pub blobs: usize,
+    /// Number of blobs newly stored as deltas.
+    pub deltified: usize,
+    /// Bytes of content no longer stored.
+    pub saved: usize
+}
+
+struct WindowEntry {
+    hash: String,
+    raw: Vec<u8>,
+    index: Option<DeltaIndex>,
+    depth: usize
+}
+
+fn is_candidate(base: &WindowEntry, size: usize, max_depth: usize) -> bool {
+    if base.depth >= max_depth {
+        return false;
+    }
+
+    let (small, large) = match base.raw.len().cmp(&size) {
+        Ordering::Less => (base.raw.len(), size),
+        _ => (size, base.raw.len())
+    };
+    small * 32 >= large
+}
+
+/// Stores blobs reachable from the repository's references as deltas against similar blobs.
+///
+/// Blobs are ordered like a pack, so each is compared against a window of preceding blobs with
+/// similar paths. Only fully stored blobs that no other object depends on are rewritten, and
+/// chains never grow beyond `options.delta_depth`.
+pub fn repack(client: &GitSqlClient, options: &StorageOptions) -> Result<RepackStats> {
+    let tips: Vec<String> = client.list_refs()?.into_iter()
+        .filter(|x| x.0 != "HEAD" && !x.0.ends_with("^{}"))
+        .map(|x| x.1)
+        .collect();
+    let mut walk = ObjectWalk::new(client);
+    walk.push(&tips)?;
+    let objects = walk.into_objects();
+
+    let paths: HashMap<&String, &String> = objects.iter().map(|x| (&x.0, &x.1)).collect();
+    let hashes: Vec<String> = objects.iter().map(|x| x.0.clone()).collect();
+
+    let mut entries = Vec::new();
+    let mut stored: HashMap<String, (usize, usize, bool)> = HashMap::new();
+    for chunk in hashes.chunks(REPACK_READ_CHUNK) {
+        for (hash, kind, size) in client.read_object_sizes(chunk)? {
+            if kind == ObjectType::Blob {
+                let path = paths[&hash].clone();
+                entries.push(PackEntry { hash, kind, size, path });
+            }
+        }
+    }
+    let blobs: Vec<String> = entries.iter().map(|x| x.hash.clone()).collect();
+    for chunk in blobs.chunks(REPACK_READ_CHUNK) {
+        for (hash, depth, size, is_base) in client.read_delta_info(chunk)? {
+            stored.insert(hash, (depth, size, is_base));
+        }
+    }
+    entries.retain(|x| stored.contains_key(&x.hash));
+    builder::sort_for_deltas(&mut entries);
+
+    let mut stats = RepackStats { blobs: entries.len(), deltified: 0, saved: 0 };
+    if options.delta_window == 0 || options.delta_depth == 0 {
+        return Ok(stats);
+    }
+
+    let mut window: VecDeque<WindowEntry> = VecDeque::new();
+    for chunk in entries.chunks(REPACK_READ_CHUNK) {
+        let ordered: Vec<String> = chunk.iter().map(|x| x.hash.clone()).collect();
+        let mut loaded: HashMap<String, Vec<u8>> = client.read_raw_objects(&ordered)?.into_iter().collect();
+
+        for hash in &ordered {
+            let raw = loaded.remove(hash).unwrap();
+            let (mut depth, stored_size, is_base) = stored[hash];
+
+            // Rewriting a delta or a base would change the chains of other objects.
+            let is_target = depth == 0 && !is_base && raw.len() >= MIN_DELTA_SIZE;
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            if is_target {
+                let mut max_size = ((raw.len() / 2).saturating_sub(20)).min(stored_size);
+
+                for position in (0..window.len()).rev() {
+                    let base = &mut window[position];
+                    if !is_candidate(base, raw.len(), options.delta_depth) {
+                        continue;
+                    }
+
+                    if base.index.is_none() {
+                        base.index = Some(DeltaIndex::new(&base.raw));
+                    }
+
+                    if let Some(delta) = base.index.as_ref().unwrap().create_delta(&base.raw, &raw, max_size) {
+                        max_size = delta.len();
+                        best = Some((position, delta));
+                    }
+                }
+            }
+
+            if let Some((position, delta)) = best {
+                let base = &window[position];
+                if delta::apply_delta(&base.raw, &delta)? == raw
+                    && client.store_delta(hash, &base.hash, base.depth + 1, &delta)? {
+                    depth = base.depth + 1;
+                    stats.deltified += 1;
+                    stats.saved += stored_size - delta.len();
+                }
+            }
+
+            window.push_back(WindowEntry { hash: hash.clone(), raw, index: None, depth });
+            while window.len() > options.delta_window {
+                window.pop_front();
+            }
+        }
+    }
+
+    Ok(stats)
+}
@@ -0,0 +1,304 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use object::{self};
+
+use std::collections::{HashMap, HashSet};
+
+use git2::ObjectType;
+
+const COMMIT_READ_CHUNK: usize = 1000;
+
+/// A partial clone filter, which omits objects found while walking trees.
+///
+/// Objects that are asked for directly are never filtered.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFilter {
+    None,
+    BlobNone,
+    BlobLimit(usize),
+    TreeDepth(usize)
+}
+
+impl ObjectFilter {
+    /// Parses a filter spec such as `blob:none`, `blob:limit=1m` or `tree:0`.
+    pub fn parse(spec: &str) -> Result<ObjectFilter> {
+        if spec == "blob:none" {
+            return Ok(ObjectFilter::BlobNone);
+        }
+
+        if let Some(value) = spec.strip_prefix("blob:limit=") {
+            let (digits, scale) = match value.chars().last().map(|x| x.to_ascii_lowercase()) {
+                Some('k') => (&value[..value.len() - 1], 1024),
+                Some('m') => (&value[..value.len() - 1], 1024 * 1024),
+                Some('g') => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+                _ => (value, 1)
+            };
+
+            return digits.parse::<usize>()
+                .map(|x| ObjectFilter::BlobLimit(x.saturating_mul(scale)))
+                .map_err(|_| SimpleError::new(format!("Invalid filter: {}", spec)));
+        }
+
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return depth.parse::<usize>()
+                .map(ObjectFilter::TreeDepth)
+                .map_err(|_| SimpleError::new(format!("Invalid filter: {}", spec)));
+        }
+
+        Err(SimpleError::new(format!("Unsupported filter: {}", spec)))
+    }
+}
+
+/// Computes the set of objects a client needs, given what it wants and what it already has.
+pub struct ObjectWalk<'a> {
+    client: &'a GitSqlClient,
+    hidden: Vec<String>,
+    excluded: HashSet<String>,
+    seen: HashSet<String>,
+    client_shallow: HashSet<String>,
+    shallow_boundary: HashSet<String>,
+    filter: ObjectFilter,
+    tree_depths: HashMap<String, usize>,
+    objects: Vec<(String, String)>
+}
+
+impl<'a> ObjectWalk<'a> {
+    pub fn new(client: &'a GitSqlClient) -> ObjectWalk<'a> {
+        ObjectWalk {
+            client,
+            hidden: Vec::new(),
+            excluded: HashSet::new(),
+            seen: HashSet::new(),
+            client_shallow: HashSet::new(),
+            shallow_boundary: HashSet::new(),
+            filter: ObjectFilter::None,
+            tree_depths: HashMap::new(),
+            objects: Vec::new()
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: ObjectFilter) {
+        self.filter = filter;
+    }
+
+    /// Treats commits as having no parents: `client_shallow` while hiding what the client has,
+    /// and `boundary` while collecting what it needs.
+    pub fn set_shallow(&mut self, client_shallow: &[String], boundary: &[String]) {
+        self.client_shallow = client_shallow.iter().cloned().collect();
+        self.shallow_boundary = boundary.iter().cloned().collect();
+    }
+
+    /// Marks the given commits and all of their ancestors as already present on the client.
+    ///
+    /// Their history is only walked by `push`, and only as far as the commits it collects.
+    pub fn hide(&mut self, haves: &[String]) -> Result<()> {
+        self.hidden.extend_from_slice(haves);
+        Ok(())
+    }
+
+    /// Marks a tree and everything below it as already present on the client.
+    fn exclude_tree(&mut self, tree: &str) -> Result<()> {
+        let mut stack = vec![tree.to_string()];
+
+        while let Some(hash) = stack.pop() {
+            if !self.excluded.insert(hash.clone()) {
+                continue;
+            }
+
+            for entry in self.client.read_tree_entries(&hash)? {
+                if entry.is_gitlink() {
+                    continue;
+                }
+
+                if entry.is_tree() {
+                    stack.push(entry.hash);
+                } else {
+                    self.excluded.insert(entry.hash);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add(&mut self, hash: &str, path: &str) -> bool {
+        if self.excluded.contains(hash) || !self.seen.insert(hash.to_string()) {
+            return false;
+        }
+
+        self.objects.push((hash.to_string(), path.to_string()));
+        true
+    }
+
+    /// Adds the given objects and everything reachable from them that is not hidden.
+    pub fn push(&mut self, wants: &[String]) -> Result<()> {
+        let mut tips = Vec::new();
+        let mut trees = Vec::new();
+
+        for want in wants {
+            let mut current = want.clone();
+            loop {
+                let (kind, data) = self.client.read_object(&current)?;
+                match kind {
+                    ObjectType::Tag => {
+                        self.add(&current, "");
+                        current = object::parse_tag(&data)?.object;
+                    },
+                    ObjectType::Commit => {
+                        tips.push(current);
+                        break;
+                    },
+                    ObjectType::Tree => {
+                        trees.push((current, true));
+                        break;
+                    },
+                    ObjectType::Blob => {
+                        self.add(&current, "");
+                        break;
+                    },
+                    _ => return Err(SimpleError::new(format!("Unknown object type for {}.", current)))
+                }
+            }
+        }
+
+        let client_shallow: Vec<String> = self.client_shallow.iter().cloned().collect();
+        let shallow_boundary: Vec<String> = self.shallow_boundary.iter().cloned().collect();
+        let hashes = self.client.new_commits(&tips, &self.hidden, &shallow_boundary, &client_shallow)?;
+        let new: HashSet<&String> = hashes.iter().collect();
+
+        let mut commits = HashMap::new();
+        for chunk in hashes.chunks(COMMIT_READ_CHUNK) {
+            for (hash, tree, parents) in self.client.read_commit_graph(chunk)? {
+                commits.insert(hash, (tree, parents));
+            }
+        }
+
+        // Hidden commits next to the new ones, whose trees the client is assumed to have.
+        let mut boundary: Vec<String> = tips.iter().filter(|x| !new.contains(x)).cloned().collect();
+        for hash in &hashes {
+            let (tree, parents) = commits.remove(hash)
+                .ok_or_else(|| SimpleError::new(format!("Commit {} is missing from the commit graph.", hash)))?;
+            if !self.shallow_boundary.contains(hash) {
+                boundary.extend(parents.into_iter().filter(|x| !new.contains(x)));
+            }
+            self.add(hash, "");
+            trees.push((tree, false));
+        }
+
+        boundary.sort();
+        boundary.dedup();
+        for chunk in boundary.chunks(COMMIT_READ_CHUNK) {
+            for (_, tree, _) in self.client.read_commit_graph(chunk)? {
+                self.exclude_tree(&tree)?;
+            }
+        }
+
+        for (tree, wanted) in trees {
+            self.push_tree(&tree, wanted)?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether a tree reached at `depth` still needs to be expanded.
+    ///
+    /// With a tree depth filter, a tree first reached deep in the hierarchy is expanded again
+    /// when it is later reached closer to the root.
+    fn visit_tree(&mut self, hash: &str, path: &str, depth: usize) -> bool {
+        if let ObjectFilter::TreeDepth(_) = self.filter {
+            if self.excluded.contains(hash) {
+                return false;
+            }
+
+            if let Some(previous) = self.tree_depths.get(hash) {
+                if *previous <= depth {
+                    return false;
+                }
+            }
+            self.tree_depths.insert(hash.to_string(), depth);
+            self.add(hash, path);
+            return true;
+        }
+
+        self.add(hash, path)
+    }
+
+    /// Adds a tree and what it contains, as far as the filter allows. A tree that was `wanted`
+    /// directly is added even when the filter omits all trees.
+    fn push_tree(&mut self, tree: &str, wanted: bool) -> Result<()> {
+        if self.filter == ObjectFilter::TreeDepth(0) && !wanted {
+            return Ok(());
+        }
+
+        let mut stack = vec![(tree.to_string(), String::new(), 0)];
+
+        while let Some((hash, path, depth)) = stack.pop() {
+            if !self.visit_tree(&hash, &path, depth) {
+                continue;
+            }
+
+            let child_depth = depth + 1;
+            let include_children = match self.filter {
+                ObjectFilter::TreeDepth(limit) => child_depth < limit,
+                _ => true
+            };
+            if !include_children {
+                continue;
+            }
+
+            let mut blobs = Vec::new();
+            for entry in self.client.read_tree_entries(&hash)? {
+                if entry.is_gitlink() {
+                    continue;
+                }
+
+                let name = String::from_utf8_lossy(&entry.name);
+                let child_path = if path.is_empty() { name.into_owned() } else { format!("{}/{}", path, name) };
+                if entry.is_tree() {
+                    stack.push((entry.hash, child_path, child_depth));
+                } else if self.filter != ObjectFilter::BlobNone && !self.excluded.contains(&entry.hash) {
+                    blobs.push((entry.hash, child_path));
+                }
+            }
+
+            if let ObjectFilter::BlobLimit(limit) = self.filter {
+                let hashes: Vec<String> = blobs.iter()
+                    .filter(|x| !self.seen.contains(&x.0))
+                    .map(|x| x.0.clone())
+                    .collect::<HashSet<String>>()
+                    .into_iter()
+                    .collect();
+                let small: HashSet<String> = self.client.read_object_sizes(&hashes)?
+                    .into_iter()
+                    .filter(|x| x.2 < limit)
+                    .map(|x| x.0)
+                    .collect();
+                blobs.retain(|x| small.contains(&x.0));
+            }
+
+            for (blob, blob_path) in blobs {
+                self.add(&blob, &blob_path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the collected objects, each paired with the path it was first reached at.
+    pub fn into_objects(self) -> Vec<(String, String)> {
+        self.objects
+    }
+}
+
+/// Peels tags until a non-tag object is reached, returning it if it is a commit.
+pub fn peel_to_commit(client: &GitSqlClient, hash: &str) -> Result<Option<String>> {
+    let mut current = hash.to_string();
+    loop {
+        let (kind, data) = client.read_object(&current)?;
+        match kind {
+            ObjectType::Tag => current = object::parse_tag(&data)?.object,
+            ObjectType::Commit => return Ok(Some(current)),
+            _ => return Ok(None)
+        }
+    }
+}
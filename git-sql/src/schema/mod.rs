@@ -0,0 +1,160 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+
+/// A numbered schema change from `db/migrations`, applied once per database.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str
+}
+
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+/// Types, functions, views and caches, which are replaced on every migration.
+const DEFINITIONS: &str = include_str!(concat!(env!("OUT_DIR"), "/git.rs.sql"));
+
+/// The schema version this build of git-sql works with.
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map(|x| x.version).unwrap_or(0)
+}
+
+/// Returns the definitions SQL, optionally without the sections that need PL/Python.
+pub fn definitions(no_python: bool) -> String {
+    if !no_python {
+        return DEFINITIONS.to_string();
+    }
+
+    strip_python(DEFINITIONS)
+}
+
+fn strip_python(sql: &str) -> String {
+    let mut used = String::new();
+    let mut inside_python_section = false;
+    for line in sql.lines() {
+        if line.contains("<PYTHON ONLY>") {
+            inside_python_section = true;
+        } else if line.contains("</PYTHON ONLY>") {
+            inside_python_section = false;
+            continue;
+        }
+
+        if !inside_python_section {
+            used.push_str(line);
+            used.push('\n');
+        }
+    }
+    used
+}
+
+/// Migrations newer than the schema of the client's database.
+pub fn pending(client: &GitSqlClient) -> Result<Vec<&'static Migration>> {
+    let current = client.schema_version()?;
+    if current > latest_version() {
+        return Err(newer_schema(current));
+    }
+    Ok(MIGRATIONS.iter().filter(|x| x.version > current).collect())
+}
+
+/// Applies pending migrations in order, then reloads the definitions.
+///
+/// References of a database created before repositories were tracked are assigned to `repository`,
+/// and offloaded files found in its offload-directory are recorded for the objects without one.
+pub fn migrate(client: &GitSqlClient, repository: &String, no_python: bool) -> Result<Vec<&'static Migration>> {
+    let migrations = pending(client)?;
+
+    for migration in &migrations {
+        client.apply_migration(migration.version, migration.name, migration.sql, repository)
+            .map_err(|x| SimpleError::new(format!("Migration {:04}_{} failed: {}", migration.version, migration.name, x)))?;
+    }
+
+    client.run_sql(&definitions(no_python))?;
+    client.update_commit_graph()?;
+    client.update_tree_entries()?;
+    client.update_offload_paths()?;
+    Ok(migrations)
+}
+
+/// Fails unless the database schema is exactly the version this build expects.
+pub fn check(client: &GitSqlClient) -> Result<()> {
+    let current = client.schema_version()?;
+    let latest = latest_version();
+
+    if current < latest {
+        return Err(SimpleError::new(format!(
+            "Database schema is at version {}, but version {} is required. Run 'git-sql migrate' to upgrade it.",
+            current, latest
+        )));
+    }
+
+    if current > latest {
+        return Err(newer_schema(current));
+    }
+    Ok(())
+}
+
+fn newer_schema(current: i32) -> SimpleError {
+    SimpleError::new(format!(
+        "Database schema version {} is newer than this git-sql supports ({}).",
+        current, latest_version()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+    use postgres::{Connection, TlsMode};
+
+    const INFLATE: &str = include_str!("../../../db/functions/specials/03_git_inflate.sql");
+
+    // Streams written by zlib itself, with a level of 0, the fixed strategy, the default strategy,
+    // and a full flush between two halves of the input.
+    const STORED: &[u8] = include_bytes!("testdata/stored.zz");
+    const FIXED: &[u8] = include_bytes!("testdata/fixed.zz");
+    const DYNAMIC: &[u8] = include_bytes!("testdata/dynamic.zz");
+    const BLOCKS: &[u8] = include_bytes!("testdata/blocks.zz");
+
+    /// Connects to the database in GIT_SQL_TEST_POSTGRES_URL, skipping the test when it is unset.
+    fn connect() -> Option<Connection> {
+        let url = env::var("GIT_SQL_TEST_POSTGRES_URL").ok()?;
+        Some(Connection::connect(url, TlsMode::None).unwrap())
+    }
+
+    #[test]
+    fn inflates_zlib_streams() {
+        let conn = match connect() {
+            Some(conn) => conn,
+            None => return
+        };
+        let transact = conn.transaction().unwrap();
+        transact.batch_execute(&strip_python(INFLATE)).unwrap();
+
+        for &(name, stream, block_type) in &[("stored", STORED, 0), ("fixed", FIXED, 1), ("dynamic", DYNAMIC, 2), ("blocks", BLOCKS, 2)] {
+            assert_eq!((stream[2] >> 1) & 3, block_type, "first block type of {}", name);
+
+            let mut expected = Vec::new();
+            ZlibDecoder::new(stream).read_to_end(&mut expected).unwrap();
+
+            let rows = transact.query("SELECT git_inflate($1)", &[&stream]).unwrap();
+            let inflated: Vec<u8> = rows.get(0).get(0);
+            assert_eq!(inflated, expected, "inflated {}", name);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_streams() {
+        let conn = match connect() {
+            Some(conn) => conn,
+            None => return
+        };
+        let transact = conn.transaction().unwrap();
+        transact.batch_execute(&strip_python(INFLATE)).unwrap();
+
+        let reserved: &[u8] = &[0x78, 0x9c, 0x07];
+        let error = transact.query("SELECT git_inflate($1)", &[&reserved]).unwrap_err();
+        assert!(error.to_string().contains("Invalid deflate block type"), "{}", error);
+    }
+}
@@ -1,31 +1,160 @@
-use core::{SimpleError};
-use client::{GitSqlClient};
+use core::{SimpleError, Result};
+use client::{GitSqlClient, ObjectStream, LargeObjectReader, LargeObjectWriter};
+use pktline::{PktLineReader, PktLineWriter};
+use uploadpack::{self, UploadPack};
+use uploadpack::v2::{UploadPackV2};
+use receivepack::{ReceivePack};
+use revwalk::{ObjectWalk};
+use pack::index;
+use storage;
 
 use iron::prelude::*;
 use iron::{BeforeMiddleware, typemap};
 use iron::status;
 use iron::mime::Mime;
+use iron::headers::{CacheControl, CacheDirective};
+use iron::response::{WriteBody, BodyReader};
 
 use router::Router;
 
-use std::io::{Write};
+use std::io::{self, Cursor, Read, Write};
 
 use git2::{ObjectType};
 
 use flate2::Compression;
-use flate2::write::ZlibEncoder;
+use flate2::read::{GzDecoder, ZlibEncoder};
+
+const DEFAULT_MAX_REQUEST_SIZE: usize = 512 * 1024 * 1024;
 
 impl typemap::Key for GitSqlServer {
     type Value = GitSqlServer;
 }
 
 pub struct GitSqlServer {
-    loader: fn(String) -> Option<GitSqlClient>
+    loader: fn(String) -> Option<GitSqlClient>,
+    receive_pack: bool,
+    max_request_size: usize
+}
+
+/// Streams the result of a stateless smart protocol request directly into the response.
+struct ServiceBody {
+    client: GitSqlClient,
+    service: String,
+    version: u32,
+    request: Vec<u8>
+}
+
+impl WriteBody for ServiceBody {
+    fn write_body(&mut self, res: &mut dyn Write) -> io::Result<()> {
+        let mut input = PktLineReader::new(&self.request[..]);
+        let mut output = PktLineWriter::new(res);
+
+        let result = if self.service == "git-receive-pack" {
+            ReceivePack::new(&self.client).serve(&mut input, &mut output)
+        } else if self.version == 2 {
+            UploadPackV2::new(&self.client).serve(&mut input, &mut output)
+        } else {
+            UploadPack::new(&self.client, true).serve(&mut input, &mut output)
+        };
+        result.map_err(io::Error::other)
+    }
+}
+
+fn service_of(query: Option<&str>) -> Option<String> {
+    query?.split('&')
+        .find(|x| x.starts_with("service="))
+        .map(|x| x["service=".len()..].to_string())
+}
+
+/// Reads the protocol version requested through the `Git-Protocol` header.
+fn protocol_version_of(req: &Request) -> u32 {
+    req.headers.get_raw("Git-Protocol")
+        .map(|x| x.iter().map(|v| uploadpack::protocol_version(&String::from_utf8_lossy(v))).max().unwrap_or(0))
+        .unwrap_or(0)
+}
+
+/// Finds or generates the pack of every object reachable from the current references.
+///
+/// Packs are keyed by a digest of the references, so a pack is regenerated only after a ref changes.
+/// Requests made while a pack is generated wait for it rather than generating it again.
+fn ensure_dumb_pack(client: &GitSqlClient) -> Result<String> {
+    let digest = client.refs_digest()?;
+    if let Some(name) = client.find_cached_pack(&digest)? {
+        return Ok(name);
+    }
+
+    client.lock_packs()?;
+    let result = client.find_cached_pack(&digest).and_then(|found| match found {
+        Some(name) => Ok(name),
+        None => generate_dumb_pack(client, &digest)
+    });
+    client.unlock_packs()?;
+    result
+}
+
+/// Writes the pack of every object reachable from the references to a large object.
+///
+/// Offloaded objects are left out, as they are too large to be worth packing and are still
+/// served as loose objects.
+fn generate_dumb_pack(client: &GitSqlClient, digest: &String) -> Result<String> {
+    let tips: Vec<String> = client.list_refs()?.into_iter()
+        .filter(|x| x.0 != "HEAD" && !x.0.ends_with("^{}"))
+        .map(|x| x.1)
+        .collect();
+    let mut walk = ObjectWalk::new(client);
+    walk.push(&tips)?;
+    let mut objects = walk.into_objects();
+
+    let hashes: Vec<String> = objects.iter().map(|x| x.0.clone()).collect();
+    let offloaded = client.filter_offloaded(&hashes)?;
+    objects.retain(|x| !offloaded.contains(&x.0));
+    let hashes: Vec<String> = objects.iter().map(|x| x.0.clone()).collect();
+
+    let oid = client.create_large_object()?;
+    let mut pack = LargeObjectWriter::new(client, oid);
+    let result = client.write_pack(&objects, &client.pack_options(), &mut pack)
+        .and_then(|(checksum, mut entries)| {
+            let mut idx = Vec::new();
+            index::write_index(&mut entries, &checksum, &mut idx)?;
+            client.store_cached_pack(&checksum, digest, oid, pack.size(), &idx, &hashes)?;
+            Ok(checksum)
+        });
+
+    if result.is_err() {
+        client.unlink_large_object(oid)?;
+    }
+    result
+}
+
+/// Fails with not found unless the references of the repository reach the object, as the
+/// database may hold objects of other repositories.
+fn check_reachable(client: &GitSqlClient, hash: &String) -> IronResult<()> {
+    let result = client.filter_reachable(std::slice::from_ref(hash));
+    if result.is_err() {
+        return Err(IronError::new(result.err().unwrap(), status::InternalServerError));
+    }
+    if !result.unwrap().contains(hash) {
+        return Err(IronError::new(SimpleError::new("Object not found."), status::NotFound));
+    }
+    Ok(())
 }
 
 impl GitSqlServer {
     pub fn new(loader: fn(String) -> Option<GitSqlClient>) -> GitSqlServer {
-        return GitSqlServer { loader }
+        GitSqlServer { loader, receive_pack: false, max_request_size: DEFAULT_MAX_REQUEST_SIZE }
+    }
+
+    pub fn set_receive_pack(&mut self, enabled: bool) {
+        self.receive_pack = enabled;
+    }
+
+    /// Limits the size of smart HTTP request bodies, after decompressing them.
+    pub fn set_max_request_size(&mut self, size: usize) {
+        self.max_request_size = size;
+    }
+
+    fn is_service_enabled(&self, service: &str) -> bool {
+        service == "git-upload-pack" || (service == "git-receive-pack" && self.receive_pack)
     }
 
     pub fn download_object(&self, repo: &String, hash: &String) -> IronResult<Response> {
@@ -34,15 +163,28 @@ impl GitSqlServer {
             return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
         }
         let client = maybe_client.unwrap();
-        let result = client.read_raw_object(hash);
+        check_reachable(&client, hash)?;
+
+        let result = client.read_stored_object(hash);
         if result.is_err() {
             return Err(IronError::new(result.err().unwrap(), status::BadRequest));
         }
-        let data = result.unwrap();
-        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
-        e.write_all(&data).unwrap();
+        let (encoding, data) = result.unwrap();
         let mime = "application/octet-stream".parse::<Mime>().unwrap();
-        Ok(Response::with((mime, status::Ok, e.finish().unwrap())))
+
+        // Compressed objects are stored exactly as loose object files.
+        if encoding == storage::ENCODING_ZLIB {
+            return Ok(Response::with((mime, status::Ok, data)));
+        }
+
+        // Offloaded objects may be too large to hold in memory, so they are compressed as they are sent.
+        let result = ObjectStream::open(client, hash);
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::InternalServerError));
+        }
+        let stream = result.unwrap();
+        let raw = Cursor::new(stream.header()).chain(stream);
+        Ok(Response::with((mime, status::Ok, BodyReader(ZlibEncoder::new(raw, Compression::default())))))
     }
 
     pub fn list_refs(&self, repo: &String) -> IronResult<Response> {
@@ -51,14 +193,17 @@ impl GitSqlServer {
             return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
         }
         let client = maybe_client.unwrap();
-        let result = client.list_refs();
+        let result = uploadpack::advertised_refs(&client);
         if result.is_err() {
             return Err(IronError::new(result.err().unwrap(), status::BadRequest));
         }
-        
+
         let refs = result.unwrap();
         let mut output = String::new();
         for (name, target) in refs {
+            if name == "HEAD" {
+                continue;
+            }
             output.push_str(&target);
             output.push_str("\t");
             output.push_str(&name);
@@ -67,19 +212,142 @@ impl GitSqlServer {
         Ok(Response::with((status::Ok, output)))
     }
 
+    pub fn read_head(&self, repo: &String) -> IronResult<Response> {
+        let maybe_client = (self.loader)(repo.to_string());
+        if maybe_client.is_none() {
+            return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
+        }
+        let client = maybe_client.unwrap();
+        let result = client.read_ref(&"HEAD".to_string());
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::NotFound));
+        }
+
+        let target = result.unwrap();
+        let output = if target.starts_with("refs/") {
+            format!("ref: {}\n", target)
+        } else {
+            format!("{}\n", target)
+        };
+        Ok(Response::with((status::Ok, output)))
+    }
+
+    pub fn list_packs(&self, repo: &String) -> IronResult<Response> {
+        let maybe_client = (self.loader)(repo.to_string());
+        if maybe_client.is_none() {
+            return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
+        }
+        let client = maybe_client.unwrap();
+        let result = ensure_dumb_pack(&client);
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::InternalServerError));
+        }
+
+        let output = format!("P pack-{}.pack\n\n", result.unwrap());
+        let mut response = Response::with((status::Ok, output));
+        response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        Ok(response)
+    }
+
+    pub fn download_pack(&self, repo: &String, file: &str) -> IronResult<Response> {
+        let maybe_client = (self.loader)(repo.to_string());
+        if maybe_client.is_none() {
+            return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
+        }
+        let client = maybe_client.unwrap();
+
+        let (name, is_index, mime) = if file.starts_with("pack-") && file.ends_with(".pack") {
+            (&file[5..file.len() - 5], false, "application/x-git-packed-objects")
+        } else if file.starts_with("pack-") && file.ends_with(".idx") {
+            (&file[5..file.len() - 4], true, "application/x-git-packed-objects-toc")
+        } else {
+            return Err(IronError::new(SimpleError::new("Pack not found."), status::NotFound));
+        };
+
+        let mime = mime.parse::<Mime>().unwrap();
+        if is_index {
+            let result = client.read_cached_pack_index(&name.to_string());
+            if result.is_err() {
+                return Err(IronError::new(result.err().unwrap(), status::NotFound));
+            }
+            return Ok(Response::with((mime, status::Ok, result.unwrap())));
+        }
+
+        let result = client.read_cached_pack(&name.to_string());
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::NotFound));
+        }
+        let (oid, size) = result.unwrap();
+        Ok(Response::with((mime, status::Ok, BodyReader(LargeObjectReader::new(client, oid, 0, size)))))
+    }
+
+    pub fn advertise_service(&self, repo: &String, service: &String, version: u32) -> IronResult<Response> {
+        let maybe_client = (self.loader)(repo.to_string());
+        if maybe_client.is_none() {
+            return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
+        }
+        let client = maybe_client.unwrap();
+
+        if !self.is_service_enabled(service) {
+            return Err(IronError::new(SimpleError::new("Unsupported Service."), status::Forbidden));
+        }
+
+        let mut output = PktLineWriter::new(Vec::new());
+        let result = output.write_line(&format!("# service={}", service))
+            .and_then(|_| output.write_flush())
+            .and_then(|_| {
+                if service == "git-receive-pack" {
+                    ReceivePack::new(&client).advertise(&mut output)
+                } else if version == 2 {
+                    UploadPackV2::new(&client).advertise(&mut output)
+                } else {
+                    UploadPack::new(&client, true).advertise(&mut output)
+                }
+            });
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::InternalServerError));
+        }
+
+        let mime = format!("application/x-{}-advertisement", service).parse::<Mime>().unwrap();
+        let mut response = Response::with((mime, status::Ok, output.into_inner()));
+        response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        Ok(response)
+    }
+
+    /// Runs a stateless smart protocol service. `actor` is recorded in the reflog for any reference it changes.
+    pub fn run_service(&self, repo: &String, service: &String, version: u32, request: Vec<u8>, actor: Option<String>) -> IronResult<Response> {
+        let maybe_client = (self.loader)(repo.to_string());
+        if maybe_client.is_none() {
+            return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
+        }
+        let mut client = maybe_client.unwrap();
+        client.set_actor(actor);
+
+        if !self.is_service_enabled(service) {
+            return Err(IronError::new(SimpleError::new("Unsupported Service."), status::Forbidden));
+        }
+
+        let mime = format!("application/x-{}-result", service).parse::<Mime>().unwrap();
+        let body: Box<dyn WriteBody> = Box::new(ServiceBody { client, service: service.clone(), version, request });
+        let mut response = Response::with((mime, status::Ok, body));
+        response.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+        Ok(response)
+    }
+
     pub fn fetch_content_of(&self, repo: &String, hash: &String) -> IronResult<Response> {
         let maybe_client = (self.loader)(repo.to_string());
         if maybe_client.is_none() {
             return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
         }
         let client = maybe_client.unwrap();
-        let result = client.read_object(hash);
+        check_reachable(&client, hash)?;
+
+        let result = ObjectStream::open(client, hash);
         if result.is_err() {
             return Err(IronError::new(result.err().unwrap(), status::BadRequest));
         }
 
-        let (_, data) = result.unwrap();
-        Ok(Response::with((status::Ok, data)))
+        Ok(Response::with((status::Ok, BodyReader(result.unwrap()))))
     }
 
     pub fn view_content_of(&self, repo: &String, path: &String, at: &String, again: bool) -> IronResult<Response> {
@@ -88,12 +356,13 @@ impl GitSqlServer {
             return Err(IronError::new(SimpleError::new("Unknown Repository."), status::BadRequest));
         }
         let client = maybe_client.unwrap();
-        let result = client.read_file_at(path, at);
+        let result = client.find_file_at(path, at);
         if result.is_err() {
             return Err(IronError::new(result.err().unwrap(), status::NotFound));
         }
 
-        let (otype, data) = result.unwrap();
+        let (otype, hash) = result.unwrap();
+        check_reachable(&client, &hash)?;
 
         if otype == ObjectType::Tree {
             if again {
@@ -108,7 +377,12 @@ impl GitSqlServer {
             return self.view_content_of(repo, &again_path, at, true);
         }
 
-        Ok(Response::with((status::Ok, data)))
+        let result = ObjectStream::open(client, &hash);
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::InternalServerError));
+        }
+
+        Ok(Response::with((status::Ok, BodyReader(result.unwrap()))))
     }
 
     fn handle_dl_object(req: &mut Request) -> IronResult<Response> {
@@ -138,9 +412,84 @@ impl GitSqlServer {
         let rt = req.extensions.get::<Router>().unwrap();
         let ref repo = rt.find("repo").unwrap();
         let server = req.extensions.get::<GitSqlServer>().unwrap();
+
+        if let Some(service) = service_of(req.url.query()) {
+            return server.advertise_service(&(*repo).into(), &service, protocol_version_of(req));
+        }
         server.list_refs(&(*repo).into())
     }
 
+    fn handle_head(req: &mut Request) -> IronResult<Response> {
+        let rt = req.extensions.get::<Router>().unwrap();
+        let repo = rt.find("repo").unwrap();
+        let server = req.extensions.get::<GitSqlServer>().unwrap();
+
+        server.read_head(&repo.into())
+    }
+
+    fn handle_info_packs(req: &mut Request) -> IronResult<Response> {
+        let rt = req.extensions.get::<Router>().unwrap();
+        let repo = rt.find("repo").unwrap();
+        let server = req.extensions.get::<GitSqlServer>().unwrap();
+
+        server.list_packs(&repo.into())
+    }
+
+    fn handle_info_alternates(_: &mut Request) -> IronResult<Response> {
+        // Objects are never borrowed from another repository.
+        Ok(Response::with((status::Ok, "")))
+    }
+
+    fn handle_dl_pack(req: &mut Request) -> IronResult<Response> {
+        let rt = req.extensions.get::<Router>().unwrap();
+        let repo = rt.find("repo").unwrap();
+        let file = rt.find("file").unwrap();
+        let server = req.extensions.get::<GitSqlServer>().unwrap();
+
+        server.download_pack(&repo.into(), file)
+    }
+
+    /// Reads a smart HTTP request body, which the response is only written from once the
+    /// handler has returned, so it is buffered up to the configured maximum size.
+    fn read_request_body(req: &mut Request) -> IronResult<Vec<u8>> {
+        let limit = req.extensions.get::<GitSqlServer>().unwrap().max_request_size;
+        let gzipped = req.headers.get_raw("Content-Encoding")
+            .map(|x| x.iter().any(|v| v.as_slice() == b"gzip"))
+            .unwrap_or(false);
+
+        let mut body = Vec::new();
+        let result = if gzipped {
+            GzDecoder::new(&mut req.body).take(limit as u64 + 1).read_to_end(&mut body)
+        } else {
+            (&mut req.body).take(limit as u64 + 1).read_to_end(&mut body)
+        };
+
+        if result.is_err() {
+            return Err(IronError::new(result.err().unwrap(), status::BadRequest));
+        }
+        if body.len() > limit {
+            return Err(IronError::new(SimpleError::new("Request body too large."), status::PayloadTooLarge));
+        }
+        Ok(body)
+    }
+
+    fn handle_upload_pack(req: &mut Request) -> IronResult<Response> {
+        let body = GitSqlServer::read_request_body(req)?;
+        let rt = req.extensions.get::<Router>().unwrap();
+        let repo = rt.find("repo").unwrap();
+        let server = req.extensions.get::<GitSqlServer>().unwrap();
+        server.run_service(&repo.into(), &"git-upload-pack".into(), protocol_version_of(req), body, None)
+    }
+
+    fn handle_receive_pack(req: &mut Request) -> IronResult<Response> {
+        let body = GitSqlServer::read_request_body(req)?;
+        let rt = req.extensions.get::<Router>().unwrap();
+        let repo = rt.find("repo").unwrap();
+        let server = req.extensions.get::<GitSqlServer>().unwrap();
+        let actor = format!("http {}", req.remote_addr.ip());
+        server.run_service(&repo.into(), &"git-receive-pack".into(), 0, body, Some(actor))
+    }
+
     fn handle_view_content(req: &mut Request) -> IronResult<Response> {
         let rt = req.extensions.get::<Router>().unwrap();
         let ref repo = rt.find("repo").unwrap();
@@ -153,6 +502,13 @@ impl GitSqlServer {
     
     fn add_to_router(&self, router: &mut Router) {
         router.get("/:repo/info/refs", GitSqlServer::handle_info_refs, "info-refs");
+        router.get("/:repo/HEAD", GitSqlServer::handle_head, "head");
+        router.get("/:repo/objects/info/packs", GitSqlServer::handle_info_packs, "info-packs");
+        router.get("/:repo/objects/info/alternates", GitSqlServer::handle_info_alternates, "info-alternates");
+        router.get("/:repo/objects/info/http-alternates", GitSqlServer::handle_info_alternates, "info-http-alternates");
+        router.get("/:repo/objects/pack/:file", GitSqlServer::handle_dl_pack, "pack-download");
+        router.post("/:repo/git-upload-pack", GitSqlServer::handle_upload_pack, "upload-pack");
+        router.post("/:repo/git-receive-pack", GitSqlServer::handle_receive_pack, "receive-pack");
         router.get("/:repo/objects/:ha/:hb", GitSqlServer::handle_dl_object, "object-download");
         router.get("/:repo/blobs/:hash", GitSqlServer::handle_raw_content, "blob-download");
         router.get("/:repo/view/*path", GitSqlServer::handle_view_content, "view-content");
@@ -165,7 +521,7 @@ impl GitSqlServer {
     }
 
     pub fn clone(&self) -> GitSqlServer {
-        return GitSqlServer::new(self.loader)
+        GitSqlServer { loader: self.loader, receive_pack: self.receive_pack, max_request_size: self.max_request_size }
     }
 }
 
@@ -0,0 +1,96 @@
+use core::{SimpleError, Result, repo_name_of};
+use client::{GitSqlClient};
+use pktline::{PktLineReader, PktLineWriter};
+use uploadpack::{UploadPack};
+use uploadpack::v2::{UploadPackV2};
+use receivepack::{ReceivePack};
+
+use std::io::{self, BufReader, BufWriter};
+
+/// A git command requested through SSH, as found in `SSH_ORIGINAL_COMMAND`.
+pub struct ShellCommand {
+    pub service: String,
+    pub repo: String
+}
+
+impl ShellCommand {
+    /// Parses `git-upload-pack 'repo'`, `git-receive-pack 'repo'` and their `git <service>` forms.
+    pub fn parse(command: &str) -> Result<ShellCommand> {
+        let command = command.trim();
+        let (program, rest) = match command.find(' ') {
+            Some(index) => (&command[..index], command[index + 1..].trim()),
+            None => return Err(SimpleError::new(format!("Unsupported command: {}", command)))
+        };
+
+        let (service, argument) = if program == "git" {
+            match rest.find(' ') {
+                Some(index) => (format!("git-{}", &rest[..index]), rest[index + 1..].trim()),
+                None => return Err(SimpleError::new(format!("Unsupported command: {}", command)))
+            }
+        } else {
+            (program.to_string(), rest)
+        };
+
+        if service != "git-upload-pack" && service != "git-receive-pack" {
+            return Err(SimpleError::new(format!("Unsupported command: {}", command)));
+        }
+
+        let path = unquote(argument)?;
+        Ok(ShellCommand { service, repo: repo_name_of(&path) })
+    }
+
+    /// Runs the command against the repository over standard input and output.
+    ///
+    /// `version` is the protocol version requested by the client through `GIT_PROTOCOL`. Pushes are
+    /// always accepted, as sshd has already authenticated the user.
+    pub fn run(&self, client: &GitSqlClient, version: u32) -> Result<()> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        let mut input = PktLineReader::new(BufReader::new(stdin.lock()));
+        let mut output = PktLineWriter::new(BufWriter::new(stdout.lock()));
+
+        if self.service == "git-receive-pack" {
+            let receive_pack = ReceivePack::new(client);
+            receive_pack.advertise(&mut output)?;
+            receive_pack.serve(&mut input, &mut output)
+        } else if version == 2 {
+            let upload_pack = UploadPackV2::new(client);
+            upload_pack.advertise(&mut output)?;
+            upload_pack.serve(&mut input, &mut output)
+        } else {
+            let upload_pack = UploadPack::new(client, false);
+            upload_pack.advertise(&mut output)?;
+            upload_pack.serve(&mut input, &mut output)
+        }
+    }
+}
+
+/// Removes the shell quoting git applies to the repository argument.
+fn unquote(argument: &str) -> Result<String> {
+    if !argument.starts_with('\'') {
+        if argument.contains(|x: char| x.is_whitespace() || x == '\'' || x == '"') {
+            return Err(SimpleError::new(format!("Unsupported repository argument: {}", argument)));
+        }
+        return Ok(argument.to_string());
+    }
+
+    let mut result = String::new();
+    let mut quoted = false;
+    let mut chars = argument.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => quoted = !quoted,
+            '\\' if !quoted => match chars.next() {
+                Some(escaped) => result.push(escaped),
+                None => return Err(SimpleError::new("Trailing backslash in repository argument."))
+            },
+            _ if !quoted => return Err(SimpleError::new(format!("Unsupported repository argument: {}", argument))),
+            _ => result.push(c)
+        }
+    }
+
+    if quoted {
+        return Err(SimpleError::new("Unterminated quote in repository argument."));
+    }
+    Ok(result)
+}
@@ -0,0 +1,283 @@
+use core::{SimpleError, Result};
+use pack::delta;
+
+use std::collections::{HashMap};
+use std::io::{Read, Write};
+use std::path::{PathBuf};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use git2::ObjectType;
+
+use sha1;
+
+/// Objects stored as `"<type> <size>\0<data>"`, verified by the database.
+pub const ENCODING_RAW: &str = "raw";
+/// Objects stored as a zlib stream of the raw form, exactly like a loose object file.
+pub const ENCODING_ZLIB: &str = "zlib";
+/// Objects stored as a delta against the raw form of their base object.
+pub const ENCODING_DELTA: &str = "delta";
+/// Objects offloaded to `pg_largeobject`, with only their header stored in the row.
+pub const ENCODING_LARGE_OBJECT: &str = "largeobject";
+/// Objects offloaded to a file in the offload directory, with only their header stored in the row.
+pub const ENCODING_FILE: &str = "file";
+
+/// Where objects above the offload threshold are stored.
+#[derive(Clone)]
+pub enum Offload {
+    LargeObject,
+    /// A directory holding the raw form of each object at `<first two hex digits>/<rest of hash>`.
+    Directory(PathBuf)
+}
+
+/// How new objects are laid out in the `objects` table.
+#[derive(Clone)]
+pub struct StorageOptions {
+    /// Whether objects are stored zlib-compressed.
+    pub compress: bool,
+    /// Number of preceding objects considered as delta bases by repack; zero disables deltas.
+    pub delta_window: usize,
+    /// Maximum number of deltas applied to reconstruct a stored object.
+    pub delta_depth: usize,
+    /// Raw size above which objects are offloaded out of the `objects` table.
+    pub offload_threshold: Option<usize>,
+    pub offload: Offload
+}
+
+impl StorageOptions {
+    pub fn new() -> StorageOptions {
+        StorageOptions {
+            compress: false,
+            delta_window: 10,
+            delta_depth: 10,
+            offload_threshold: None,
+            offload: Offload::LargeObject
+        }
+    }
+
+    pub fn should_offload(&self, raw_size: usize) -> bool {
+        self.offload_threshold.map(|x| raw_size > x).unwrap_or(false)
+    }
+
+    /// The path of an object offloaded to the offload directory.
+    pub fn offload_path(&self, hash: &String) -> Result<PathBuf> {
+        match self.offload {
+            Offload::Directory(ref directory) if hash.len() > 2 => Ok(directory.join(&hash[..2]).join(&hash[2..])),
+            Offload::Directory(_) => Err(SimpleError::new(format!("Invalid object hash {}.", hash))),
+            Offload::LargeObject => Err(SimpleError::new(format!(
+                "Object {} is stored in an offload directory, but offload-directory is not configured.", hash
+            )))
+        }
+    }
+
+    /// The file of an object offloaded to an offload directory, as recorded in its row, or in the
+    /// configured offload directory for rows that do not record it.
+    pub fn offloaded_file(&self, hash: &String, path: Option<String>) -> Result<PathBuf> {
+        match path {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => self.offload_path(hash)
+        }
+    }
+}
+
+/// Whether rows with this encoding hold only the object header, with the object stored elsewhere.
+pub fn is_offloaded(encoding: &str) -> bool {
+    encoding == ENCODING_LARGE_OBJECT || encoding == ENCODING_FILE
+}
+
+/// Fails unless the raw object hashes to `hash`.
+pub fn verify(hash: &String, raw: &[u8]) -> Result<()> {
+    let mut sha = sha1::Sha1::new();
+    sha.update(raw);
+    let actual = sha.digest().to_string();
+    if actual != *hash {
+        return Err(SimpleError::new(format!("Expected hash to be {}, but the object hashes to {}", hash, actual)));
+    }
+    Ok(())
+}
+
+/// Encodes a raw object for storage, returning the encoding and the bytes to store.
+///
+/// The database can only check the hash of raw rows, so compressed objects are verified here.
+pub fn encode(hash: &String, raw: Vec<u8>, options: &StorageOptions) -> Result<(String, Vec<u8>)> {
+    if !options.compress {
+        return Ok((ENCODING_RAW.to_string(), raw));
+    }
+
+    verify(hash, &raw)?;
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&raw).map_err(SimpleError::from)?;
+    Ok((ENCODING_ZLIB.to_string(), encoder.finish().map_err(SimpleError::from)?))
+}
+
+/// Decodes stored bytes back into the raw `"<type> <size>\0<data>"` form.
+pub fn decode(encoding: &str, stored: Vec<u8>) -> Result<Vec<u8>> {
+    match encoding {
+        ENCODING_RAW => Ok(stored),
+        ENCODING_ZLIB => {
+            let mut raw = Vec::new();
+            ZlibDecoder::new(&stored[..]).read_to_end(&mut raw).map_err(SimpleError::from)?;
+            Ok(raw)
+        },
+        ENCODING_DELTA => Err(SimpleError::new("A delta object cannot be decoded without its base.")),
+        ENCODING_LARGE_OBJECT | ENCODING_FILE => Err(SimpleError::new("An offloaded object must be read from where it is stored.")),
+        _ => Err(SimpleError::new(format!("Unknown object encoding {}.", encoding)))
+    }
+}
+
+/// Reconstructs a raw object from a delta chain, ordered from the full base to the object itself.
+pub fn decode_chain(chain: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>> {
+    let mut links = chain.into_iter();
+    let (encoding, stored) = links.next().ok_or_else(|| SimpleError::new("Object not found."))?;
+    let mut raw = decode(&encoding, stored)?;
+
+    for (encoding, stored) in links {
+        if encoding != ENCODING_DELTA {
+            return Err(SimpleError::new(format!("Expected a delta in an object chain, found {}.", encoding)));
+        }
+        raw = delta::apply_delta(&raw, &stored)?;
+    }
+    Ok(raw)
+}
+
+/// The encoding, stored bytes and delta base of an object, as read from its row.
+pub type StoredObject = (String, Vec<u8>, Option<String>);
+
+/// The encoding and stored bytes of an object, with the large object or file it is offloaded to.
+pub type ObjectLocation = (String, Vec<u8>, Option<u32>, Option<String>);
+
+/// Reconstructs a raw object from its row and the rows of its delta bases. Every object decoded
+/// along the way is kept in `resolved`, so bases shared by several objects are decoded once.
+pub fn resolve(hash: &String, rows: &HashMap<String, StoredObject>, resolved: &mut HashMap<String, Vec<u8>>) -> Result<Vec<u8>> {
+    let mut links = Vec::new();
+    let mut current = hash;
+    let mut raw = loop {
+        if let Some(raw) = resolved.get(current) {
+            break raw.clone();
+        }
+
+        let (encoding, stored, base) = rows.get(current)
+            .ok_or_else(|| SimpleError::new(format!("Object {} not found.", current)))?;
+        match *base {
+            Some(ref base) if encoding == ENCODING_DELTA => {
+                links.push(current);
+                current = base;
+            },
+            Some(_) => return Err(SimpleError::new(format!("Expected a delta in an object chain, found {}.", encoding))),
+            None => {
+                let raw = decode(encoding, stored.clone())?;
+                resolved.insert(current.clone(), raw.clone());
+                break raw;
+            }
+        }
+    };
+
+    for link in links.into_iter().rev() {
+        raw = delta::apply_delta(&raw, &rows[link].1)?;
+        resolved.insert(link.clone(), raw.clone());
+    }
+    Ok(raw)
+}
+
+fn parse_header(header: &[u8]) -> Result<(ObjectType, usize)> {
+    let text = String::from_utf8_lossy(header);
+    let mut parts = text.splitn(2, ' ');
+    let kind = parts.next().and_then(ObjectType::from_str)
+        .ok_or_else(|| SimpleError::new(format!("Invalid object header: {}", text)))?;
+    let size = parts.next().and_then(|x| x.parse::<usize>().ok())
+        .ok_or_else(|| SimpleError::new(format!("Invalid object header: {}", text)))?;
+    Ok((kind, size))
+}
+
+/// Splits a raw object into its type and content.
+pub fn split(mut raw: Vec<u8>) -> Result<(ObjectType, Vec<u8>)> {
+    let end = raw.iter().position(|x| *x == 0)
+        .ok_or_else(|| SimpleError::new("Object header is not terminated."))?;
+    let (kind, _) = parse_header(&raw[..end])?;
+    raw.drain(..end + 1);
+    Ok((kind, raw))
+}
+
+/// Reads the type and size from the start of a stored object, which may be truncated.
+pub fn read_header(encoding: &str, stored: &[u8]) -> Result<(ObjectType, usize)> {
+    let mut reader: Box<dyn Read> = match encoding {
+        ENCODING_RAW | ENCODING_LARGE_OBJECT | ENCODING_FILE => Box::new(stored),
+        ENCODING_ZLIB => Box::new(ZlibDecoder::new(stored)),
+        _ => return Err(SimpleError::new(format!("Unknown object encoding {}.", encoding)))
+    };
+
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if reader.read(&mut byte).map_err(SimpleError::from)? == 0 {
+            return Err(SimpleError::new("Object header is not terminated."));
+        }
+        if byte[0] == 0 {
+            return parse_header(&header);
+        }
+        header.push(byte[0]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pack::delta::{DeltaIndex};
+
+    fn blob(text: &str) -> Vec<u8> {
+        format!("blob {}\0{}", text.len(), text).into_bytes()
+    }
+
+    fn hash_of(raw: &[u8]) -> String {
+        let mut sha = sha1::Sha1::new();
+        sha.update(raw);
+        sha.digest().to_string()
+    }
+
+    fn delta_of(base: &[u8], target: &[u8]) -> Vec<u8> {
+        DeltaIndex::new(base).create_delta(base, target, target.len()).unwrap()
+    }
+
+    #[test]
+    fn resolves_delta_chains() {
+        let text = "a line long enough to be matched by the delta index\n".repeat(20);
+        let base = blob(&text);
+        let middle = blob(&format!("{}one more line\n", text));
+        let top = blob(&format!("first line\n{}one more line\n", text));
+        let (base_hash, middle_hash, top_hash) = (hash_of(&base), hash_of(&middle), hash_of(&top));
+
+        let mut options = StorageOptions::new();
+        options.compress = true;
+        let (encoding, stored) = encode(&base_hash, base.clone(), &options).unwrap();
+
+        let mut rows: HashMap<String, StoredObject> = HashMap::new();
+        rows.insert(base_hash.clone(), (encoding, stored, None));
+        rows.insert(middle_hash.clone(), (ENCODING_DELTA.to_string(), delta_of(&base, &middle), Some(base_hash.clone())));
+        rows.insert(top_hash.clone(), (ENCODING_DELTA.to_string(), delta_of(&middle, &top), Some(middle_hash.clone())));
+
+        let mut resolved = HashMap::new();
+        assert_eq!(resolve(&top_hash, &rows, &mut resolved).unwrap(), top);
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(resolve(&middle_hash, &rows, &mut HashMap::new()).unwrap(), middle);
+
+        let chain = vec![
+            rows[&base_hash].clone(),
+            rows[&middle_hash].clone(),
+            rows[&top_hash].clone()
+        ].into_iter().map(|(encoding, stored, _)| (encoding, stored)).collect();
+        assert_eq!(decode_chain(chain).unwrap(), top);
+    }
+
+    #[test]
+    fn rejects_broken_chains() {
+        let mut rows: HashMap<String, StoredObject> = HashMap::new();
+        rows.insert("a".to_string(), (ENCODING_DELTA.to_string(), Vec::new(), Some("b".to_string())));
+        assert!(resolve(&"a".to_string(), &rows, &mut HashMap::new()).is_err());
+
+        rows.insert("b".to_string(), (ENCODING_RAW.to_string(), blob("b"), Some("c".to_string())));
+        assert!(resolve(&"a".to_string(), &rows, &mut HashMap::new()).is_err());
+    }
+}
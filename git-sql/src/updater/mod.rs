@@ -11,6 +11,7 @@ use r2d2;
 use r2d2_postgres;
 
 use std::sync::Arc;
+use std::fs;
 use std::io;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
@@ -102,6 +103,7 @@ impl<'a> RepositoryUpdater<'a> {
     }
 
     pub fn update_objects_chunked(&mut self, repo: &Repository) -> Result<()> {
+        let storage_options = self.client.storage_options();
         let mut pool = jobsteal::make_pool(10).map_err(|x| SimpleError::from(x))?;
         let cman = r2d2_postgres::PostgresConnectionManager::new(self.client.url(), r2d2_postgres::TlsMode::None).map_err(|x| SimpleError::from(x))?;
         let cpool = r2d2::Pool::new(cman).map_err(|x| SimpleError::from(x))?;
@@ -118,6 +120,7 @@ impl<'a> RepositoryUpdater<'a> {
             let id = index;
             let cpool = cpool.clone();
             let completed_objects = completed_objects.clone();
+            let storage_options = storage_options.clone();
             pool.submit(move || {
                 let repo = Repository::open(rpath).map_err(|x| SimpleError::from(x)).unwrap();
                 let odb = repo.odb().map_err(|x| SimpleError::from(x)).unwrap();
@@ -129,7 +132,7 @@ impl<'a> RepositoryUpdater<'a> {
                     let kind = obj.kind();
                     let size = obj.len();
                     let data = obj.data();
-                    GitSqlClient::insert_object_transaction(&transact, &hash, &kind, size, data).unwrap();
+                    GitSqlClient::insert_object_transaction(&transact, hash, &kind, size, data, &storage_options).unwrap();
                 }
                 transact.commit().unwrap();
                 let completed_count = completed_objects.fetch_add(chunk.len(), Ordering::SeqCst);
@@ -142,6 +145,7 @@ impl<'a> RepositoryUpdater<'a> {
     }
 
     pub fn update_objects_fixed_workers(&mut self, repo: &Repository, workers: usize) -> Result<()> {
+        let storage_options = self.client.storage_options();
         let url = self.client.url();
         let hashes = self.client.diff_object_list_direct()?;
         let count = hashes.len();
@@ -163,6 +167,7 @@ impl<'a> RepositoryUpdater<'a> {
             let rpath = rpath.clone();
             let rpo = Repository::open(rpath).map_err(|x| SimpleError::from(x)).unwrap();
             let url = url.clone();
+            let storage_options = storage_options.clone();
 
             let mut pb = mb.create_bar(chunk.len() as u64);
 
@@ -181,7 +186,7 @@ impl<'a> RepositoryUpdater<'a> {
                     let size = obj.len();
                     let data = obj.data();
 
-                    GitSqlClient::insert_object_indirect(&conn, &hash, &kind, size, data).unwrap();
+                    GitSqlClient::insert_object_indirect(&conn, &hash, &kind, size, data, &storage_options).unwrap();
                     pb.inc();
                 }
                 pb.finish_print(&format!("worker {} : done", worker));
@@ -194,6 +199,7 @@ impl<'a> RepositoryUpdater<'a> {
     }
 
     pub fn update_objects_concurrent(&mut self, repo: &Repository) -> Result<()> {
+        let storage_options = self.client.storage_options();
         let mut pool = jobsteal::make_pool(10).map_err(|x| SimpleError::from(x))?;
         let cman = r2d2_postgres::PostgresConnectionManager::new(self.client.url(), r2d2_postgres::TlsMode::None).map_err(|x| SimpleError::from(x))?;
         let cpool = r2d2::Pool::new(cman).map_err(|x| SimpleError::from(x))?;
@@ -208,6 +214,7 @@ impl<'a> RepositoryUpdater<'a> {
                 let cpool = cpool.clone();
                 let completed_objects = completed_objects.clone();
                 let rpath = rpath.clone();
+                let storage_options = storage_options.clone();
                 scope.submit(move || {
                     let rpo = Repository::open(rpath).map_err(|x| SimpleError::from(x)).unwrap();
                     let conn = cpool.get().unwrap();
@@ -217,7 +224,7 @@ impl<'a> RepositoryUpdater<'a> {
                     let kind = obj.kind();
                     let size = obj.len();
                     let data = obj.data();
-                    GitSqlClient::insert_object_indirect(&conn, &hash, &kind, size, data).unwrap();
+                    GitSqlClient::insert_object_indirect(&conn, &hash, &kind, size, data, &storage_options).unwrap();
                     let completed_count = completed_objects.fetch_add(1, Ordering::SeqCst);
                     if completed_count % 100 == 0 {
                         let percent = (completed_count as f64 / total_count as f64) * 100.0;
@@ -263,20 +270,12 @@ impl<'a> RepositoryUpdater<'a> {
             target = rf.target().unwrap().to_string();
         }
 
-        let peeled = rf.target_peel();
-        if peeled.is_some() {
-            let mut peeled_name = name.clone();
-            peeled_name.push_str("^{}");
-            self.client.set_ref(
-                &peeled_name,
-                &peeled.unwrap().to_string()
-            )?;
-        }
-
-        let did_update = self.client.set_ref(
-            &name,
-            &target
-        )?;
+        // Peeled rows are derived from the tags in the database rather than from the local repository.
+        let did_update = if rf.symbolic_target().is_none() {
+            self.client.set_ref_peeled(&name, &target, "update")?
+        } else {
+            self.client.set_ref(&name, &target, "update")?
+        };
 
         if did_update {
             println!("{} updated to {}", name, target);
@@ -285,6 +284,18 @@ impl<'a> RepositoryUpdater<'a> {
         Ok(())
     }
 
+    /// Records the boundary commits of a shallow local repository, whose parents are not imported.
+    pub fn update_shallow(&mut self, repo: &Repository) -> Result<()> {
+        if !repo.is_shallow() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(repo.path().join("shallow")).map_err(SimpleError::from)?;
+        let hashes: Vec<String> = contents.lines().map(|x| x.trim().to_string()).filter(|x| !x.is_empty()).collect();
+        self.client.add_shallow(&hashes)?;
+        Ok(())
+    }
+
     pub fn update_refs(&mut self, repo: &Repository) -> Result<()> {
         let refs = repo.references().map_err(|x| SimpleError::from(x))?;
 
@@ -0,0 +1,381 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use pktline::{PktLineReader, PktLineWriter, ProgressSink, MAX_SIDEBAND_DATA, MAX_SIDEBAND_SMALL_DATA};
+use revwalk::{ObjectWalk, ObjectFilter};
+use object::{self};
+
+use std::collections::{HashSet};
+use std::io::{Read, Write};
+
+pub mod shallow;
+pub mod v2;
+
+use self::shallow::{ShallowRequest, ShallowInfo};
+
+pub const NULL_ID: &str = "0000000000000000000000000000000000000000";
+
+const CAPABILITIES: &[&str] = &[
+    "multi_ack",
+    "multi_ack_detailed",
+    "side-band",
+    "side-band-64k",
+    "no-progress",
+    "ofs-delta",
+    "include-tag",
+    "shallow",
+    "deepen-since",
+    "deepen-relative",
+    "deepen-not",
+    "filter",
+    "allow-tip-sha1-in-want",
+    "allow-reachable-sha1-in-want"
+];
+
+pub fn agent() -> String {
+    format!("agent=git-sql/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// Returns the protocol version requested by colon separated `key=value` parameters,
+/// as sent in the `Git-Protocol` header or the `GIT_PROTOCOL` environment variable.
+pub fn protocol_version(params: &str) -> u32 {
+    params.split(':')
+        .filter(|x| x.starts_with("version="))
+        .filter_map(|x| x["version=".len()..].parse::<u32>().ok())
+        .max()
+        .unwrap_or(0)
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum MultiAck {
+    None,
+    Basic,
+    Detailed
+}
+
+/// Lists references in advertisement order: `HEAD` first, then each ref followed by its peeled value.
+pub fn advertised_refs(client: &GitSqlClient) -> Result<Vec<(String, String)>> {
+    Ok(order_refs(client.list_refs()?))
+}
+
+/// Orders stored references for advertisement, pairing each ref with its stored `^{}` peeled row.
+pub fn order_refs(stored: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut targets = Vec::new();
+    let mut peeled = Vec::new();
+    let mut head = None;
+
+    for (name, target) in stored {
+        if name == "HEAD" {
+            head = Some(target);
+        } else if name.ends_with("^{}") {
+            peeled.push((name[..name.len() - 3].to_string(), target));
+        } else {
+            targets.push((name, target));
+        }
+    }
+
+    targets.sort();
+
+    let mut refs = Vec::new();
+    if let Some(target) = head {
+        refs.push(("HEAD".to_string(), target));
+    }
+
+    for (name, target) in targets {
+        let peeled_target = peeled.iter()
+            .find(|x| x.0 == name && x.1 != target)
+            .map(|x| x.1.clone());
+        refs.push((name.clone(), target));
+
+        if let Some(peeled_target) = peeled_target {
+            refs.push((format!("{}^{{}}", name), peeled_target));
+        }
+    }
+
+    refs
+}
+
+/// Returns the ref `HEAD` points to if it is a symbolic reference.
+pub fn head_symref(client: &GitSqlClient) -> Result<Option<String>> {
+    let head = String::from("HEAD");
+    if !client.has_ref(&head)? {
+        return Ok(None);
+    }
+
+    let target = client.read_ref(&head)?;
+    if target.starts_with("refs/") {
+        Ok(Some(target))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Rejects wants that the references of the repository do not reach, as objects of other
+/// repositories sharing the database must not be sent.
+pub fn check_wants<W: Write>(client: &GitSqlClient, wants: &[String], output: &mut PktLineWriter<W>) -> Result<()> {
+    let reachable = client.filter_reachable(wants)?;
+
+    if let Some(hash) = wants.iter().find(|x| !reachable.contains(*x)) {
+        output.write_line(&format!("ERR upload-pack: not our ref {}", hash))?;
+        output.flush()?;
+        return Err(SimpleError::new(format!("Client requested unknown object {}.", hash)));
+    }
+    Ok(())
+}
+
+pub struct UploadPack<'a> {
+    client: &'a GitSqlClient,
+    stateless_rpc: bool
+}
+
+impl<'a> UploadPack<'a> {
+    pub fn new(client: &'a GitSqlClient, stateless_rpc: bool) -> UploadPack<'a> {
+        UploadPack { client, stateless_rpc }
+    }
+
+    fn capabilities(&self) -> Result<String> {
+        let mut caps: Vec<String> = CAPABILITIES.iter().map(|x| x.to_string()).collect();
+        if let Some(target) = head_symref(self.client)? {
+            caps.push(format!("symref=HEAD:{}", target));
+        }
+        caps.push(agent());
+        Ok(caps.join(" "))
+    }
+
+    pub fn advertise<W: Write>(&self, output: &mut PktLineWriter<W>) -> Result<()> {
+        let refs = advertised_refs(self.client)?;
+        let caps = self.capabilities()?;
+
+        if refs.is_empty() {
+            output.write_line(&format!("{} capabilities^{{}}\0{}", NULL_ID, caps))?;
+        }
+
+        for (index, (name, target)) in refs.iter().enumerate() {
+            if index == 0 {
+                output.write_line(&format!("{} {}\0{}", target, name, caps))?;
+            } else {
+                output.write_line(&format!("{} {}", target, name))?;
+            }
+        }
+
+        output.write_flush()?;
+        output.flush()
+    }
+
+    pub fn serve<R: Read, W: Write>(&self, input: &mut PktLineReader<R>, output: &mut PktLineWriter<W>) -> Result<()> {
+        let mut wants: Vec<String> = Vec::new();
+        let mut caps: Vec<String> = Vec::new();
+        let mut shallow_request = ShallowRequest::new();
+
+        loop {
+            let line = match input.read_packet()? {
+                Some(packet) => match packet.text() {
+                    Some(line) => line,
+                    None => break
+                },
+                None => return Ok(())
+            };
+
+            if let Some(rest) = line.strip_prefix("want ") {
+                let mut parts = rest.split(' ');
+                let hash = parts.next().unwrap_or("").to_string();
+
+                if wants.is_empty() {
+                    caps = parts.filter(|x| !x.is_empty()).map(|x| x.to_string()).collect();
+                }
+
+                wants.push(hash);
+            } else if line.starts_with("filter ") {
+                caps.push(line);
+            } else if !shallow_request.parse_line(&line)? {
+                return Err(SimpleError::new(format!("Unexpected line in want section: {}", line)));
+            }
+        }
+
+        if wants.is_empty() {
+            return Ok(());
+        }
+        check_wants(self.client, &wants, output)?;
+
+        if caps.iter().any(|x| x == "deepen-relative") {
+            shallow_request.relative = true;
+        }
+
+        let shallow = if shallow_request.is_shallow() {
+            shallow_request.compute(self.client, &wants)?
+        } else {
+            ShallowInfo::none()
+        };
+
+        if shallow_request.is_deepen() {
+            shallow.write(output)?;
+            output.write_flush()?;
+            output.flush()?;
+        }
+
+        let multi_ack = if caps.iter().any(|x| x == "multi_ack_detailed") {
+            MultiAck::Detailed
+        } else if caps.iter().any(|x| x == "multi_ack") {
+            MultiAck::Basic
+        } else {
+            MultiAck::None
+        };
+
+        let mut common: Vec<String> = Vec::new();
+        let mut haves: Vec<String> = Vec::new();
+
+        loop {
+            let packet = match input.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(())
+            };
+
+            let line = match packet.text() {
+                Some(line) => line,
+                None => {
+                    self.acknowledge(&mut haves, &mut common, multi_ack, output)?;
+                    if common.is_empty() || multi_ack != MultiAck::None {
+                        output.write_line("NAK")?;
+                    }
+                    output.flush()?;
+
+                    if self.stateless_rpc {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+
+            if line == "done" {
+                self.acknowledge(&mut haves, &mut common, multi_ack, output)?;
+                break;
+            }
+
+            if !line.starts_with("have ") {
+                return Err(SimpleError::new(format!("Unexpected line during negotiation: {}", line)));
+            }
+            haves.push(line[5..].trim().to_string());
+        }
+
+        match common.last() {
+            Some(last) => {
+                if multi_ack != MultiAck::None {
+                    output.write_line(&format!("ACK {}", last))?;
+                }
+            },
+            None => output.write_line("NAK")?
+        }
+
+        self.send_pack(&wants, &common, &shallow, &caps, output)
+    }
+
+    /// Acknowledges the haves of a negotiation round that the repository has, emptying `haves`.
+    fn acknowledge<W: Write>(&self, haves: &mut Vec<String>, common: &mut Vec<String>, multi_ack: MultiAck, output: &mut PktLineWriter<W>) -> Result<()> {
+        if haves.is_empty() {
+            return Ok(());
+        }
+
+        let reachable = self.client.filter_reachable(haves)?;
+        for hash in haves.drain(..) {
+            if !reachable.contains(&hash) {
+                continue;
+            }
+
+            if !common.contains(&hash) {
+                common.push(hash.clone());
+            }
+
+            match multi_ack {
+                MultiAck::Detailed => output.write_line(&format!("ACK {} common", hash))?,
+                MultiAck::Basic => output.write_line(&format!("ACK {} continue", hash))?,
+                MultiAck::None => {
+                    if common.len() == 1 {
+                        output.write_line(&format!("ACK {}", hash))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Walks and sends the pack for the given wants, using the fetch capabilities the client asked for.
+    pub fn send_pack<W: Write>(&self, wants: &[String], common: &[String], shallow: &ShallowInfo, caps: &[String], output: &mut PktLineWriter<W>) -> Result<()> {
+        let sideband = if caps.iter().any(|x| x == "side-band-64k") {
+            Some(MAX_SIDEBAND_DATA)
+        } else if caps.iter().any(|x| x == "side-band") {
+            Some(MAX_SIDEBAND_SMALL_DATA)
+        } else {
+            None
+        };
+        let quiet = caps.iter().any(|x| x == "no-progress");
+
+        {
+            let mut sink = ProgressSink::new(output, sideband, quiet);
+
+            let mut walk = ObjectWalk::new(self.client);
+            shallow.apply(&mut walk);
+
+            if let Some(spec) = caps.iter().find(|x| x.starts_with("filter ")) {
+                match ObjectFilter::parse(&spec["filter ".len()..]) {
+                    Ok(filter) => walk.set_filter(filter),
+                    Err(error) => {
+                        sink.error(&format!("upload-pack: {}", error))?;
+                        return Err(error);
+                    }
+                }
+            }
+
+            let mut tips = wants.to_vec();
+            tips.extend_from_slice(shallow.extra_wants());
+
+            let result = walk.hide(common).and_then(|_| walk.push(&tips));
+            if let Err(error) = result {
+                sink.error(&format!("upload-pack: {}", error))?;
+                return Err(error);
+            }
+
+            let mut objects = walk.into_objects();
+            if caps.iter().any(|x| x == "include-tag") {
+                self.include_tags(&mut objects)?;
+            }
+            sink.progress(&format!("Counting objects: {}, done.\n", objects.len()))?;
+
+            let mut options = self.client.pack_options();
+            options.ofs_delta = caps.iter().any(|x| x == "ofs-delta");
+
+            if let Err(error) = self.client.write_pack(&objects, &options, &mut sink) {
+                sink.error(&format!("upload-pack: {}", error))?;
+                return Err(error);
+            }
+        }
+
+        if sideband.is_some() {
+            output.write_flush()?;
+        }
+        output.flush()
+    }
+
+    /// Adds annotated tags whose peeled target is already being sent.
+    fn include_tags(&self, objects: &mut Vec<(String, String)>) -> Result<()> {
+        let mut sending: HashSet<String> = objects.iter().map(|x| x.0.clone()).collect();
+        let refs = advertised_refs(self.client)?;
+
+        for (index, (name, target)) in refs.iter().enumerate() {
+            if !name.ends_with("^{}") || !sending.contains(target) || index == 0 {
+                continue;
+            }
+
+            // Only tags pointing directly at the object are included; nested tags would need their chain too.
+            let tag = &refs[index - 1].1;
+            if sending.contains(tag) || object::parse_tag(&self.client.read_object(tag)?.1)?.object != *target {
+                continue;
+            }
+
+            if sending.insert(tag.clone()) {
+                objects.push((tag.clone(), String::new()));
+            }
+        }
+
+        Ok(())
+    }
+}
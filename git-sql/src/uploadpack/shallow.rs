@@ -0,0 +1,179 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use object::{self};
+use pktline::{PktLineWriter};
+use revwalk::{self, ObjectWalk};
+
+use std::collections::{HashSet};
+use std::io::{Write};
+
+/// The shallow and deepen lines sent by a client alongside its wants.
+pub struct ShallowRequest {
+    pub client_shallow: Vec<String>,
+    pub depth: Option<i32>,
+    pub relative: bool,
+    pub since: Option<i64>,
+    pub not: Vec<String>
+}
+
+/// The shallow state of a fetch once the requested depth has been applied.
+pub struct ShallowInfo {
+    client_shallow: Vec<String>,
+    boundary: Vec<String>,
+    pub shallow: Vec<String>,
+    pub unshallow: Vec<String>,
+    unshallow_parents: Vec<String>
+}
+
+impl ShallowRequest {
+    pub fn new() -> ShallowRequest {
+        ShallowRequest { client_shallow: Vec::new(), depth: None, relative: false, since: None, not: Vec::new() }
+    }
+
+    /// Consumes a `shallow`, `deepen`, `deepen-since` or `deepen-not` line, returning whether it was one.
+    pub fn parse_line(&mut self, line: &str) -> Result<bool> {
+        if let Some(hash) = line.strip_prefix("shallow ") {
+            self.client_shallow.push(hash.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("deepen-since ") {
+            let value = value.trim();
+            self.since = Some(value.parse::<i64>()
+                .map_err(|_| SimpleError::new(format!("Invalid deepen-since value: {}", value)))?);
+        } else if line == "deepen-relative" {
+            self.relative = true;
+        } else if let Some(name) = line.strip_prefix("deepen-not ") {
+            self.not.push(name.trim().to_string());
+        } else if let Some(value) = line.strip_prefix("deepen ") {
+            let value = value.trim();
+            let depth = value.parse::<i64>()
+                .map_err(|_| SimpleError::new(format!("Invalid deepen value: {}", value)))?;
+            if depth <= 0 {
+                return Err(SimpleError::new(format!("Invalid deepen value: {}", value)));
+            }
+            self.depth = Some(std::cmp::min(depth, i32::MAX as i64) as i32);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Whether the client asked to change the depth of its history.
+    pub fn is_deepen(&self) -> bool {
+        self.depth.is_some() || self.since.is_some() || !self.not.is_empty()
+    }
+
+    /// Whether shallow state needs to be reported back to the client.
+    pub fn is_shallow(&self) -> bool {
+        self.is_deepen() || !self.client_shallow.is_empty()
+    }
+
+    /// Resolves `deepen-not` arguments, which must name references.
+    fn resolve_not(&self, client: &GitSqlClient) -> Result<Vec<String>> {
+        let mut resolved = Vec::new();
+
+        for name in &self.not {
+            let (_, target) = client.resolve_ref_name(name)?
+                .ok_or_else(|| SimpleError::new(format!("deepen-not is not a ref: {}", name)))?;
+
+            if let Some(commit) = revwalk::peel_to_commit(client, &target)? {
+                resolved.push(commit);
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Computes the new shallow boundary for the wants through a commit-graph walk in the database.
+    pub fn compute(&self, client: &GitSqlClient, wants: &[String]) -> Result<ShallowInfo> {
+        let client_shallow: HashSet<String> = self.client_shallow.iter().cloned().collect();
+
+        if !self.is_deepen() {
+            return Ok(ShallowInfo {
+                client_shallow: self.client_shallow.clone(),
+                boundary: self.client_shallow.clone(),
+                shallow: Vec::new(),
+                unshallow: Vec::new(),
+                unshallow_parents: Vec::new()
+            });
+        }
+
+        let mut heads = Vec::new();
+        let mut depth = self.depth;
+
+        if self.relative && depth.is_some() {
+            // A relative deepen extends the client's current shallow commits rather than the wants.
+            for hash in &self.client_shallow {
+                if client.has_object(hash)? {
+                    heads.push(hash.clone());
+                }
+            }
+            depth = depth.map(|x| x.saturating_add(1));
+        } else {
+            for want in wants {
+                if let Some(commit) = revwalk::peel_to_commit(client, want)? {
+                    heads.push(commit);
+                }
+            }
+        }
+
+        let excluded = self.resolve_not(client)?;
+        let walked = client.shallow_walk(&heads, depth, self.since, &excluded)?;
+
+        let mut info = ShallowInfo {
+            client_shallow: self.client_shallow.clone(),
+            boundary: Vec::new(),
+            shallow: Vec::new(),
+            unshallow: Vec::new(),
+            unshallow_parents: Vec::new()
+        };
+
+        for (hash, boundary) in walked {
+            if boundary {
+                if !client_shallow.contains(&hash) {
+                    info.shallow.push(hash.clone());
+                }
+                info.boundary.push(hash);
+            } else if client_shallow.contains(&hash) {
+                let (_, data) = client.read_object(&hash)?;
+                info.unshallow_parents.extend(object::parse_commit(&data)?.parents);
+                info.unshallow.push(hash);
+            }
+        }
+
+        Ok(info)
+    }
+}
+
+impl ShallowInfo {
+    /// Shallow state for a client that has complete history and asked for all of it.
+    pub fn none() -> ShallowInfo {
+        ShallowInfo {
+            client_shallow: Vec::new(),
+            boundary: Vec::new(),
+            shallow: Vec::new(),
+            unshallow: Vec::new(),
+            unshallow_parents: Vec::new()
+        }
+    }
+
+    /// Configures the walk so history stops at the shallow boundary.
+    pub fn apply(&self, walk: &mut ObjectWalk) {
+        walk.set_shallow(&self.client_shallow, &self.boundary);
+    }
+
+    /// Parents of commits that are no longer shallow, which must be sent in addition to the wants.
+    pub fn extra_wants(&self) -> &[String] {
+        &self.unshallow_parents
+    }
+
+    /// Writes the `shallow` and `unshallow` lines.
+    pub fn write<W: Write>(&self, output: &mut PktLineWriter<W>) -> Result<()> {
+        for hash in &self.shallow {
+            output.write_line(&format!("shallow {}", hash))?;
+        }
+
+        for hash in &self.unshallow {
+            output.write_line(&format!("unshallow {}", hash))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use core::{SimpleError, Result};
+use client::{GitSqlClient};
+use pktline::{Packet, PktLineReader, PktLineWriter};
+use uploadpack::{self, UploadPack};
+use uploadpack::shallow::{ShallowRequest, ShallowInfo};
+
+use std::collections::{HashSet};
+use std::io::{Read, Write};
+
+const COMMANDS: &[&str] = &[
+    "ls-refs",
+    "fetch=shallow filter",
+    "server-option",
+    "object-format=sha1",
+    "object-info"
+];
+
+/// Serves `git-upload-pack` using protocol v2 command dispatch.
+pub struct UploadPackV2<'a> {
+    client: &'a GitSqlClient
+}
+
+impl<'a> UploadPackV2<'a> {
+    pub fn new(client: &'a GitSqlClient) -> UploadPackV2<'a> {
+        UploadPackV2 { client }
+    }
+
+    pub fn advertise<W: Write>(&self, output: &mut PktLineWriter<W>) -> Result<()> {
+        output.write_line("version 2")?;
+        output.write_line(&uploadpack::agent())?;
+        for command in COMMANDS {
+            output.write_line(command)?;
+        }
+        output.write_flush()?;
+        output.flush()
+    }
+
+    /// Runs commands until the client sends a flush or closes the stream.
+    pub fn serve<R: Read, W: Write>(&self, input: &mut PktLineReader<R>, output: &mut PktLineWriter<W>) -> Result<()> {
+        loop {
+            let line = match input.read_packet()? {
+                Some(packet) => match packet.text() {
+                    Some(line) => line,
+                    None => return output.flush()
+                },
+                None => return output.flush()
+            };
+
+            if !line.starts_with("command=") {
+                return Err(SimpleError::new(format!("Expected a command, got: {}", line)));
+            }
+            let command = &line["command=".len()..];
+
+            let mut args = Vec::new();
+            loop {
+                match input.expect_packet()? {
+                    Packet::Data(_) => continue,
+                    Packet::Delim => {
+                        args = input.read_section()?;
+                        break;
+                    },
+                    Packet::Flush => break,
+                    Packet::ResponseEnd => return Err(SimpleError::new("Unexpected response end packet."))
+                }
+            }
+
+            match command {
+                "ls-refs" => self.ls_refs(&args, output)?,
+                "fetch" => self.fetch(&args, output)?,
+                "object-info" => self.object_info(&args, output)?,
+                _ => {
+                    output.write_line(&format!("ERR unknown command {}", command))?;
+                    output.flush()?;
+                    return Err(SimpleError::new(format!("Unknown protocol v2 command {}.", command)));
+                }
+            }
+            output.flush()?;
+        }
+    }
+
+    fn ls_refs<W: Write>(&self, args: &[String], output: &mut PktLineWriter<W>) -> Result<()> {
+        let peel = args.iter().any(|x| x == "peel");
+        let symrefs = args.iter().any(|x| x == "symrefs");
+        let prefixes: Vec<String> = args.iter()
+            .filter(|x| x.starts_with("ref-prefix "))
+            .map(|x| x["ref-prefix ".len()..].to_string())
+            .collect();
+
+        let stored = if prefixes.is_empty() {
+            self.client.list_refs()?
+        } else {
+            self.client.list_refs_with_prefixes(&prefixes)?
+        };
+        let refs = uploadpack::order_refs(stored);
+
+        for (index, (name, target)) in refs.iter().enumerate() {
+            if name.ends_with("^{}") {
+                continue;
+            }
+
+            let mut line = format!("{} {}", target, name);
+            if symrefs && name == "HEAD" {
+                if let Some(head) = uploadpack::head_symref(self.client)? {
+                    line.push_str(&format!(" symref-target:{}", head));
+                }
+            }
+
+            if peel {
+                if let Some((peeled_name, peeled)) = refs.get(index + 1) {
+                    if peeled_name.ends_with("^{}") {
+                        line.push_str(&format!(" peeled:{}", peeled));
+                    }
+                }
+            }
+            output.write_line(&line)?;
+        }
+
+        output.write_flush()
+    }
+
+    fn fetch<W: Write>(&self, args: &[String], output: &mut PktLineWriter<W>) -> Result<()> {
+        let mut wants: Vec<String> = Vec::new();
+        let mut haves: Vec<String> = Vec::new();
+        let mut caps: Vec<String> = Vec::new();
+        let mut shallow_request = ShallowRequest::new();
+        let mut done = false;
+
+        for arg in args {
+            if let Some(hash) = arg.strip_prefix("want ") {
+                wants.push(hash.trim().to_string());
+            } else if let Some(hash) = arg.strip_prefix("have ") {
+                haves.push(hash.trim().to_string());
+            } else if arg == "done" {
+                done = true;
+            } else if shallow_request.parse_line(arg)? {
+                continue;
+            } else {
+                caps.push(arg.clone());
+            }
+        }
+
+        uploadpack::check_wants(self.client, &wants, output)?;
+
+        let reachable = self.client.filter_reachable(&haves)?;
+        let mut common: Vec<String> = Vec::new();
+        let mut common_set: HashSet<String> = HashSet::new();
+        for hash in haves {
+            if reachable.contains(&hash) && common_set.insert(hash.clone()) {
+                common.push(hash);
+            }
+        }
+
+        if !done {
+            output.write_line("acknowledgments")?;
+            if common.is_empty() {
+                output.write_line("NAK")?;
+            }
+            for hash in &common {
+                output.write_line(&format!("ACK {}", hash))?;
+            }
+
+            if !self.is_ready(&wants, &common_set)? {
+                return output.write_flush();
+            }
+
+            output.write_line("ready")?;
+            output.write_delim()?;
+        }
+
+        let shallow = if shallow_request.is_shallow() {
+            let shallow = shallow_request.compute(self.client, &wants)?;
+            output.write_line("shallow-info")?;
+            shallow.write(output)?;
+            output.write_delim()?;
+            shallow
+        } else {
+            ShallowInfo::none()
+        };
+
+        output.write_line("packfile")?;
+        caps.push(String::from("side-band-64k"));
+        UploadPack::new(self.client, true).send_pack(&wants, &common, &shallow, &caps, output)
+    }
+
+    /// The server may stop negotiating once every want is known to reach a common object.
+    fn is_ready(&self, wants: &[String], common: &HashSet<String>) -> Result<bool> {
+        if common.is_empty() {
+            return Ok(false);
+        }
+
+        let common: Vec<String> = common.iter().cloned().collect();
+        for want in wants {
+            if !self.client.reaches_any(want, &common)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn object_info<W: Write>(&self, args: &[String], output: &mut PktLineWriter<W>) -> Result<()> {
+        let size = args.iter().any(|x| x == "size");
+        let oids: Vec<String> = args.iter()
+            .filter(|x| x.starts_with("oid "))
+            .map(|x| x[4..].trim().to_string())
+            .collect();
+
+        let present: Vec<String> = self.client.filter_reachable(&oids)?.into_iter().collect();
+        let sizes = self.client.read_object_sizes(&present)?;
+
+        if size {
+            output.write_line("size")?;
+        }
+
+        for oid in &oids {
+            if !size {
+                output.write_line(oid)?;
+                continue;
+            }
+
+            match sizes.iter().find(|x| x.0 == *oid) {
+                Some(entry) => output.write_line(&format!("{} {}", oid, entry.2))?,
+                None => output.write_line(&format!("{} ", oid))?
+            }
+        }
+
+        output.write_flush()
+    }
+}